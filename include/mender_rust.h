@@ -0,0 +1,69 @@
+#ifndef MENDER_RUST_H
+#define MENDER_RUST_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The call succeeded.
+#define MENDER_OK 0
+
+// The request is not valid: null pointer, invalid UTF-8 or JSON, missing or invalid
+// argument, or invalid configuration.
+#define MENDER_INVALID_REQUEST 1
+
+// The command failed, e.g. the server can't be reached or refused the request.
+#define MENDER_COMMAND_FAILED 2
+
+// The command panicked, this is a bug of mender-rust.
+#define MENDER_INTERNAL_ERROR 3
+
+// Log in with `{"server_url", "email", "password"}`, the response is `{"token"}`.
+//
+// # Safety
+//
+// request must be null or a NUL terminated string, response must be null or valid for
+// writes.
+int mender_login(const char *request, char **response);
+
+// Get the Mender id of a device with `{"server_url", "token", "value", "attribute",
+// "first"}`, only value is required. The response is `{"id"}`.
+//
+// # Safety
+//
+// request must be null or a NUL terminated string, response must be null or valid for
+// writes.
+int mender_get_id(const char *request, char **response);
+
+// Deploy an artifact with `{"server_url", "token", "artifact", "group", "devices",
+// "name", "allow_downgrade", "dry_run"}`, to a group or to a list of device ids. The
+// deployment is created without confirmation. The response is `{"devices"}`, the
+// number of devices of the deployment.
+//
+// # Safety
+//
+// request must be null or a NUL terminated string, response must be null or valid for
+// writes.
+int mender_deploy(const char *request, char **response);
+
+// Get the inventory of a device with `{"server_url", "token", "device", "attributes"}`.
+// The response is the inventory of the device, or an object of the requested
+// attributes.
+//
+// # Safety
+//
+// request must be null or a NUL terminated string, response must be null or valid for
+// writes.
+int mender_get_info(const char *request, char **response);
+
+// Release a response of the other functions, nothing is done for null.
+//
+// # Safety
+//
+// string must be null or a response which has not been released yet.
+void mender_free_string(char *string);
+
+#endif /* MENDER_RUST_H */
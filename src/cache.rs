@@ -0,0 +1,47 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Directory of the cached results.
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("mender-rust"))
+}
+
+/// Path of a cache entry, the key is hashed so that it can hold any character.
+fn entry_path(cache_dir: Option<&Path>, kind: &str, key: &str) -> Option<PathBuf> {
+    let hash: String = Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    cache_dir.map(|dir| dir.join(format!("{}-{}.json", kind, hash)))
+}
+
+/// Read a cache entry of the cache directory, None if there is none or it can't be read.
+pub fn load<T: DeserializeOwned>(cache_dir: Option<&Path>, kind: &str, key: &str) -> Option<T> {
+    let content = std::fs::read(entry_path(cache_dir, kind, key)?).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+/// Write a cache entry, nothing is done if there is no cache directory.
+pub fn save<T: Serialize>(
+    cache_dir: Option<&Path>,
+    kind: &str,
+    key: &str,
+    value: &T,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = entry_path(cache_dir, kind, key) {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(value)?)?;
+        std::fs::rename(&tmp_path, &path)?;
+    }
+    Ok(())
+}
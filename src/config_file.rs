@@ -0,0 +1,55 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Values read from the configuration file, they have the same meaning as the
+/// environment variables of the same name in uppercase.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub server_url: Option<String>,
+    pub cert_file: Option<String>,
+    pub token: Option<String>,
+    /// Default of --max-failure-rate for deploy --wait and stats.
+    pub max_failure_rate: Option<f64>,
+    /// Default of --max-failures for deploy --wait and stats.
+    pub max_failures: Option<u64>,
+    #[serde(default)]
+    pub pagination: PaginationSettings,
+}
+
+/// The [pagination] table of the configuration file.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PaginationSettings {
+    /// Same as MENDER_PAGE_SIZE.
+    pub per_page: Option<usize>,
+}
+
+/// Configuration file used when --config is not given.
+fn default_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".mender-rust").join("config.toml"))
+}
+
+/// Read the configuration file given with --config, or the default one. A missing
+/// default file is the same as an empty one while a missing given file is an error.
+pub fn load(path: Option<&str>) -> Result<ConfigFile, &'static str> {
+    let (path, explicit) = match path {
+        Some(path) => (PathBuf::from(path), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(ConfigFile::default()),
+        },
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && !explicit => {
+            return Ok(ConfigFile::default())
+        }
+        Err(_) => return Err("configuration file can't be read"),
+    };
+    toml::from_str(&content).map_err(|_| {
+        "configuration file must be TOML with only server_url, cert_file, token, max_failure_rate \
+         and max_failures keys and a pagination table with a per_page key"
+    })
+}
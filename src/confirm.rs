@@ -0,0 +1,235 @@
+use super::parse::{Config, OutputFormat};
+use std::error::Error;
+use std::io::{BufRead, IsTerminal, Write};
+
+#[derive(PartialEq, Debug)]
+pub enum Decision {
+    Proceed,
+    Abort,
+    DryRun,
+}
+
+/// Decide if a destructive action can be done, the action and its impact are printed first.
+/// Dry run always wins over --yes, then --yes proceeds without prompting. Otherwise the
+/// user is asked for confirmation, by typing the confirm_server name of the profile if
+/// any, unless stdin is not a terminal in which case the action is aborted so scripts
+/// never act without an explicit --yes.
+pub fn confirm(action: &str, impact: &str, conf: &Config) -> Result<Decision, Box<dyn Error>> {
+    confirm_from(
+        action,
+        impact,
+        conf,
+        std::io::stdin().is_terminal(),
+        &mut std::io::stdin().lock(),
+    )
+}
+
+/// Same as confirm with the answer read from input, is_terminal tells if it is a terminal.
+fn confirm_from(
+    action: &str,
+    impact: &str,
+    conf: &Config,
+    is_terminal: bool,
+    input: &mut impl BufRead,
+) -> Result<Decision, Box<dyn Error>> {
+    progress!(conf, "{}: {}\n", action, impact);
+    if conf.dry_run {
+        progress!(conf, "Dry run, nothing was done.\n");
+        return Ok(Decision::DryRun);
+    }
+    if conf.assume_yes {
+        return Ok(Decision::Proceed);
+    }
+    if !is_terminal {
+        progress!(conf, "Not running in a terminal, use --yes to proceed.\n");
+        return Ok(Decision::Abort);
+    }
+
+    match &conf.confirm_server {
+        Some(server) => progress!(conf, "Type {} to proceed: ", server),
+        None => progress!(conf, "Proceed? [y/N] "),
+    }
+    flush_progress!(conf);
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    let proceed = match &conf.confirm_server {
+        Some(server) => answer.trim() == server,
+        None => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+    };
+    Ok(if proceed {
+        Decision::Proceed
+    } else {
+        Decision::Abort
+    })
+}
+
+/// Let the user pick one of several candidates by its number in the printed list. None
+/// is returned when the choice can't be made interactively, because stdin is not a
+/// terminal or --no-interactive is given, the caller then fails as it would without a
+/// chooser so that scripts behave the same.
+pub fn choose(
+    prompt: &str,
+    candidates: &[String],
+    conf: &Config,
+) -> Result<Option<usize>, Box<dyn Error>> {
+    if conf.no_interactive || !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let mut output: Box<dyn Write> = if conf.output_format == OutputFormat::Json {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    };
+    choose_from(
+        prompt,
+        candidates,
+        &mut std::io::stdin().lock(),
+        &mut output,
+    )
+    .map(Some)
+}
+
+/// Print the numbered candidates and read the choice from input until a valid number is
+/// given, an empty answer aborts.
+fn choose_from(
+    prompt: &str,
+    candidates: &[String],
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<usize, Box<dyn Error>> {
+    writeln!(output, "{}:", prompt)?;
+    for (idx, candidate) in candidates.iter().enumerate() {
+        writeln!(output, "  {}) {}", idx + 1, candidate)?;
+    }
+    loop {
+        write!(output, "Choice [1-{}], empty to abort: ", candidates.len())?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 || answer.trim().is_empty() {
+            return Err("Selection aborted".into());
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(choice) if (1..=candidates.len()).contains(&choice) => return Ok(choice - 1),
+            _ => writeln!(output, "Invalid choice")?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server;
+
+    fn decide(args: &[&str], is_terminal: bool, answer: &str) -> Decision {
+        let mut args = args.to_vec();
+        args.push("showconfig");
+        let conf = mock_server::config("https://mender.test", &args);
+        confirm_from(
+            "Delete",
+            "1 device",
+            &conf,
+            is_terminal,
+            &mut answer.as_bytes(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn dry_run_wins() {
+        for is_terminal in &[false, true] {
+            assert_eq!(
+                decide(&["--dry-run"], *is_terminal, "y\n"),
+                Decision::DryRun
+            );
+            assert_eq!(
+                decide(&["--dry-run", "--yes"], *is_terminal, "y\n"),
+                Decision::DryRun
+            );
+        }
+    }
+
+    #[test]
+    fn yes_proceeds_without_prompt() {
+        for is_terminal in &[false, true] {
+            assert_eq!(decide(&["--yes"], *is_terminal, "n\n"), Decision::Proceed);
+        }
+    }
+
+    #[test]
+    fn no_terminal_aborts() {
+        assert_eq!(decide(&[], false, "y\n"), Decision::Abort);
+    }
+
+    #[test]
+    fn terminal_answers() {
+        for answer in &["y\n", "yes\n", " Y \n", "YES"] {
+            assert_eq!(decide(&[], true, answer), Decision::Proceed, "{:?}", answer);
+        }
+        for answer in &["n\n", "\n", "", "yep\n"] {
+            assert_eq!(decide(&[], true, answer), Decision::Abort, "{:?}", answer);
+        }
+    }
+
+    #[test]
+    fn confirm_server_name() {
+        let mut conf = mock_server::config("https://mender.test", &["showconfig"]);
+        conf.confirm_server = Some("production".to_string());
+        let decide = |answer: &str| {
+            confirm_from("Delete", "1 device", &conf, true, &mut answer.as_bytes()).unwrap()
+        };
+        assert_eq!(decide("production\n"), Decision::Proceed);
+        for answer in &["y\n", "yes\n", "Production\n", "prod\n", ""] {
+            assert_eq!(decide(answer), Decision::Abort, "{:?}", answer);
+        }
+    }
+
+    fn choice(answers: &str) -> (Result<usize, String>, String) {
+        let candidates = ["d1".to_string(), "d2".to_string(), "d3".to_string()];
+        let mut output = vec![];
+        let choice = choose_from("Device", &candidates, &mut answers.as_bytes(), &mut output)
+            .map_err(|err| err.to_string());
+        (choice, String::from_utf8(output).unwrap())
+    }
+
+    fn choice_of(answers: &str) -> Result<usize, String> {
+        choice(answers).0
+    }
+
+    #[test]
+    fn valid_choice() {
+        let (choice, output) = choice("2\n");
+        assert_eq!(choice, Ok(1));
+        assert_eq!(
+            output,
+            "Device:\n  1) d1\n  2) d2\n  3) d3\nChoice [1-3], empty to abort: "
+        );
+        assert_eq!(choice_of(" 3 "), Ok(2));
+    }
+
+    #[test]
+    fn invalid_choices_are_asked_again() {
+        for answers in &["0\n1\n", "4\n1\n", "-1\n1\n", "two\n1\n", "1.5\n1\n"] {
+            let (choice, output) = choice(answers);
+            assert_eq!(choice, Ok(0), "{:?}", answers);
+            assert_eq!(
+                output.matches("Invalid choice\n").count(),
+                1,
+                "{:?}",
+                answers
+            );
+            assert_eq!(output.matches("Choice [1-3]").count(), 2, "{:?}", answers);
+        }
+    }
+
+    #[test]
+    fn empty_answer_or_eof_aborts() {
+        for answers in &["\n", "  \n", "", "x\n", "4"] {
+            assert_eq!(
+                choice_of(answers),
+                Err("Selection aborted".to_string()),
+                "{:?}",
+                answers
+            );
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+/// Variables which can be set in an env file, the other ones are reported as unknown.
+const KNOWN_VARIABLES: [&str; 13] = [
+    "SERVER_URL",
+    "TOKEN",
+    "CERT_FILE",
+    "HTTPS_PROXY",
+    "HTTP_PROXY",
+    "MENDER_PAGE_SIZE",
+    "MENDER_CONCURRENT_REQUESTS",
+    "MENDER_REQUEST_TIMEOUT",
+    "MENDER_DEADLINE",
+    "MENDER_RETRIES",
+    "MENDER_RETRY_DELAY_MS",
+    "MENDER_PROFILE",
+    "MENDER_RECORD_GROUP_HISTORY",
+];
+
+/// Environment variables used to build the configuration. The variables of the env file
+/// given with --env-file are only used when they are not set in the real environment,
+/// the process environment itself is never modified.
+#[derive(Debug, Default)]
+pub struct Environment {
+    file: HashMap<String, String>,
+}
+
+impl Environment {
+    /// Read the env file if one is given, unknown variables are reported on stderr.
+    pub fn new(env_file: Option<&str>) -> Result<Environment, &'static str> {
+        let file = match env_file {
+            Some(path) => {
+                parse(&std::fs::read_to_string(path).map_err(|_| "env file can't be read")?)?
+            }
+            None => HashMap::new(),
+        };
+        for name in file.keys() {
+            if !KNOWN_VARIABLES.contains(&name.as_str()) {
+                eprintln!("Warning: unknown variable {} in env file is ignored", name);
+            }
+        }
+        Ok(Environment { file })
+    }
+
+    /// Value of a variable, from the real environment or else from the env file.
+    pub fn var(&self, name: &str) -> Option<String> {
+        std::env::var(name)
+            .ok()
+            .or_else(|| self.file.get(name).cloned())
+    }
+}
+
+/// Parse the KEY=VALUE lines of a dotenv-style file. Empty lines and lines starting
+/// with # are skipped, a leading `export` is allowed and values can be quoted. Unquoted
+/// values end at the first ` #`, in double quoted values \n, \" and \\ are unescaped.
+fn parse(content: &str) -> Result<HashMap<String, String>, &'static str> {
+    let mut variables = HashMap::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line
+            .split_once('=')
+            .ok_or("env file lines must be KEY=VALUE")?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err("env file variable names must only contain letters, digits and '_'");
+        }
+        variables.insert(name.to_string(), parse_value(value.trim())?);
+    }
+    Ok(variables)
+}
+
+fn parse_value(value: &str) -> Result<String, &'static str> {
+    if let Some(quoted) = value.strip_prefix('\'') {
+        let end = quoted.find('\'').ok_or("unterminated quote in env file")?;
+        return Ok(quoted[..end].to_string());
+    }
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut unquoted = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Ok(unquoted),
+                '\\' => match chars.next() {
+                    Some('n') => unquoted.push('\n'),
+                    Some(escaped) => unquoted.push(escaped),
+                    None => break,
+                },
+                c => unquoted.push(c),
+            }
+        }
+        return Err("unterminated quote in env file");
+    }
+    Ok(match value.find(" #") {
+        Some(comment) => value[..comment].trim_end().to_string(),
+        None => value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dotenv_lines() {
+        let variables = parse(
+            "# Staging server\n\
+             \n\
+             SERVER_URL=https://staging.mender.test # hosted\n\
+             export TOKEN = 'abc # def'\n\
+             CERT_FILE=\"/etc/ssl/my \\\"cert\\\".pem\"\n\
+             MENDER_PAGE_SIZE=\n",
+        )
+        .unwrap();
+        let mut variables: Vec<(&str, &str)> = variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        variables.sort();
+        assert_eq!(
+            variables,
+            [
+                ("CERT_FILE", "/etc/ssl/my \"cert\".pem"),
+                ("MENDER_PAGE_SIZE", ""),
+                ("SERVER_URL", "https://staging.mender.test"),
+                ("TOKEN", "abc # def"),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_dotenv_lines() {
+        for content in &[
+            "SERVER_URL",
+            "=value",
+            "SERVER URL=x",
+            "TOKEN='abc",
+            "TOKEN=\"abc",
+        ] {
+            assert!(parse(content).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn real_environment_takes_precedence() {
+        let env_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            env_file.path(),
+            "MENDER_RUST_TEST_BOTH=file\nMENDER_RUST_TEST_FILE=file\n",
+        )
+        .unwrap();
+        std::env::set_var("MENDER_RUST_TEST_BOTH", "real");
+        let env = Environment::new(env_file.path().to_str()).unwrap();
+        assert_eq!(env.var("MENDER_RUST_TEST_BOTH").as_deref(), Some("real"));
+        assert_eq!(env.var("MENDER_RUST_TEST_FILE").as_deref(), Some("file"));
+        assert_eq!(env.var("MENDER_RUST_TEST_NONE"), None);
+        // The process environment is left untouched
+        assert!(std::env::var("MENDER_RUST_TEST_FILE").is_err());
+        assert_eq!(
+            Environment::new(None).unwrap().var("MENDER_RUST_TEST_FILE"),
+            None
+        );
+        assert!(Environment::new(Some("/nonexistent/.env")).is_err());
+    }
+}
@@ -0,0 +1,573 @@
+//! C ABI of the login, getid, deploy and getinfo commands, built with the ffi feature.
+//!
+//! Each function takes its request as a JSON object in a NUL terminated UTF-8 string,
+//! and stores a JSON response in `*response`, also on failure. The response is owned by
+//! the caller, who must release it with `mender_free_string`. The return value is
+//! `MENDER_OK` on success, or else an error code with `{"error": "<message>"}` as
+//! response. The configuration file, the profiles and the environment are used as on
+//! the command line for the settings missing from the request, and progress information
+//! is printed on stderr.
+
+use super::mender;
+use super::parse::{build_cli, Command, Config};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+
+/// The call succeeded.
+pub const MENDER_OK: c_int = 0;
+/// The request is not valid: null pointer, invalid UTF-8 or JSON, missing or invalid
+/// argument, or invalid configuration.
+pub const MENDER_INVALID_REQUEST: c_int = 1;
+/// The command failed, e.g. the server can't be reached or refused the request.
+pub const MENDER_COMMAND_FAILED: c_int = 2;
+/// The command panicked, this is a bug of mender-rust.
+pub const MENDER_INTERNAL_ERROR: c_int = 3;
+
+/// Failure of a call with the code returned to the caller.
+struct Failure {
+    code: c_int,
+    message: String,
+}
+
+impl Failure {
+    fn invalid_request(err: impl ToString) -> Failure {
+        Failure {
+            code: MENDER_INVALID_REQUEST,
+            message: err.to_string(),
+        }
+    }
+
+    fn command_failed(err: Box<dyn Error>) -> Failure {
+        Failure {
+            code: MENDER_COMMAND_FAILED,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Configuration of the command given as command line args, with the connection
+/// settings of the request. Results are formatted as JSON, so that progress information
+/// goes to stderr, and no confirmation is asked.
+///
+/// The values of the request are never read as flags: options are given as
+/// `--name=value` and the positional values come after `--`.
+fn config(
+    server_url: Option<&str>,
+    token: Option<&str>,
+    subcommand: &str,
+    options: &[String],
+    values: &[&str],
+) -> Result<Config, Failure> {
+    let mut cli_args = vec![
+        "mender-rust".to_string(),
+        "--output=json".to_string(),
+        "--yes".to_string(),
+    ];
+    if let Some(server_url) = server_url {
+        cli_args.push(option("server-url", server_url));
+    }
+    cli_args.push(subcommand.to_string());
+    cli_args.extend(options.iter().cloned());
+    cli_args.push("--".to_string());
+    cli_args.extend(values.iter().map(|value| value.to_string()));
+    let matches = build_cli()
+        .get_matches_from_safe(cli_args)
+        .map_err(|err| Failure::invalid_request(err.message))?;
+    // The token of the request is set once the configuration is built, the command is
+    // then only checked by the call
+    let mut conf = Config::new(Command::ShowConfig, &matches).map_err(Failure::invalid_request)?;
+    conf.command = Command::new(&matches).map_err(Failure::invalid_request)?;
+    if let Some(token) = token {
+        conf.token = Some(token.to_string());
+    }
+    Ok(conf)
+}
+
+/// Option of the command line with its value.
+fn option(name: &str, value: &str) -> String {
+    format!("--{}={}", name, value)
+}
+
+/// Run a call: parse the request, run the command and store its response, or the error.
+///
+/// # Safety
+///
+/// request must be null or a NUL terminated string, response must be null or valid for
+/// writes.
+unsafe fn call<R: DeserializeOwned>(
+    request: *const c_char,
+    response: *mut *mut c_char,
+    command: impl FnOnce(R) -> Result<serde_json::Value, Failure>,
+) -> c_int {
+    if response.is_null() {
+        return MENDER_INVALID_REQUEST;
+    }
+    let result = if request.is_null() {
+        Err(Failure::invalid_request("request is null"))
+    } else {
+        let request = CStr::from_ptr(request);
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let request = request.to_str().map_err(Failure::invalid_request)?;
+            command(serde_json::from_str(request).map_err(Failure::invalid_request)?)
+        }))
+        .unwrap_or_else(|_| {
+            Err(Failure {
+                code: MENDER_INTERNAL_ERROR,
+                message: String::from("mender-rust panicked"),
+            })
+        })
+    };
+    let (code, json) = match result {
+        Ok(json) => (MENDER_OK, json),
+        Err(failure) => (
+            failure.code,
+            serde_json::json!({ "error": failure.message }),
+        ),
+    };
+    // A JSON string escapes the NUL characters, it can't contain one
+    *response = CString::new(json.to_string())
+        .expect("JSON without NUL")
+        .into_raw();
+    code
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoginRequest {
+    server_url: Option<String>,
+    email: String,
+    password: String,
+}
+
+/// Log in with `{"server_url", "email", "password"}`, the response is `{"token"}`.
+///
+/// # Safety
+///
+/// request must be null or a NUL terminated string, response must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn mender_login(request: *const c_char, response: *mut *mut c_char) -> c_int {
+    call(request, response, |request: LoginRequest| {
+        let conf = config(
+            request.server_url.as_deref(),
+            None,
+            "login",
+            &[],
+            &[&request.email],
+        )?;
+        let token = mender::get_token(&conf, &request.password).map_err(Failure::command_failed)?;
+        Ok(serde_json::json!({ "token": token }))
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GetIdRequest {
+    server_url: Option<String>,
+    token: Option<String>,
+    value: String,
+    /// Attribute of the value, SerialNumber by default.
+    attribute: Option<String>,
+    /// Take the first device when several match instead of failing.
+    #[serde(default)]
+    first: bool,
+}
+
+/// Get the Mender id of a device with `{"server_url", "token", "value", "attribute",
+/// "first"}`, only value is required. The response is `{"id"}`.
+///
+/// # Safety
+///
+/// request must be null or a NUL terminated string, response must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn mender_get_id(
+    request: *const c_char,
+    response: *mut *mut c_char,
+) -> c_int {
+    call(request, response, |request: GetIdRequest| {
+        let mut options = vec![];
+        if let Some(attribute) = &request.attribute {
+            options.push(option("attribute", attribute));
+        }
+        if request.first {
+            options.push("--first".to_string());
+        }
+        let conf = config(
+            request.server_url.as_deref(),
+            request.token.as_deref(),
+            "getid",
+            &options,
+            &[&request.value],
+        )?;
+        let id = mender::get_id(&conf).map_err(Failure::command_failed)?;
+        Ok(serde_json::json!({ "id": id }))
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeployRequest {
+    server_url: Option<String>,
+    token: Option<String>,
+    artifact: String,
+    group: Option<String>,
+    #[serde(default)]
+    devices: Vec<String>,
+    /// Name of the deployment, the group or device name by default.
+    name: Option<String>,
+    #[serde(default)]
+    allow_downgrade: bool,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Deploy an artifact with `{"server_url", "token", "artifact", "group", "devices",
+/// "name", "allow_downgrade", "dry_run"}`, to a group or to a list of device ids. The
+/// deployment is created without confirmation. The response is `{"devices"}`, the
+/// number of devices of the deployment.
+///
+/// # Safety
+///
+/// request must be null or a NUL terminated string, response must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn mender_deploy(
+    request: *const c_char,
+    response: *mut *mut c_char,
+) -> c_int {
+    call(request, response, |request: DeployRequest| {
+        let mut options = vec![];
+        if let Some(group) = &request.group {
+            options.push(option("group", group));
+        }
+        for device in &request.devices {
+            options.push(option("device", device));
+        }
+        if request.allow_downgrade {
+            options.push("--allow-downgrade".to_string());
+        }
+        if request.dry_run {
+            options.push("--dry-run".to_string());
+        }
+        let mut values = vec![request.artifact.as_str()];
+        if let Some(name) = &request.name {
+            values.push(name);
+        }
+        let conf = config(
+            request.server_url.as_deref(),
+            request.token.as_deref(),
+            "deploy",
+            &options,
+            &values,
+        )?;
+        let devices = mender::deploy(&conf).map_err(Failure::command_failed)?;
+        Ok(serde_json::json!({ "devices": devices }))
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GetInfoRequest {
+    server_url: Option<String>,
+    token: Option<String>,
+    /// Device given as on the command line, a Mender id with the id: prefix.
+    device: String,
+    /// Only return these attributes.
+    #[serde(default)]
+    attributes: Vec<String>,
+}
+
+/// Get the inventory of a device with `{"server_url", "token", "device", "attributes"}`.
+/// The response is the inventory of the device, or an object of the requested
+/// attributes.
+///
+/// # Safety
+///
+/// request must be null or a NUL terminated string, response must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn mender_get_info(
+    request: *const c_char,
+    response: *mut *mut c_char,
+) -> c_int {
+    call(request, response, |request: GetInfoRequest| {
+        let options: Vec<_> = request
+            .attributes
+            .iter()
+            .map(|attribute| option("attr", attribute))
+            .collect();
+        let conf = config(
+            request.server_url.as_deref(),
+            request.token.as_deref(),
+            "getinfo",
+            &options,
+            &[&request.device],
+        )?;
+        let info = mender::get_info(&conf).map_err(Failure::command_failed)?;
+        serde_json::from_str(&info).map_err(|err| Failure::command_failed(Box::new(err)))
+    })
+}
+
+/// Release a response of the other functions, nothing is done for null.
+///
+/// # Safety
+///
+/// string must be null or a response which has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn mender_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mender::{
+        DEPLOYMENT_LIMITS_API, DEPLOY_API, GET_DEVICES_INVENTORY_API, GROUPS_API, LOGIN_API,
+    };
+    use crate::mock_server::{MockMenderServer, TOKEN};
+    use mockito::Matcher;
+
+    type Function = unsafe extern "C" fn(*const c_char, *mut *mut c_char) -> c_int;
+
+    /// Call a function with a request as the C code would, return the code and the
+    /// response.
+    fn call_ffi(function: Function, request: &str) -> (c_int, serde_json::Value) {
+        let request = CString::new(request).unwrap();
+        let mut response = std::ptr::null_mut();
+        unsafe {
+            let code = function(request.as_ptr(), &mut response);
+            assert!(!response.is_null());
+            let json = serde_json::from_str(CStr::from_ptr(response).to_str().unwrap()).unwrap();
+            mender_free_string(response);
+            (code, json)
+        }
+    }
+
+    /// Request with the connection settings of the mock server.
+    fn request(server: &MockMenderServer, fields: serde_json::Value) -> String {
+        let mut request = serde_json::json!({
+            "server_url": server.server.url(),
+            "token": TOKEN,
+        });
+        for (name, value) in fields.as_object().unwrap() {
+            request[name] = value.clone();
+        }
+        request.to_string()
+    }
+
+    #[test]
+    fn login() {
+        let mut server = MockMenderServer::new();
+        server
+            .server
+            .mock("POST", LOGIN_API)
+            .match_header("authorization", Matcher::Regex("^Basic ".into()))
+            .with_body("new-token")
+            .create();
+        let request = serde_json::json!({
+            "server_url": server.server.url(),
+            "email": "user@example.com",
+            "password": "secret",
+        });
+
+        assert_eq!(
+            call_ffi(mender_login, &request.to_string()),
+            (MENDER_OK, serde_json::json!({ "token": "new-token" }))
+        );
+    }
+
+    #[test]
+    fn get_id() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("SerialNumber".into(), "SN1".into()))
+            .with_body(
+                r#"[{"id": "d1", "attributes": [{"name": "SerialNumber", "value": "SN1"}]}]"#,
+            )
+            .create();
+
+        assert_eq!(
+            call_ffi(
+                mender_get_id,
+                &request(&server, serde_json::json!({ "value": "SN1" }))
+            ),
+            (MENDER_OK, serde_json::json!({ "id": "d1" }))
+        );
+    }
+
+    #[test]
+    fn values_starting_with_a_dash() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("--first".into(), "--help".into()))
+            .with_body(r#"[{"id": "d1", "attributes": [{"name": "--first", "value": "--help"}]}]"#)
+            .create();
+
+        assert_eq!(
+            call_ffi(
+                mender_get_id,
+                &request(
+                    &server,
+                    serde_json::json!({ "value": "--help", "attribute": "--first" })
+                )
+            ),
+            (MENDER_OK, serde_json::json!({ "id": "d1" }))
+        );
+    }
+
+    #[test]
+    fn deploy() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", &*format!("{}/g1/devices", GROUPS_API))
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(r#"["d1", "d2"]"#)
+            .create();
+        server.empty_page(&format!("{}/g1/devices", GROUPS_API), 2);
+        server
+            .mock("GET", DEPLOYMENT_LIMITS_API)
+            .with_status(404)
+            .create();
+        let posted = server
+            .mock("POST", DEPLOY_API)
+            .match_body(Matcher::Json(serde_json::json!({
+                "artifact_name": "release-2",
+                "name": "g1",
+                "devices": ["d1", "d2"],
+            })))
+            .with_status(201)
+            .with_header("location", &format!("{}/dep1", DEPLOY_API))
+            .create();
+
+        let deploy = |dry_run: bool| {
+            call_ffi(
+                mender_deploy,
+                &request(
+                    &server,
+                    serde_json::json!({
+                        "group": "g1",
+                        "artifact": "release-2",
+                        "allow_downgrade": true,
+                        "dry_run": dry_run,
+                    }),
+                ),
+            )
+        };
+        assert_eq!(
+            deploy(true),
+            (MENDER_OK, serde_json::json!({ "devices": 2 }))
+        );
+        assert_eq!(
+            deploy(false),
+            (MENDER_OK, serde_json::json!({ "devices": 2 }))
+        );
+        posted.assert();
+    }
+
+    #[test]
+    fn get_info() {
+        let mut server = MockMenderServer::new();
+        let inventory = serde_json::json!({
+            "id": "d1",
+            "attributes": [{ "name": "artifact_name", "value": "release-1" }],
+        });
+        server
+            .mock("GET", &*format!("{}/d1", GET_DEVICES_INVENTORY_API))
+            .with_body(inventory.to_string())
+            .create();
+
+        assert_eq!(
+            call_ffi(
+                mender_get_info,
+                &request(&server, serde_json::json!({ "device": "id:d1" }))
+            ),
+            (MENDER_OK, inventory)
+        );
+        assert_eq!(
+            call_ffi(
+                mender_get_info,
+                &request(
+                    &server,
+                    serde_json::json!({ "device": "id:d1", "attributes": ["artifact_name"] })
+                )
+            ),
+            (
+                MENDER_OK,
+                serde_json::json!({ "artifact_name": "release-1" })
+            )
+        );
+    }
+
+    #[test]
+    fn command_failure() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", &*format!("{}/d2", GET_DEVICES_INVENTORY_API))
+            .with_status(404)
+            .create();
+
+        let (code, response) = call_ffi(
+            mender_get_info,
+            &request(&server, serde_json::json!({ "device": "id:d2" })),
+        );
+        assert_eq!(code, MENDER_COMMAND_FAILED);
+        assert_eq!(
+            response["error"],
+            "No device with Mender id d2, use the sn: prefix for a SerialNumber"
+        );
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let server = MockMenderServer::new();
+        for request in &[
+            String::from("not json"),
+            String::from("[]"),
+            // Missing value
+            request(&server, serde_json::json!({})),
+            request(&server, serde_json::json!({ "value": "SN1", "unknown": 1 })),
+        ] {
+            let (code, response) = call_ffi(mender_get_id, request);
+            assert_eq!(code, MENDER_INVALID_REQUEST, "{}", request);
+            assert!(response["error"].is_string(), "{}", request);
+        }
+        // Arguments are checked as on the command line
+        let (code, response) = call_ffi(
+            mender_deploy,
+            &request(&server, serde_json::json!({ "artifact": "release-2" })),
+        );
+        assert_eq!(code, MENDER_INVALID_REQUEST);
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .contains("arguments were not provided"));
+
+        let mut response = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                mender_get_id(std::ptr::null(), &mut response),
+                MENDER_INVALID_REQUEST
+            );
+            assert_eq!(
+                CStr::from_ptr(response).to_str().unwrap(),
+                r#"{"error":"request is null"}"#
+            );
+            mender_free_string(response);
+            let request = CString::new("{}").unwrap();
+            assert_eq!(
+                mender_get_id(request.as_ptr(), std::ptr::null_mut()),
+                MENDER_INVALID_REQUEST
+            );
+            // Freeing null is allowed
+            mender_free_string(std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Client of the Mender management API behind the mender-rust command line. With the ffi
+//! feature, some of its commands can also be called from C through the ffi module.
+
+/// Print progress information, on stderr when the output is JSON so that stdout only
+/// holds the result.
+macro_rules! progress {
+    ($conf:expr, $($arg:tt)*) => {
+        if $conf.output_format == $crate::parse::OutputFormat::Json {
+            eprint!($($arg)*)
+        } else {
+            print!($($arg)*)
+        }
+    };
+}
+
+/// Flush the stream written by progress!, to show a progress line before it ends.
+macro_rules! flush_progress {
+    ($conf:expr) => {
+        if $conf.output_format == $crate::parse::OutputFormat::Json {
+            std::io::stderr().flush().unwrap()
+        } else {
+            std::io::stdout().flush().unwrap()
+        }
+    };
+}
+
+mod cache;
+mod config_file;
+mod confirm;
+mod env_file;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod man;
+pub mod mender;
+#[cfg(test)]
+mod mock_server;
+pub mod parse;
+pub mod profile;
+mod report;
+mod status;
+mod time;
+mod timings;
+pub mod token_cache;
+mod version;
@@ -1,41 +1,423 @@
 use std::error::Error;
 use std::process;
 
-mod mender;
-mod parse;
+use mender_rust::{man, mender, parse, profile};
 
 fn main() {
     let matches = parse::build_cli().get_matches();
 
-    let command = parse::Command::new(matches).unwrap_or_else(|err| {
+    let command = parse::Command::new(&matches).unwrap_or_else(|err| {
         println!("Parse error: {}", err);
         process::exit(1);
     });
-    let config = parse::Config::new(command).unwrap_or_else(|err| {
+    // The manual page doesn't need a server
+    if let parse::Command::Man { out_dir } = &command {
+        match man::write(out_dir) {
+            Ok(Some(path)) => println!("Manual page written to {}", path),
+            Ok(None) => (),
+            Err(e) => {
+                println!("Run error: {}", e);
+                process::exit(2);
+            }
+        }
+        return;
+    }
+    let config = parse::Config::new(command, &matches).unwrap_or_else(|err| {
         println!("Config error: {}", err);
         process::exit(1);
     });
 
-    if let Err(e) = run(config) {
-        println!("Run error: {}", e);
-        process::exit(2);
+    let result = run(&config);
+    if let Some(timings) = &config.timings {
+        print!("{}", timings.summary());
+    }
+    match result {
+        Ok(0) => (),
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            println!("Run error: {}", e);
+            process::exit(mender::exit_code(e.as_ref()));
+        }
     }
 }
 
-fn run(config: parse::Config) -> Result<(), Box<dyn Error>> {
-    match config.command {
-        parse::Command::Login { .. } => {
+/// Run the command, return the exit status of a command which completed but reports a
+/// failure, see EXIT_CODES.
+fn run(config: &parse::Config) -> Result<i32, Box<dyn Error>> {
+    match &config.command {
+        parse::Command::Login { save, .. } => {
             println!("Type password:");
             let mut password = String::new();
             std::io::stdin().read_line(&mut password)?;
-            println!("Token {}", mender::get_token(&config, &password.trim())?);
+            let token = mender::get_token(config, password.trim())?;
+            if *save {
+                let path =
+                    profile::save_token(config.config_dir.as_deref(), &config.profile, &token)?;
+                println!(
+                    "Token of profile {} saved to {}",
+                    config.profile,
+                    path.display()
+                );
+            } else {
+                println!("Token {}", token);
+            }
+        }
+        parse::Command::Logout => {
+            if profile::remove_token(config.config_dir.as_deref(), &config.profile)? {
+                println!("Token of profile {} removed", config.profile);
+            } else {
+                println!("No token saved for profile {}", config.profile);
+            }
         }
         parse::Command::Deploy { .. } => {
-            println!("Deployed to {} devices", mender::deploy(&config)?)
+            let nb_devices = mender::deploy(config)?;
+            print_result(
+                config,
+                if config.dry_run {
+                    format!("Would deploy to {} devices", nb_devices)
+                } else {
+                    format!("Deployed to {} devices", nb_devices)
+                },
+                serde_json::json!({ "devices": nb_devices }),
+            )?
+        }
+        parse::Command::GetId { .. } => {
+            let id = mender::get_id(config)?;
+            print_result(
+                config,
+                format!("Mender id is: {}", id),
+                serde_json::json!({ "id": id }),
+            )?
+        }
+        parse::Command::GetInfo { .. } => println!("{}", mender::get_info(config)?),
+        parse::Command::CountArtifacts { .. } => {
+            let counts = mender::count_artifacts(config)?;
+            print_result(
+                config,
+                mender::display_ordered(counts.clone()),
+                serde_json::to_value(counts)?,
+            )?
+        }
+        parse::Command::CountGroups => {
+            let counts = mender::count_groups(config)?;
+            print_result(
+                config,
+                mender::display_ordered(counts.clone()),
+                serde_json::to_value(counts)?,
+            )?
+        }
+        parse::Command::DeploymentStatus { .. } => {
+            let statistics = mender::deployment_status(config)?;
+            print_result(
+                config,
+                mender::display_ordered(statistics.clone()),
+                serde_json::to_value(&statistics)?,
+            )?;
+            if statistics
+                .get("failure")
+                .is_some_and(|failures| *failures > 0)
+            {
+                return Ok(3);
+            }
+        }
+        parse::Command::Stats { .. } => println!("{}", mender::stats(config)?),
+        parse::Command::FindId { .. } => {
+            let found = mender::find_ids(config)?;
+            print_result(
+                config,
+                found
+                    .iter()
+                    .map(|(id, value)| format!("{}\t{}", id, value))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                serde_json::json!(found
+                    .iter()
+                    .map(|(id, value)| serde_json::json!({ "id": id, "value": value }))
+                    .collect::<Vec<_>>()),
+            )?
+        }
+        parse::Command::Search { .. } => print!("{}", mender::search(config)?),
+        parse::Command::SetAttributesFromFile { device, .. } => println!(
+            "Updated {} attributes on device {}",
+            mender::set_attributes_from_file(config)?,
+            device
+        ),
+        parse::Command::ListDevices { .. } => print!("{}", mender::list_devices(config)?),
+        parse::Command::DeviceCount => {
+            let counts = mender::device_count(config)?;
+            let total: usize = counts.iter().map(|(_, count)| count).sum();
+            let mut text = String::new();
+            for (status, count) in &counts {
+                text.push_str(&format!("{:<15}{:>8}\n", status, count));
+            }
+            text.push_str(&format!("{:<15}{:>8}", "total", total));
+            let mut json: serde_json::Map<String, serde_json::Value> = counts
+                .into_iter()
+                .map(|(status, count)| (status, serde_json::json!(count)))
+                .collect();
+            json.insert(String::from("total"), serde_json::json!(total));
+            print_result(config, text, serde_json::Value::Object(json))?
+        }
+        parse::Command::Man { .. } => {
+            unreachable!("the manual page is written before the configuration is built")
+        }
+        parse::Command::GroupDevices { .. } => {
+            for device in mender::group_devices(config)? {
+                println!("{}", device);
+            }
+        }
+        parse::Command::ListGroups => {
+            let counts = mender::list_groups(config)?;
+            print_result(
+                config,
+                mender::display_ordered_with_unit(counts.clone(), " devices")
+                    .trim_end()
+                    .to_string(),
+                serde_json::json!(counts),
+            )?
+        }
+        parse::Command::Stale { .. } => print!("{}", mender::stale(config)?),
+        parse::Command::GetDevicesNotInAnyGroup => {
+            print!("{}", mender::get_devices_not_in_any_group(config)?)
+        }
+        parse::Command::GetArtifactProvides { .. } => {
+            let mut provides: Vec<_> = mender::get_artifact_provides(config)?.into_iter().collect();
+            provides.sort();
+            for (key, value) in provides {
+                println!("{}: {}", key, value);
+            }
+        }
+        parse::Command::ListArtifacts => print!("{}", mender::list_artifacts(config)?),
+        parse::Command::UploadArtifact { .. } => {
+            let id = mender::upload_artifact(config)?;
+            print_result(
+                config,
+                format!("Artifact {} uploaded", id),
+                serde_json::json!({ "id": id }),
+            )?
+        }
+        parse::Command::ListGroupsForDevice { .. } => {
+            let groups = mender::list_groups_for_device(config)?;
+            if groups.is_empty() {
+                println!("Device is not a member of any group");
+            }
+            for group in groups {
+                println!("{}", group);
+            }
+        }
+        parse::Command::GetGroup { .. } => println!("{}", mender::get_group(config)?),
+        parse::Command::WaitAttr { condition, .. } => println!(
+            "Attribute {} of device {} reached the expected value",
+            condition.name,
+            mender::wait_attr(config)?
+        ),
+        parse::Command::AbortDeployment { .. } => {
+            if let Some(id) = mender::abort_deployment(config)? {
+                println!("Deployment {} aborted", id);
+            }
+        }
+        parse::Command::DeploymentDevices { .. } => {
+            print!("{}", mender::deployment_devices(config)?)
+        }
+        parse::Command::GetDeploymentErrors { .. } => {
+            let mut errors: Vec<_> = mender::get_deployment_errors(config)?.into_iter().collect();
+            errors.sort();
+            for (device, error) in errors {
+                println!("{:<40} {}", device, error);
+            }
+        }
+        parse::Command::CheckHealth { .. } => print!("{}", mender::check_health(config)?),
+        parse::Command::SelfTest { .. } => print!("{}", mender::selftest(config)?),
+        parse::Command::GetDeploymentYaml { output, .. } => {
+            let yaml = mender::get_deployment_yaml(config)?;
+            if let Some(output) = output {
+                std::fs::write(output, yaml)?;
+                println!("Deployment written to {}", output);
+            } else {
+                print!("{}", yaml);
+            }
+        }
+        parse::Command::ExportFilters { out } => {
+            let filters = mender::export_filters(config)?;
+            if let Some(out) = out {
+                std::fs::write(out, filters)?;
+                println!("Filters written to {}", out);
+            } else {
+                println!("{}", filters);
+            }
+        }
+        parse::Command::ImportFilters { .. } => {
+            println!("Imported {} filters", mender::import_filters(config)?)
+        }
+        parse::Command::GetPaginationDefaults => {
+            println!("per_page: {}", config.pagination.per_page)
+        }
+        parse::Command::StatsAttr { .. } => print!("{}", mender::stats_attr(config)?),
+        parse::Command::GetLatestArtifactPerDeviceType => {
+            let mut latest: Vec<_> = mender::get_latest_artifact_per_device_type(config)?
+                .into_iter()
+                .collect();
+            latest.sort();
+            for (device_type, artifact) in latest {
+                println!("{:<30} {}", device_type, artifact);
+            }
+        }
+        parse::Command::ShowConfig => println!("{}", config.to_json()?),
+        parse::Command::GetPendingDeploymentStatsAll => {
+            print!("{}", mender::get_pending_deployment_stats_all(config)?)
+        }
+        parse::Command::SetGroup { group, .. } => {
+            println!(
+                "Device {} moved to group {}",
+                mender::set_group(config)?,
+                group
+            )
+        }
+        parse::Command::AddToGroup { group, .. } => {
+            let (id, previous_group) = mender::add_to_group(config)?;
+            match previous_group {
+                Some(previous_group) if &previous_group == group => {
+                    println!("Device {} already in group {}", id, group)
+                }
+                Some(previous_group) => println!(
+                    "Device {} added to group {}, previous group was {}",
+                    id, group, previous_group
+                ),
+                None => println!("Device {} added to group {}", id, group),
+            }
+        }
+        parse::Command::AssignGroup { .. } => println!("{}", mender::assign_group(config)?),
+        parse::Command::CreateGroup { .. } => println!("{}", mender::create_group(config)?),
+        parse::Command::DeleteGroup { .. } => println!("{}", mender::delete_group(config)?),
+        parse::Command::RenameGroup { .. } => println!("{}", mender::rename_group(config)?),
+        parse::Command::RemoveFromGroup { .. } => match mender::remove_from_group(config)? {
+            (id, Some(group)) => println!("Device {} removed from group {}", id, group),
+            (id, None) => println!("Device {} is not in any group, nothing to do", id),
+        },
+        parse::Command::SetGroupFromDeployment { .. } => {
+            println!("{}", mender::set_group_from_deployment(config)?)
+        }
+        parse::Command::FleetReport { out, .. } => {
+            std::fs::write(out, mender::fleet_report(config)?)?;
+            println!("Fleet report written to {}", out);
+        }
+        parse::Command::ListPending => print!("{}", mender::list_pending(config)?),
+        parse::Command::GetAuth { .. } => println!("{}", mender::get_auth(config)?),
+        parse::Command::Preauth { .. } => match mender::preauth(config)? {
+            Some(id) => println!("Device {} preauthorized", id),
+            None => println!("Device preauthorized"),
+        },
+        parse::Command::Accept { .. } => println!("Device {} accepted", mender::accept(config)?),
+        parse::Command::Reject { .. } => println!("{}", mender::reject(config)?),
+        parse::Command::Decommission { .. } => {
+            if let Some(id) = mender::decommission(config)? {
+                println!("Device {} decommissioned", id);
+            }
+        }
+        parse::Command::DeleteInventory { .. } => {
+            if let Some(id) = mender::delete_inventory(config)? {
+                println!("Inventory of device {} deleted", id);
+            }
+        }
+        parse::Command::ListGroupsByArtifact { .. } => {
+            let groups = mender::list_groups_by_artifact(config)?;
+            if groups.is_empty() {
+                println!("No group has reached the threshold");
+                return Ok(1);
+            }
+            for group in groups {
+                println!("{}", group);
+            }
+        }
+        parse::Command::ListDeployments { .. } => {
+            print!("{}", mender::list_filtered_deployments(config)?)
+        }
+        parse::Command::CleanupDeployments { .. } => {
+            println!("{}", mender::cleanup_deployments(config)?)
+        }
+        parse::Command::CleanupFinishedDeployments { .. } => {
+            println!("{}", mender::cleanup_finished_deployments(config)?)
         }
-        parse::Command::GetId { .. } => println!("Mender id is: {}", mender::get_id(&config)?),
-        parse::Command::GetInfo { .. } => println!("{}", mender::get_info(&config)?),
-        parse::Command::CountArtifacts => println!("{}", mender::count_artifacts(&config)?),
     };
+    Ok(0)
+}
+
+/// Print the result of a command in the requested output format.
+fn print_result(
+    config: &parse::Config,
+    text: String,
+    json: serde_json::Value,
+) -> Result<(), Box<dyn Error>> {
+    match config.output_format {
+        parse::OutputFormat::Text => println!("{}", text),
+        parse::OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&json)?),
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Configuration of a command run against the server with a token.
+    fn config(server_url: &str, args: &[&str]) -> parse::Config {
+        let env_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(env_file.path(), "TOKEN=token\n").unwrap();
+        let matches = parse::build_cli()
+            .get_matches_from_safe(
+                [
+                    "mender-rust",
+                    "--server-url",
+                    server_url,
+                    "--config",
+                    "/dev/null",
+                    "--env-file",
+                    env_file.path().to_str().unwrap(),
+                ]
+                .iter()
+                .chain(args.iter())
+                .copied(),
+            )
+            .unwrap();
+        parse::Config::with_config_dir(parse::Command::new(&matches).unwrap(), &matches, None)
+            .unwrap()
+    }
+
+    #[test]
+    fn failed_devices_of_deploymentstatus_exit_with_3() {
+        let mut server = mockito::Server::new();
+        server
+            .mock(
+                "GET",
+                format!("{}/dep1/statistics", mender::DEPLOY_API).as_str(),
+            )
+            .with_body(r#"{"success": 1, "failure": 1}"#)
+            .create();
+        let conf = config(
+            &server.url(),
+            &["deploymentstatus", "--deployment-id", "dep1"],
+        );
+        assert_eq!(run(&conf).unwrap(), 3);
+
+        server.reset();
+        server
+            .mock(
+                "GET",
+                format!("{}/dep1/statistics", mender::DEPLOY_API).as_str(),
+            )
+            .with_body(r#"{"success": 2}"#)
+            .create();
+        assert_eq!(run(&conf).unwrap(), 0);
+    }
+
+    #[test]
+    fn no_group_of_listgroupsbyartifact_exits_with_1() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", mender::GROUPS_API)
+            .with_body("[]")
+            .create();
+        let conf = config(&server.url(), &["listgroupsbyartifact", "release-1"]);
+        assert_eq!(run(&conf).unwrap(), 1);
+    }
+}
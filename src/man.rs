@@ -0,0 +1,198 @@
+use super::parse::{build_cli, EXAMPLES, EXIT_CODES};
+use std::error::Error;
+use std::path::Path;
+
+/// Width of the help texts included in the manual page.
+const TERM_WIDTH: usize = 80;
+
+/// Help of the tool or of one of its subcommands, rendered by clap as for --help.
+fn help(args: &[&str]) -> String {
+    match build_cli()
+        .set_term_width(TERM_WIDTH)
+        .get_matches_from_safe(args)
+    {
+        Err(err) if err.kind == clap::ErrorKind::HelpDisplayed => err.message,
+        _ => String::new(),
+    }
+}
+
+/// Escape text for roff, so that lines starting with a dot aren't taken as requests.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e")
+        .lines()
+        .map(|line| {
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}\n", line)
+            } else {
+                format!("{}\n", line)
+            }
+        })
+        .collect()
+}
+
+fn section(title: &str, preformatted: &str) -> String {
+    format!(".SH {}\n.nf\n{}.fi\n", title, escape(preformatted))
+}
+
+/// Render the manual page in roff format. The options and subcommands come from the
+/// clap App, so the page can't get out of sync with the parser.
+pub fn render() -> String {
+    let app = build_cli();
+    let meta = &app.p.meta;
+    let mut page = format!(
+        ".TH MENDER-RUST 1 \"\" \"mender-rust {}\" \"User Commands\"\n.SH NAME\nmender-rust \\- {}\n",
+        meta.version.unwrap_or_default(),
+        escape(meta.about.unwrap_or_default()).trim_end()
+    );
+    page.push_str(".SH SYNOPSIS\n.B mender-rust\n[OPTIONS] <SUBCOMMAND>\n");
+    // The environment has its own section, it ends the help but clap may wrap it so
+    // it is cut from its first line
+    let options = help(&["mender-rust", "--help"]);
+    let options = match meta
+        .more_help
+        .and_then(|more_help| more_help.lines().next())
+        .and_then(|first_line| options.find(first_line))
+    {
+        Some(start) => options[..start].to_string(),
+        None => options,
+    };
+    page.push_str(&section("OPTIONS", options.trim_end()));
+
+    page.push_str(".SH SUBCOMMANDS\n");
+    for subcommand in app
+        .p
+        .subcommands
+        .iter()
+        .filter(|sc| sc.p.meta.name != "help")
+    {
+        let name = &subcommand.p.meta.name;
+        page.push_str(&format!(
+            ".SS {}\n.nf\n{}.fi\n",
+            name,
+            escape(help(&["mender-rust", name, "--help"]).trim_end())
+        ));
+    }
+
+    if let Some(more_help) = meta.more_help {
+        page.push_str(&section("ENVIRONMENT", more_help));
+    }
+    page.push_str(".SH EXIT STATUS\n");
+    for (code, description) in EXIT_CODES.iter() {
+        page.push_str(&format!(".TP\n.B {}\n{}", code, escape(description)));
+    }
+    page.push_str(".SH EXAMPLES\n");
+    for (example, description) in EXAMPLES.iter() {
+        page.push_str(&format!(
+            ".TP\n.B {}\n{}",
+            escape(example).trim_end(),
+            escape(description)
+        ));
+    }
+    page
+}
+
+/// Print the manual page, or write it as mender-rust.1 in a directory and return its
+/// path.
+pub fn write(out_dir: &Option<impl AsRef<Path>>) -> Result<Option<String>, Box<dyn Error>> {
+    match out_dir {
+        Some(out_dir) => {
+            std::fs::create_dir_all(out_dir)?;
+            let path = out_dir.as_ref().join("mender-rust.1");
+            std::fs::write(&path, render())?;
+            Ok(Some(path.display().to_string()))
+        }
+        None => {
+            print!("{}", render());
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_in_order() {
+        let page = render();
+        assert!(page.starts_with(".TH MENDER-RUST 1 "));
+        let titles: Vec<&str> = page
+            .lines()
+            .filter_map(|line| line.strip_prefix(".SH "))
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                "NAME",
+                "SYNOPSIS",
+                "OPTIONS",
+                "SUBCOMMANDS",
+                "ENVIRONMENT",
+                "EXIT STATUS",
+                "EXAMPLES"
+            ]
+        );
+    }
+
+    #[test]
+    fn every_subcommand_has_its_help() {
+        let page = render();
+        let subcommands: Vec<&str> = page
+            .lines()
+            .filter_map(|line| line.strip_prefix(".SS "))
+            .collect();
+        assert!(subcommands.contains(&"deploy"));
+        assert!(subcommands.contains(&"man"));
+        assert!(!subcommands.contains(&"help"));
+        assert_eq!(
+            subcommands.len(),
+            build_cli().p.subcommands.len(),
+            "{:?}",
+            subcommands
+        );
+        let deploy = page.split(".SS deploy\n").nth(1).unwrap();
+        assert!(deploy.starts_with(".nf\n"));
+        assert!(deploy.contains("--dry-run"));
+    }
+
+    #[test]
+    fn environment_is_only_in_its_section() {
+        let page = render();
+        let more_help = build_cli().p.meta.more_help.unwrap();
+        let first_line = more_help.lines().find(|line| !line.is_empty()).unwrap();
+        assert_eq!(page.matches(first_line).count(), 1);
+        assert!(page.find(first_line) > page.find(".SH ENVIRONMENT"));
+    }
+
+    #[test]
+    fn exit_codes_and_examples() {
+        let page = render();
+        for (code, description) in EXIT_CODES.iter() {
+            assert!(
+                page.contains(&format!(".TP\n.B {}\n{}\n", code, description)),
+                "{}",
+                code
+            );
+        }
+        for (example, _) in EXAMPLES.iter() {
+            assert!(page.contains(&format!(".B {}\n", example)), "{}", example);
+        }
+    }
+
+    #[test]
+    fn escape_roff_requests() {
+        assert_eq!(
+            escape(".hidden\n'quoted\nC:\\path\nplain"),
+            "\\&.hidden\n\\&'quoted\nC:\\epath\nplain\n"
+        );
+    }
+
+    #[test]
+    fn write_to_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = Some(dir.path().join("man1"));
+        let path = write(&out_dir).unwrap().unwrap();
+        assert!(path.ends_with("mender-rust.1"));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), render());
+    }
+}
@@ -1,15 +1,49 @@
-use super::parse::{Command, Config};
+use super::cache;
+use super::confirm::{choose, confirm, Decision};
+use super::parse::{
+    AttributeCondition, Command, Config, DeploymentFilter, DeviceColumn, DeviceRef,
+    FailureThresholds, Operator, OutputFormat, SerialNumberMatching,
+};
+use super::report::FleetReport;
+use super::status::{normalize_statistics, DeploymentStatus, DeviceStatus};
+use super::version::Version;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Display;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub const LOGIN_API: &str = "/api/management/v1/useradm/auth/login";
+pub const CURRENT_USER_API: &str = "/api/management/v1/useradm/users/me";
 pub const DEPLOY_API: &str = "/api/management/v1/deployments/deployments";
 pub const GET_DEVICES_INVENTORY_API: &str = "/api/management/v1/inventory/devices";
 pub const GET_DEVICES_AUTH_API: &str = "/api/management/v2/devauth/devices";
+pub const ARTIFACTS_API: &str = "/api/management/v1/deployments/artifacts";
+pub const ARTIFACTS_LIST_API: &str = "/api/management/v2/deployments/artifacts";
+pub const GROUPS_API: &str = "/api/management/v1/inventory/groups";
+pub const FILTERS_API: &str = "/api/management/v2/inventory/filters";
+pub const DEPLOYMENT_LIMITS_API: &str = "/api/management/v1/deployments/limits";
+pub const STORAGE_LIMITS_API: &str = "/api/management/v1/deployments/limits/storage";
+pub const USERADM_HEALTH_API: &str = "/api/management/v1/useradm/health";
+pub const INVENTORY_HEALTH_API: &str = "/api/management/v1/inventory/health";
+pub const DEPLOYMENTS_HEALTH_API: &str = "/api/management/v1/deployments/health";
+pub const DEVAUTH_HEALTH_API: &str = "/api/management/v1/devauth/health";
+pub const DEVICECONNECT_HEALTH_API: &str = "/api/management/v1/deviceconnect/health";
+pub const DEVICEMONITOR_HEALTH_API: &str = "/api/management/v1/devicemonitor/health";
+pub const DEVICECONFIG_HEALTH_API: &str = "/api/management/v1/deviceconfig/health";
+pub const HEALTH_APIS: [(&str, &str); 7] = [
+    ("useradm", USERADM_HEALTH_API),
+    ("inventory", INVENTORY_HEALTH_API),
+    ("deployments", DEPLOYMENTS_HEALTH_API),
+    ("devauth", DEVAUTH_HEALTH_API),
+    ("deviceconnect", DEVICECONNECT_HEALTH_API),
+    ("devicemonitor", DEVICEMONITOR_HEALTH_API),
+    ("deviceconfig", DEVICECONFIG_HEALTH_API),
+];
 
 #[derive(Debug)]
 pub struct MenderError {
@@ -30,18 +64,310 @@ impl MenderError {
     }
 }
 
-fn blocking_client(
-    cert_file: &Option<String>,
-) -> Result<reqwest::blocking::Client, Box<dyn Error>> {
-    if let Some(cert_file) = cert_file {
+/// Error returned when a deployment doesn't target the expected number of devices, so
+/// that scripts can tell it apart from other failures.
+#[derive(Debug)]
+pub struct UnexpectedCount {
+    count: usize,
+    expected: String,
+}
+
+impl Display for UnexpectedCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "Deployment targets {} devices, {} expected",
+            self.count, self.expected
+        )
+    }
+}
+
+impl Error for UnexpectedCount {}
+
+/// Error of a command stopped by the deadline, with what it completed before.
+#[derive(Debug)]
+pub struct DeadlineReached {
+    deadline: std::time::Duration,
+    /// Partial results, such as the pages of a listing received before the deadline.
+    completed: Option<String>,
+}
+
+impl Display for DeadlineReached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "Deadline of {}s reached", self.deadline.as_secs())?;
+        if let Some(completed) = &self.completed {
+            write!(f, "\nCompleted before the deadline: {}", completed)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DeadlineReached {}
+
+/// Exit status of a command which failed with the given error, see EXIT_CODES.
+pub fn exit_code(err: &(dyn Error + 'static)) -> i32 {
+    if err.is::<UnexpectedCount>() {
+        3
+    } else if err.is::<DeadlineReached>() {
+        4
+    } else {
+        2
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum CertFormat {
+    Pem,
+    Der,
+}
+
+/// Tell DER certificates, which start with the ASN.1 SEQUENCE tag, from PEM ones.
+fn detect_cert_format(cert: &[u8]) -> CertFormat {
+    if cert.first() == Some(&0x30) {
+        CertFormat::Der
+    } else {
+        CertFormat::Pem
+    }
+}
+
+/// Certificate of the configuration, to trust in addition to the system ones.
+fn root_certificate(conf: &Config) -> Result<Option<reqwest::Certificate>, Box<dyn Error>> {
+    if let Some(cert_file) = &conf.cert_file {
         let mut buf = Vec::new();
         File::open(cert_file)?.read_to_end(&mut buf)?;
-        let cert = reqwest::Certificate::from_pem(&buf)?;
-        Ok(reqwest::blocking::Client::builder()
-            .add_root_certificate(cert)
-            .build()?)
+        let cert = match detect_cert_format(&buf) {
+            CertFormat::Der => reqwest::Certificate::from_der(&buf)?,
+            CertFormat::Pem => reqwest::Certificate::from_pem(&buf)?,
+        };
+        return Ok(Some(cert));
+    }
+    Ok(None)
+}
+
+/// Proxy of the configuration, through which all the requests are sent.
+fn proxy(conf: &Config) -> Result<Option<reqwest::Proxy>, Box<dyn Error>> {
+    match &conf.proxy_url {
+        Some(proxy_url) => {
+            Ok(Some(reqwest::Proxy::all(proxy_url).map_err(|err| {
+                MenderError::new(format!("Invalid proxy url: {}", err))
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Client builder trusting the certificate of the configuration and sending the
+/// requests through its proxy, if any.
+fn blocking_client_builder(
+    conf: &Config,
+) -> Result<reqwest::blocking::ClientBuilder, Box<dyn Error>> {
+    let mut builder =
+        reqwest::blocking::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if let Some(cert) = root_certificate(conf)? {
+        builder = builder.add_root_certificate(cert);
+    }
+    if let Some(proxy) = proxy(conf)? {
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder)
+}
+
+fn blocking_client(conf: &Config) -> Result<reqwest::blocking::Client, Box<dyn Error>> {
+    Ok(blocking_client_builder(conf)?.build()?)
+}
+
+/// Send a request, its duration is recorded when timings are enabled. The request
+/// fails if the deadline of the command has been reached or is reached while waiting
+/// for the response.
+/// Requests failing on a transient error are retried as set by the retry policy.
+/// Refused connections and 429 responses, for which the request wasn't processed, are
+/// always retried while other network errors and 5xx responses are only retried for
+/// idempotent methods, so that deployments and the like are never created twice.
+/// Requests whose body is streamed can't be retried.
+fn send(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    request: reqwest::blocking::RequestBuilder,
+) -> Result<reqwest::blocking::Response, Box<dyn Error>> {
+    let mut request = request.build()?;
+    let idempotent = request.method().is_idempotent();
+    let mut retry = 0;
+    loop {
+        let retry_request = request.try_clone();
+        let description = format!("{} {}", request.method(), request.url());
+        let result = execute(conf, client, request);
+        let transient = match &result {
+            Ok(response) => is_transient_status(response.status(), idempotent),
+            Err(err) => is_transient_error(err.as_ref(), idempotent),
+        };
+        match (retry_request, retry_delay(conf, retry, transient)) {
+            (Some(retry_request), Some(delay)) => {
+                progress!(
+                    conf,
+                    "{} failed, retrying in {}ms\n",
+                    description,
+                    delay.as_millis()
+                );
+                std::thread::sleep(delay);
+                request = retry_request;
+                retry += 1;
+            }
+            _ => return result,
+        }
+    }
+}
+
+fn is_transient_status(status: reqwest::StatusCode, idempotent: bool) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || (idempotent && status.is_server_error())
+}
+
+fn is_transient_error(err: &(dyn Error + 'static), idempotent: bool) -> bool {
+    err.downcast_ref::<reqwest::Error>().is_some() && (idempotent || is_connection_refused(err))
+}
+
+/// Delay before retrying a request which failed, None if it must not be retried: the
+/// failure isn't transient, all the retries were made or the deadline would be reached.
+fn retry_delay(conf: &Config, retry: u32, transient: bool) -> Option<std::time::Duration> {
+    let delay = conf.retry.delay(retry);
+    let in_time = conf
+        .execution
+        .remaining()
+        .is_none_or(|remaining| delay < remaining);
+    if transient && retry < conf.retry.retries && in_time {
+        Some(delay)
+    } else {
+        None
+    }
+}
+
+/// Check if an error comes from a connection refused by the server.
+fn is_connection_refused(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            if io_err.kind() == std::io::ErrorKind::ConnectionRefused {
+                return true;
+            }
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Send a request once, within the deadline. Redirects are not followed by the client
+/// as they usually mean that SERVER_URL is the url of the UI, which redirects to its
+/// login page, an error is returned instead. The only redirects followed are 307 and
+/// 308 to the same path on the same host, such as from http to https.
+fn execute(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    request: reqwest::blocking::Request,
+) -> Result<reqwest::blocking::Response, Box<dyn Error>> {
+    let follow_up = request.try_clone();
+    let response = execute_once(conf, client, request)?;
+    if !response.status().is_redirection() {
+        return Ok(response);
+    }
+    match (
+        followed_redirect(response.status(), response.headers(), response.url()),
+        follow_up,
+    ) {
+        (Some(location), Some(mut follow_up)) => {
+            *follow_up.url_mut() = location;
+            let response = execute_once(conf, client, follow_up)?;
+            if response.status().is_redirection() {
+                return Err(redirect_error(response.headers(), response.url()));
+            }
+            Ok(response)
+        }
+        _ => Err(redirect_error(response.headers(), response.url())),
+    }
+}
+
+/// Location of a redirect response if it is followed, see execute.
+fn followed_redirect(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+    url: &reqwest::Url,
+) -> Option<reqwest::Url> {
+    let location = headers
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .and_then(|location| url.join(location).ok())?;
+    let same_path = &location != url
+        && location.host_str() == url.host_str()
+        && location.path() == url.path()
+        && location.query() == url.query();
+    match status {
+        reqwest::StatusCode::TEMPORARY_REDIRECT | reqwest::StatusCode::PERMANENT_REDIRECT
+            if same_path =>
+        {
+            Some(location)
+        }
+        _ => None,
+    }
+}
+
+fn redirect_error(headers: &reqwest::header::HeaderMap, url: &reqwest::Url) -> Box<dyn Error> {
+    let location = headers
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .unwrap_or("<no location>");
+    Box::new(MenderError::new(format!(
+        "server redirected {} to {}; SERVER_URL probably points at the UI, not the API",
+        url, location
+    )))
+}
+
+/// Error if the deadline of the command has been reached.
+fn check_deadline(conf: &Config) -> Result<(), Box<dyn Error>> {
+    deadline_error(conf, None).map_or(Ok(()), Err)
+}
+
+/// DeadlineReached error with the given partial results if the deadline of the
+/// command has been reached, None if it hasn't.
+fn deadline_error(conf: &Config, completed: Option<String>) -> Option<Box<dyn Error>> {
+    match conf.execution.deadline {
+        Some(deadline) if conf.execution.remaining() == Some(std::time::Duration::ZERO) => {
+            Some(Box::new(DeadlineReached {
+                deadline,
+                completed,
+            }))
+        }
+        _ => None,
+    }
+}
+
+fn execute_once(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    mut request: reqwest::blocking::Request,
+) -> Result<reqwest::blocking::Response, Box<dyn Error>> {
+    check_deadline(conf)?;
+    if let Some(timeout) = conf.execution.timeout() {
+        *request.timeout_mut() = Some(timeout);
+    }
+    if let Some(timings) = &conf.timings {
+        let url = request.url().to_string();
+        let start = std::time::Instant::now();
+        let response = client.execute(request);
+        timings.record(&url, start.elapsed());
+        timed_out_by_deadline(conf, response)
     } else {
-        Ok(reqwest::blocking::Client::builder().build()?)
+        timed_out_by_deadline(conf, client.execute(request))
+    }
+}
+
+/// Report a request whose timeout was cut short by the deadline as reaching it.
+fn timed_out_by_deadline(
+    conf: &Config,
+    response: reqwest::Result<reqwest::blocking::Response>,
+) -> Result<reqwest::blocking::Response, Box<dyn Error>> {
+    match response {
+        Err(err) if err.is_timeout() => {
+            Err(deadline_error(conf, None).unwrap_or_else(|| Box::new(err)))
+        }
+        response => Ok(response?),
     }
 }
 
@@ -58,16 +384,326 @@ macro_rules! check_success {
     };
 }
 
+/// Get the target of the link with the given relation type from a Link header value
+/// (RFC 8288), None is returned if there is no such link.
+fn find_link(header: &str, rel: &str) -> Option<String> {
+    // Split the header on commas and semicolons outside of the <uri> and quoted strings
+    let mut links: Vec<Vec<String>> = vec![vec![String::new()]];
+    let (mut in_uri, mut in_quotes, mut escaped) = (false, false, false);
+    for c in header.chars() {
+        let link = links.last_mut().unwrap();
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => {
+                escaped = true;
+                continue;
+            }
+            '"' if !in_uri => {
+                in_quotes = !in_quotes;
+                continue;
+            }
+            '<' if !in_quotes => in_uri = true,
+            '>' if !in_quotes => in_uri = false,
+            ',' if !in_uri && !in_quotes => {
+                links.push(vec![String::new()]);
+                continue;
+            }
+            ';' if !in_uri && !in_quotes => {
+                link.push(String::new());
+                continue;
+            }
+            _ => (),
+        }
+        link.last_mut().unwrap().push(c);
+    }
+
+    links.into_iter().find_map(|link| {
+        let uri = link[0]
+            .trim()
+            .strip_prefix('<')?
+            .strip_suffix('>')?
+            .to_string();
+        let has_rel = link[1..].iter().any(|param| match param.split_once('=') {
+            Some((name, value)) => {
+                name.trim().eq_ignore_ascii_case("rel")
+                    && value
+                        .split_whitespace()
+                        .any(|value| value.eq_ignore_ascii_case(rel))
+            }
+            None => false,
+        });
+        if has_rel {
+            Some(uri)
+        } else {
+            None
+        }
+    })
+}
+
+/// Walk through all the pages of a listing, `handle_page` is called with the items of
+/// each non empty page and returns false to stop. When the server sends a Link header
+/// its next link is followed and the listing ends when there is none, otherwise the
+/// page number is incremented until an empty page is returned.
+/// If concurrent requests are allowed and the first page has no Link header the next
+/// pages are fetched concurrently, see paginate_concurrently.
+/// When the deadline is reached the error tells how many pages were handled before.
+fn paginate<T: DeserializeOwned + Send>(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    url: &str,
+    query: &[(&str, &str)],
+    cmd: &str,
+    mut handle_page: impl FnMut(Vec<T>) -> bool,
+) -> Result<(), Box<dyn Error>> {
+    let (mut pages, mut items) = (0, 0);
+    paginate_pages(conf, client, token, url, query, cmd, |res: Vec<T>| {
+        pages += 1;
+        items += res.len();
+        handle_page(res)
+    })
+    .map_err(|err| {
+        // The innermost listing reports its progress
+        if matches!(err.downcast_ref::<DeadlineReached>(), Some(reached) if reached.completed.is_some())
+        {
+            return err;
+        }
+        // Errors of concurrent requests only keep their message, the deadline is checked
+        deadline_error(
+            conf,
+            Some(format!("{}, {} items in {} pages", cmd, items, pages)),
+        )
+        .unwrap_or(err)
+    })
+}
+
+/// Same as paginate without reporting the progress when the deadline is reached.
+fn paginate_pages<T: DeserializeOwned + Send>(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    url: &str,
+    query: &[(&str, &str)],
+    cmd: &str,
+    mut handle_page: impl FnMut(Vec<T>) -> bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut next_url: Option<reqwest::Url> = None;
+    let mut page_idx = 1;
+    loop {
+        let request = if let Some(next_url) = next_url.take() {
+            client.get(next_url)
+        } else {
+            client.get(url).query(query).query(&[
+                ("per_page", conf.pagination.per_page.to_string()),
+                ("page", page_idx.to_string()),
+            ])
+        };
+        let response = send(conf, client, request.bearer_auth(token))?;
+
+        check_success!(response, cmd);
+        let has_links = response.headers().contains_key(reqwest::header::LINK);
+        next_url = response
+            .headers()
+            .get(reqwest::header::LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(|link| find_link(link, "next"))
+            .map(|next| response.url().join(&next))
+            .transpose()?;
+        let res = parse_page(conf, response)?;
+        let page_size = res.len();
+        if res.is_empty() || !handle_page(res) || (has_links && next_url.is_none()) {
+            return Ok(());
+        }
+        if !has_links && conf.execution.max_concurrent_requests > 1 {
+            return paginate_concurrently(
+                conf,
+                client,
+                token,
+                url,
+                query,
+                cmd,
+                page_idx + 1,
+                page_size,
+                handle_page,
+            );
+        }
+        page_idx += 1;
+    }
+}
+
+/// Get one page of a listing, addressed by its number.
+fn get_page<T: DeserializeOwned>(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    url: &str,
+    query: &[(&str, &str)],
+    cmd: &str,
+    page_idx: usize,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    let response = send(
+        conf,
+        client,
+        client.get(url).bearer_auth(token).query(query).query(&[
+            ("per_page", conf.pagination.per_page.to_string()),
+            ("page", page_idx.to_string()),
+        ]),
+    )?;
+
+    check_success!(response, cmd);
+    parse_page(conf, response)
+}
+
+/// Parse a page of a listing. With --strict-schema each item is checked on its own, so
+/// that a field of an unexpected type is reported with the endpoint, the index of the
+/// item and the path of the field, and the fields unknown to the model are reported on
+/// stderr.
+fn parse_page<T: DeserializeOwned>(
+    conf: &Config,
+    response: reqwest::blocking::Response,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    if !conf.strict_schema {
+        return Ok(response.json()?);
+    }
+    let endpoint = response.url().path().to_string();
+    parse_page_strictly(&endpoint, &response.text()?)
+}
+
+/// Parse a page of a listing with --strict-schema, see parse_page.
+fn parse_page_strictly<T: DeserializeOwned>(
+    endpoint: &str,
+    text: &str,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    let (page, unknown_fields) = check_page(endpoint, text)?;
+    if !unknown_fields.is_empty() {
+        eprintln!(
+            "{}: fields unknown to the model: {}",
+            endpoint,
+            unknown_fields.join(", ")
+        );
+    }
+    Ok(page)
+}
+
+/// Parse the items of a page one by one, along with the paths of the fields unknown to
+/// the model.
+fn check_page<T: DeserializeOwned>(
+    endpoint: &str,
+    text: &str,
+) -> Result<(Vec<T>, Vec<String>), Box<dyn Error>> {
+    let items: Vec<serde_json::Value> = serde_json::from_str(text)
+        .map_err(|err| MenderError::new(format!("{}: not a JSON array: {}", endpoint, err)))?;
+    let mut unknown_fields: Vec<String> = vec![];
+    // Indexes in arrays are replaced by *, so that a field is reported once
+    let mut record_unknown = |path: serde_ignored::Path| {
+        let path = path
+            .to_string()
+            .split('.')
+            .map(|part| {
+                if part.chars().all(|c| c.is_ascii_digit()) {
+                    "*"
+                } else {
+                    part
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(".");
+        if !unknown_fields.contains(&path) {
+            unknown_fields.push(path);
+        }
+    };
+    let mut page = vec![];
+    for (idx, item) in items.iter().enumerate() {
+        let deserializer = serde_ignored::Deserializer::new(item, &mut record_unknown);
+        page.push(
+            serde_path_to_error::deserialize(deserializer).map_err(|err| {
+                MenderError::new(format!(
+                    "{} item {}: field {}: {}",
+                    endpoint,
+                    idx,
+                    err.path(),
+                    err.inner()
+                ))
+            })?,
+        );
+    }
+    Ok((page, unknown_fields))
+}
+
+/// Walk through the pages of a listing from the given page on, they are requested by
+/// a pool of max_concurrent_requests workers and handled in order. The listing ends at
+/// the first empty page or page shorter than page_size, the size of the previous pages,
+/// and the pages requested after it are dropped.
+#[allow(clippy::too_many_arguments)]
+fn paginate_concurrently<T: DeserializeOwned + Send>(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    url: &str,
+    query: &[(&str, &str)],
+    cmd: &str,
+    first_page: usize,
+    page_size: usize,
+    mut handle_page: impl FnMut(Vec<T>) -> bool,
+) -> Result<(), Box<dyn Error>> {
+    let next_page = AtomicUsize::new(first_page);
+    // Workers stop once the next page is after this one, it is lowered when a short
+    // page is received and set to 0 when the listing ends
+    let last_page = AtomicUsize::new(usize::MAX);
+    std::thread::scope(|scope| {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for _ in 0..conf.execution.max_concurrent_requests {
+            let sender = sender.clone();
+            let (next_page, last_page) = (&next_page, &last_page);
+            scope.spawn(move || loop {
+                let page_idx = next_page.fetch_add(1, Ordering::SeqCst);
+                if page_idx > last_page.load(Ordering::SeqCst) {
+                    return;
+                }
+                let page = get_page::<T>(conf, client, token, url, query, cmd, page_idx)
+                    .map_err(|err| err.to_string());
+                if !matches!(&page, Ok(items) if items.len() >= page_size) {
+                    last_page.fetch_min(page_idx, Ordering::SeqCst);
+                }
+                if sender.send((page_idx, page)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut received = HashMap::new();
+        let mut page_idx = first_page;
+        for (idx, page) in receiver {
+            received.insert(idx, page);
+            while let Some(page) = received.remove(&page_idx) {
+                let res = page.map_err(|err| {
+                    last_page.store(0, Ordering::SeqCst);
+                    MenderError::new(err)
+                })?;
+                let is_short = res.len() < page_size;
+                if res.is_empty() || !handle_page(res) || is_short {
+                    last_page.store(0, Ordering::SeqCst);
+                    return Ok(());
+                }
+                page_idx += 1;
+            }
+        }
+        Ok(())
+    })
+}
+
 /// Request an auth token from mender server, it should be called
 /// with a Login command otherwise an error is returned.
 pub fn get_token(conf: &Config, pass: &str) -> Result<String, Box<dyn Error>> {
-    if let Command::Login { email } = &conf.command {
-        let client = blocking_client(&conf.cert_file)?;
+    if let Command::Login { email, .. } = &conf.command {
+        let client = blocking_client(conf)?;
         let url_login = conf.server_url.clone() + LOGIN_API;
-        let get_token = client
-            .post(&url_login)
-            .basic_auth(&email, Some(pass))
-            .send()?;
+        let get_token = send(
+            conf,
+            &client,
+            client.post(&url_login).basic_auth(email, Some(pass)),
+        )?;
 
         check_success!(get_token, "login");
         Ok(get_token.text().unwrap())
@@ -83,6 +719,37 @@ struct DeployData<'a> {
     artifact_name: &'a str,
     name: &'a str,
     devices: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phases: Option<Vec<DeployPhase>>,
+}
+
+#[derive(Serialize)]
+struct DeployPhase {
+    start_ts: String,
+}
+
+/// Version of the deploy summary file format, to increase on incompatible changes.
+const DEPLOY_SUMMARY_VERSION: u32 = 1;
+
+/// Summary written by deploy --summary-file.
+#[derive(Serialize)]
+struct DeploySummary<'a> {
+    schema_version: u32,
+    tool_version: &'a str,
+    request: &'a Command,
+    device_count: usize,
+    deployment_id: Option<String>,
+    started: String,
+    finished: String,
+    statistics: Option<&'a HashMap<String, i32>>,
+}
+
+/// Write a file by renaming a temporary file, so it is either fully written or not changed.
+fn write_atomically(path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let tmp_path = format!("{}.tmp", path);
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
 }
 
 /// Deploy an update to a device group or a single device, return the number of devices affected.
@@ -95,80 +762,159 @@ pub fn deploy(conf: &Config) -> Result<usize, Box<dyn Error>> {
             device,
             artifact,
             name,
+            wait,
+            wait_timeout,
+            wait_interval,
+            thresholds,
+            allow_downgrade,
+            start_at,
+            summary_file,
+            artifact_checksum,
+            expected_count,
         },
         Some(token),
     ) = (&conf.command, &conf.token)
     {
-        if group.is_none() && device.is_none() {
-            return Err(Box::new(MenderError::new(String::from(
-                "A group or a device id must be provided for deployment",
-            ))));
-        }
-
-        let name = name.as_ref().unwrap_or_else(|| {
-            if group.is_some() {
-                group.as_ref().unwrap()
-            } else {
-                device.as_ref().unwrap()
+        let started = chrono::Utc::now();
+        let target = match (group, device) {
+            (Some(group), _) => format!("group {}", group),
+            (None, Some(devices)) if devices.len() == 1 => format!("device {}", devices[0]),
+            (None, Some(devices)) if !devices.is_empty() => {
+                format!("devices {}", devices.join(", "))
             }
-        });
-        println!(
-            "Posting deployment to {} {} using artifact {} and with name {}.",
-            if group.is_some() { "group" } else { "device" },
-            if let Some(group) = group {
-                &group
-            } else {
-                device.as_ref().unwrap()
-            },
+            _ => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "A group or a device id must be provided for deployment",
+                ))))
+            }
+        };
+
+        let default_name = match (group, device) {
+            (Some(group), _) => group.clone(),
+            (None, Some(devices)) => devices.join(","),
+            (None, None) => unreachable!(),
+        };
+        let name = name.as_ref().unwrap_or(&default_name);
+        progress!(
+            conf,
+            "Posting deployment to {} using artifact {} and with name {}.\n",
+            target,
             &artifact,
             &name
         );
 
-        let client = blocking_client(&conf.cert_file)?;
+        let client = blocking_client(conf)?;
 
-        // List devices in the group
-        let mut page = Some(1);
         let mut devices: Vec<String> = vec![];
         if let Some(group) = group {
-            while let Some(page_idx) = page {
-                let list_url = format!(
-                    "{}/api/management/v1/inventory/groups/{}/devices",
-                    conf.server_url, group
-                );
-                let list_devices = client
-                    .get(&list_url)
-                    .bearer_auth(token)
-                    .query(&[("per_page", "500"), ("page", &page_idx.to_string())])
-                    .send()?;
-
-                check_success!(list_devices, "deployment");
-                let mut res = list_devices.json::<Vec<String>>()?;
-                page = if res.len() == 0 {
-                    None
-                } else {
-                    Some(page_idx + 1)
-                };
-                devices.append(&mut res);
+            devices = list_group_devices(&client, conf, token, group)?;
+        } else if let Some(device) = device {
+            devices = device.clone();
+        }
+        if let Some(expected_count) = expected_count {
+            if !expected_count.contains(devices.len()) {
+                return Err(Box::new(UnexpectedCount {
+                    count: devices.len(),
+                    expected: expected_count.to_string(),
+                }));
             }
-        } else {
-            devices.push(device.as_ref().unwrap().to_string());
+            progress!(
+                conf,
+                "{} devices targeted, {} expected\n",
+                devices.len(),
+                expected_count
+            );
+        }
+
+        if let Some(artifact_checksum) = artifact_checksum {
+            check_artifact_checksum(&client, conf, token, artifact, artifact_checksum)?;
+        }
+        if !allow_downgrade {
+            check_downgrade(&client, conf, token, artifact, group, &devices)?;
+        }
+        check_deployment_limits(&client, conf, token, devices.len())?;
+        if conf.dry_run {
+            for device in &devices {
+                progress!(conf, "{}\n", device);
+            }
+            progress!(conf, "Dry run, the deployment is not posted.\n");
+            return Ok(devices.len());
         }
 
         // Post deployment
         let nb_devices = devices.len();
+        if let Some(start_at) = start_at {
+            progress!(
+                conf,
+                "Deployment will start at {} local time ({})\n",
+                start_at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S %:z"),
+                start_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            );
+        }
         let deploy_data = DeployData {
             artifact_name: artifact,
             name,
             devices,
+            phases: start_at.map(|start_at| {
+                vec![DeployPhase {
+                    start_ts: start_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                }]
+            }),
         };
         let url_deploy = conf.server_url.clone() + DEPLOY_API;
-        let post_deploy = client
-            .post(&url_deploy)
-            .bearer_auth(token)
-            .json(&deploy_data)
-            .send()?;
+        let post_deploy = send(
+            conf,
+            &client,
+            client
+                .post(&url_deploy)
+                .bearer_auth(token)
+                .json(&deploy_data),
+        )?;
 
         check_success!(post_deploy, "deployment");
+        let id = if *wait || summary_file.is_some() {
+            let id = deployment_id(&client, conf, token, &post_deploy, name)?;
+            if id.is_none() {
+                // Waiting is how the failure thresholds are checked, don't let them pass
+                if *wait {
+                    return Err(Box::new(MenderError::new(String::from(
+                        "Deployment created but its id is unknown, can't wait for it nor check \
+                         its failure thresholds",
+                    ))));
+                }
+                progress!(conf, "Deployment created but its id is unknown\n");
+            }
+            id
+        } else {
+            None
+        };
+        let mut statistics = None;
+        if let (true, Some(id)) = (*wait, &id) {
+            progress!(conf, "Waiting for deployment {} to finish\n", id);
+            wait_deployment(&client, conf, token, id, *wait_timeout, *wait_interval)?;
+            let deployment_statistics = deployment_statistics(&client, conf, token, id)?;
+            progress!(conf, "{}", display_ordered(deployment_statistics.clone()));
+            statistics = Some(deployment_statistics);
+        }
+        if let Some(summary_file) = summary_file {
+            let summary = DeploySummary {
+                schema_version: DEPLOY_SUMMARY_VERSION,
+                tool_version: env!("CARGO_PKG_VERSION"),
+                request: &conf.command,
+                device_count: nb_devices,
+                deployment_id: id,
+                started: started.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                finished: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                statistics: statistics.as_ref(),
+            };
+            write_atomically(summary_file, &serde_json::to_vec_pretty(&summary)?)?;
+            progress!(conf, "Deployment summary written to {}\n", summary_file);
+        }
+        if let Some(statistics) = statistics {
+            progress!(conf, "{}\n", check_thresholds(&statistics, thresholds)?);
+        }
         Ok(nb_devices)
     } else {
         Err(Box::new(MenderError::new(String::from(
@@ -177,190 +923,7237 @@ pub fn deploy(conf: &Config) -> Result<usize, Box<dyn Error>> {
     }
 }
 
-#[derive(Deserialize, Debug)]
-struct MenderId {
-    id: String,
-}
+/// Check that the artifact is not older than the one run by most of the devices,
+/// confirmation is asked if it is. Devices are taken from the group inventory
+/// when deploying to a group. Artifact names without a version are not checked.
+fn check_downgrade(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    artifact: &str,
+    group: &Option<String>,
+    devices: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let version = match Version::parse(artifact) {
+        Some(version) => version,
+        None => return Ok(()),
+    };
+    let current_artifacts: Vec<String> = if let Some(group) = group {
+        progress!(conf, "Checking artifacts of group devices");
+        let group_devices = inventory_devices(client, conf, token, &[("group", group)])?;
+        progress!(conf, "\n");
+        group_devices
+            .iter()
+            .map(|device| device.artifact_name())
+            .collect()
+    } else {
+        let mut current_artifacts = vec![];
+        for id in devices {
+            current_artifacts.push(device_inventory(client, conf, token, id)?.artifact_name());
+        }
+        current_artifacts
+    };
 
-#[derive(Deserialize, Debug)]
-struct MenderIdentity {
-    id: String,
-    identity_data: MenderSn,
+    let current_versions: Vec<Version> = current_artifacts
+        .iter()
+        .filter_map(|name| Version::parse(name))
+        .collect();
+    let nb_newer = current_versions
+        .iter()
+        .filter(|current| **current > version)
+        .count();
+    if nb_newer * 2 > current_versions.len() {
+        let impact = format!(
+            "{} of {} devices run a newer version than {}",
+            nb_newer,
+            current_versions.len(),
+            artifact
+        );
+        match confirm("Downgrade", &impact, conf)? {
+            Decision::Proceed | Decision::DryRun => (),
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Deployment aborted, use --allow-downgrade to deploy an older artifact",
+                ))))
+            }
+        }
+    }
+    Ok(())
 }
 
+/// Deployment limits enforced by the server, only some servers expose them.
 #[derive(Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct MenderSn {
-    SerialNumber: String,
+struct DeploymentLimits {
+    max_devices_per_deployment: Option<usize>,
+    max_active_deployments: Option<usize>,
+    max_artifact_size: Option<u64>,
 }
 
-/// Get mender id of a device based on its SerialNumber attribute.
-/// The command must be getid and a token must be provided.
-pub fn get_id(conf: &Config) -> Result<String, Box<dyn Error>> {
-    if let (Command::GetId { serial_number }, Some(token)) = (&conf.command, &conf.token) {
-        println!("Searching for device with SerialNumber {}", &serial_number);
-
-        let client = blocking_client(&conf.cert_file)?;
-        let get_device_inventory = client
-            .get(&format!(
-                "{}{}",
-                &conf.server_url, GET_DEVICES_INVENTORY_API
-            ))
-            .bearer_auth(token)
-            .query(&[("SerialNumber", serial_number)])
-            .send()?;
+/// Get the deployment limits of the server, None if it doesn't expose them.
+fn deployment_limits(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+) -> Result<Option<DeploymentLimits>, Box<dyn Error>> {
+    let get_limits = send(
+        conf,
+        client,
+        client
+            .get(&format!("{}{}", &conf.server_url, DEPLOYMENT_LIMITS_API))
+            .bearer_auth(token),
+    )?;
 
-        check_success!(get_device_inventory, "searching device");
-        let mut res = get_device_inventory.json::<Vec<MenderId>>()?;
-        if let Some(mender_id) = res.pop() {
-            Ok(mender_id.id)
-        } else {
-            println!("SerialNumber not found in attributes, searching in identity data.");
-
-            let mut page = Some(1);
-            while let Some(page_idx) = page {
-                print!(".");
-                std::io::stdout().flush().unwrap();
-                let get_devices_auth = client
-                    .get(&format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API))
-                    .bearer_auth(token)
-                    .query(&[
-                        ("per_page", "500"),
-                        ("page", &page_idx.to_string()),
-                        ("status", "accepted"),
-                    ])
-                    .send()?;
-
-                check_success!(get_devices_auth, "device search");
-                let res = get_devices_auth.json::<Vec<MenderIdentity>>()?;
-                let nb_results = res.len();
-                if let Some(mender_identity) = res
-                    .into_iter()
-                    .filter(|mender_identity| {
-                        mender_identity.identity_data.SerialNumber.as_str() == serial_number
-                    })
-                    .next()
-                {
-                    println!("");
-                    return Ok(mender_identity.id);
-                } else {
-                    page = if nb_results == 0 {
-                        None
-                    } else {
-                        Some(page_idx + 1)
-                    };
-                }
-            }
-            println!("");
-            Err(Box::new(MenderError::new(String::from(
-                "SerialNumber not found",
-            ))))
+    match get_limits.status() {
+        reqwest::StatusCode::NOT_FOUND
+        | reqwest::StatusCode::METHOD_NOT_ALLOWED
+        | reqwest::StatusCode::NOT_IMPLEMENTED => Ok(None),
+        _ => {
+            check_success!(get_limits, "deployment limits");
+            Ok(Some(get_limits.json()?))
         }
-    } else {
-        Err(Box::new(MenderError::new(String::from(
-            "Command must be getid and token must be provided in get_id call",
-        ))))
     }
 }
 
-/// Get info of a device
-pub fn get_info(conf: &Config) -> Result<String, Box<dyn Error>> {
-    if let (Command::GetInfo { id }, Some(token)) = (&conf.command, &conf.token) {
-        let client = blocking_client(&conf.cert_file)?;
-        let get_device_inventory = client
-            .get(&format!(
-                "{}{}/{}",
-                &conf.server_url, GET_DEVICES_INVENTORY_API, id
-            ))
-            .bearer_auth(token)
-            .send()?;
-
-        check_success!(get_device_inventory, "get info");
-        let json: serde_json::Value = get_device_inventory.json()?;
-        Ok(serde_json::to_string_pretty(&json)?)
-    } else {
-        Err(Box::new(MenderError::new(String::from(
-            "Command must be getinfo and token must be provided in get_info call",
-        ))))
+/// Check a planned deployment against the server limits before posting it, so that it
+/// fails early with an explicit message. Too many active deployments is only a warning
+/// as some of them may finish in the meantime.
+fn check_deployment_limits(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    nb_devices: usize,
+) -> Result<(), Box<dyn Error>> {
+    let limits = match deployment_limits(client, conf, token)? {
+        Some(limits) => limits,
+        None => return Ok(()),
+    };
+    let warning = plan_deployment(&limits, nb_devices, || {
+        Ok(
+            list_deployments(client, conf, token, Some("inprogress"))?.len()
+                + list_deployments(client, conf, token, Some("pending"))?.len(),
+        )
+    })?;
+    if let Some(warning) = warning {
+        progress!(conf, "Warning: {}\n", warning);
     }
+    Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-struct MenderAttribute {
-    name: String,
-    value: String,
+/// Compare a deployment to nb_devices with the limits, an error is returned if it is
+/// above them and a warning if it may be rejected. The active deployments are only
+/// counted when their number is limited.
+fn plan_deployment(
+    limits: &DeploymentLimits,
+    nb_devices: usize,
+    active_deployments: impl FnOnce() -> Result<usize, Box<dyn Error>>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if let Some(max_devices) = limits.max_devices_per_deployment {
+        if nb_devices > max_devices {
+            return Err(Box::new(MenderError::new(format!(
+                "Deployment to {} devices is above the server limit of {} devices per deployment",
+                nb_devices, max_devices
+            ))));
+        }
+    }
+    if let Some(max_active) = limits.max_active_deployments {
+        let active = active_deployments()?;
+        if active >= max_active {
+            return Ok(Some(format!(
+                "{} deployments are already active and the server allows {}, the deployment may be rejected",
+                active, max_active
+            )));
+        }
+    }
+    Ok(None)
 }
 
+/// Artifact storage of the tenant in bytes, a limit of 0 means unlimited.
 #[derive(Deserialize, Debug)]
-struct MenderDevice {
-    id: String,
-    attributes: Option<Vec<MenderAttribute>>,
+struct StorageLimits {
+    limit: u64,
+    usage: u64,
 }
 
-impl MenderDevice {
-    fn artifact_name(&self) -> String {
-        if let Some(attributes) = &self.attributes {
-            for attribute in attributes {
-                if attribute.name == "artifact_name" {
-                    return attribute.value.clone();
-                }
-            }
+impl StorageLimits {
+    fn remaining(&self) -> Option<u64> {
+        if self.limit == 0 {
+            None
+        } else {
+            Some(self.limit.saturating_sub(self.usage))
         }
-        return String::new();
     }
 }
 
-/// Return the list of artifacts with a count of how much devices are using it.
-pub fn count_artifacts(conf: &Config) -> Result<String, Box<dyn Error>> {
-    if let (Command::CountArtifacts, Some(token)) = (&conf.command, &conf.token) {
-        print!("Inventoring artifact used by devices");
-        let client = blocking_client(&conf.cert_file)?;
-        let mut artifacts_count = HashMap::new();
-        let mut page = Some(1);
-        while let Some(page_idx) = page {
-            print!(".");
-            std::io::stdout().flush().unwrap();
-            let get_devices_inv = client
-                .get(&format!(
-                    "{}{}",
-                    &conf.server_url, GET_DEVICES_INVENTORY_API
-                ))
-                .bearer_auth(token)
-                .query(&[("per_page", "500"), ("page", &page_idx.to_string())])
-                .send()?;
+/// Get the artifact storage limits of the tenant, None if the server doesn't expose them.
+fn storage_limits(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+) -> Result<Option<StorageLimits>, Box<dyn Error>> {
+    let get_limits = send(
+        conf,
+        client,
+        client
+            .get(&format!("{}{}", &conf.server_url, STORAGE_LIMITS_API))
+            .bearer_auth(token),
+    )?;
 
-            check_success!(get_devices_inv, "artifacts counting");
-            let res = get_devices_inv.json::<Vec<MenderDevice>>()?;
-            let nb_devices = res.len();
-            let artifacts: Vec<String> = res
-                .into_iter()
-                .map(|device| device.artifact_name())
-                .collect();
-            for artifact in artifacts {
-                let count = artifacts_count.entry(artifact).or_insert(0);
-                *count += 1;
-            }
-            page = if nb_devices > 0 {
-                Some(page_idx + 1)
-            } else {
-                None
-            };
+    match get_limits.status() {
+        reqwest::StatusCode::NOT_FOUND
+        | reqwest::StatusCode::METHOD_NOT_ALLOWED
+        | reqwest::StatusCode::NOT_IMPLEMENTED => Ok(None),
+        _ => {
+            check_success!(get_limits, "storage limits");
+            Ok(Some(get_limits.json()?))
         }
-        println!("");
-        Ok(display_ordered(artifacts_count))
-    } else {
-        Err(Box::new(MenderError::new(String::from(
-            "Command must be countartifacts and token must be provided in count_artifacts call",
+    }
+}
+
+/// Check the size of an artifact against the maximum artifact size and the storage left,
+/// each of them is only checked when known.
+fn check_artifact_size(
+    size: u64,
+    max_artifact_size: Option<u64>,
+    remaining_storage: Option<u64>,
+) -> Result<(), String> {
+    if let Some(max_artifact_size) = max_artifact_size {
+        if size > max_artifact_size {
+            return Err(format!(
+                "Artifact of {} bytes is above the server limit of {} bytes per artifact",
+                size, max_artifact_size
+            ));
+        }
+    }
+    if let Some(remaining_storage) = remaining_storage {
+        if size > remaining_storage {
+            return Err(format!(
+                "Artifact of {} bytes is above the {} bytes of storage left",
+                size, remaining_storage
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// How far back deployments are searched when the id of a new deployment is not returned.
+const RECENT_DEPLOYMENT_MINUTES: i64 = 5;
+
+/// Get the id of a newly created deployment from the Location header of the response.
+/// Some proxies strip this header, the recent deployments are then searched by name.
+/// None is returned if the deployment can't be found.
+fn deployment_id(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    response: &reqwest::blocking::Response,
+    name: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if let Some(id) = location_id(response.headers()) {
+        return Ok(Some(id));
+    }
+
+    progress!(
+        conf,
+        "Deployment id not returned by the server, searching it by name\n"
+    );
+    let created_after = chrono::Utc::now() - chrono::Duration::minutes(RECENT_DEPLOYMENT_MINUTES);
+    let get_deployments = send(
+        conf,
+        client,
+        client
+            .get(&format!("{}{}", &conf.server_url, DEPLOY_API))
+            .bearer_auth(token)
+            .query(&[
+                ("search", name),
+                ("created_after", &created_after.timestamp().to_string()),
+            ]),
+    )?;
+
+    check_success!(get_deployments, "deployment search");
+    Ok(get_deployments
+        .json::<Vec<MenderDeployment>>()?
+        .into_iter()
+        .find(|deployment| deployment.name == name)
+        .map(|deployment| deployment.id))
+}
+
+/// Id at the end of the Location header of a response, if any.
+fn location_id(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    headers
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .and_then(|location| location.rsplit('/').next())
+        .filter(|id| !id.is_empty())
+        .map(|id| id.to_string())
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderDeploymentStatus {
+    status: String,
+}
+
+/// Poll a deployment every interval until its status is finished, an error is
+/// returned if it takes longer than the timeout.
+fn wait_deployment(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+    timeout: std::time::Duration,
+    interval: std::time::Duration,
+) -> Result<(), Box<dyn Error>> {
+    let start = std::time::Instant::now();
+    loop {
+        let get_deployment = send(
+            conf,
+            client,
+            client
+                .get(&format!("{}{}/{}", &conf.server_url, DEPLOY_API, id))
+                .bearer_auth(token),
+        )?;
+
+        check_success!(get_deployment, "waiting deployment");
+        if DeploymentStatus::parse(&get_deployment.json::<MenderDeploymentStatus>()?.status)
+            .is_finished()
+        {
+            progress!(conf, "\n");
+            return Ok(());
+        }
+        if start.elapsed() + interval > timeout {
+            progress!(conf, "\n");
+            return Err(Box::new(MenderError::new(format!(
+                "timeout waiting for deployment {} to finish",
+                id
+            ))));
+        }
+        progress!(conf, ".");
+        flush_progress!(conf);
+        std::thread::sleep(interval);
+    }
+}
+
+/// Get the number of devices in each state of a deployment.
+fn deployment_statistics(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+) -> Result<HashMap<String, i32>, Box<dyn Error>> {
+    let get_statistics = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}/{}/statistics",
+                &conf.server_url, DEPLOY_API, id
+            ))
+            .bearer_auth(token),
+    )?;
+
+    check_success!(get_statistics, "deployment statistics");
+    Ok(normalize_statistics(get_statistics.json()?))
+}
+
+/// Poll the statistics of a deployment until no device is pending or updating, a
+/// summary of the states is printed on each poll. Return the final statistics, an
+/// error is returned on timeout.
+pub fn deployment_status(conf: &Config) -> Result<HashMap<String, i32>, Box<dyn Error>> {
+    if let (
+        Command::DeploymentStatus {
+            deployment_id,
+            poll_interval,
+            timeout,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let start = std::time::Instant::now();
+        loop {
+            let statistics = deployment_statistics(&client, conf, token, deployment_id)?;
+            let count = |state: &str| *statistics.get(state).unwrap_or(&0);
+            let in_progress: i32 = statistics
+                .iter()
+                .filter(|(state, &nb)| {
+                    let state = DeviceStatus::parse(state);
+                    nb > 0 && state != DeviceStatus::Pending && !state.is_terminal()
+                })
+                .map(|(_, nb)| nb)
+                .sum();
+            progress!(
+                conf,
+                "{} success {} failure {} inprogress {} pending {}\n",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                count("success"),
+                count("failure"),
+                in_progress,
+                count("pending")
+            );
+            if in_progress == 0 && count("pending") == 0 {
+                return Ok(statistics);
+            }
+            if start.elapsed() + *poll_interval > *timeout {
+                return Err(Box::new(MenderError::new(format!(
+                    "timeout waiting for deployment {} to complete",
+                    deployment_id
+                ))));
+            }
+            std::thread::sleep(*poll_interval);
+        }
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be deploymentstatus and token must be provided in deployment_status call",
         ))))
     }
 }
 
-fn display_ordered(map: HashMap<String, i32>) -> String {
-    let mut vec: Vec<(&String, &i32)> = map.iter().collect();
-    vec.sort_by(|a, b| b.1.cmp(a.1));
-    let mut disp = String::new();
-    for (key, value) in vec {
-        disp.push_str(&format!("{}: {}\n", key, value));
+/// Return the failure rate of a deployment, over the devices which attempted the
+/// update, or an error if it exceeds one of the thresholds.
+fn check_thresholds(
+    statistics: &HashMap<String, i32>,
+    thresholds: &FailureThresholds,
+) -> Result<String, Box<dyn Error>> {
+    let count = |state: &str| *statistics.get(state).unwrap_or(&0) as u64;
+    let failures = count("failure");
+    let attempted = statistics.values().map(|&nb| nb as u64).sum::<u64>()
+        - count("already-installed")
+        - count("noartifact");
+    let failure_rate = if attempted == 0 {
+        0.0
+    } else {
+        failures as f64 * 100.0 / attempted as f64
+    };
+    let summary = format!(
+        "Failure rate: {:.2}% ({} failed out of {} updated devices)",
+        failure_rate, failures, attempted
+    );
+
+    if let Some(max_failure_rate) = thresholds.max_failure_rate {
+        if failure_rate > max_failure_rate {
+            return Err(Box::new(MenderError::new(format!(
+                "{}, above the maximum of {}%",
+                summary, max_failure_rate
+            ))));
+        }
+    }
+    if let Some(max_failures) = thresholds.max_failures {
+        if failures > max_failures {
+            return Err(Box::new(MenderError::new(format!(
+                "{}, above the maximum of {} failures",
+                summary, max_failures
+            ))));
+        }
+    }
+    Ok(summary)
+}
+
+/// Print the statistics of a deployment and return its failure rate, an error is
+/// returned if the failures are above the given thresholds.
+pub fn stats(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::Stats { id, thresholds }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let statistics = deployment_statistics(&client, conf, token, id)?;
+        progress!(conf, "{}", display_ordered(statistics.clone()));
+        check_thresholds(&statistics, thresholds)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be stats and token must be provided in stats call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderIdentity {
+    id: String,
+    status: Option<String>,
+    created_ts: Option<String>,
+    #[serde(default)]
+    identity_data: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    auth_sets: Vec<MenderAuthSet>,
+}
+
+impl MenderIdentity {
+    /// Value of an identity attribute, non string values are returned as their JSON
+    /// representation.
+    fn identity_value(&self, name: &str) -> Option<String> {
+        self.identity_data.get(name).map(|value| match value {
+            serde_json::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        })
+    }
+
+    fn is_accepted(&self) -> bool {
+        self.status.as_deref() == Some("accepted")
+    }
+}
+
+/// Cache entry kind of the identity index.
+const IDENTITY_INDEX: &str = "identityindex";
+
+/// Age after which the identity index is rebuilt when it doesn't know a value, a
+/// younger index is only refreshed.
+const IDENTITY_INDEX_MAX_AGE_HOURS: i64 = 24;
+
+/// Index of the identity data of accepted devices kept by getid --index, per server.
+#[derive(Deserialize, Serialize, Default, Debug)]
+struct IdentityIndex {
+    /// Time the index was built from all accepted devices.
+    #[serde(default)]
+    built_ts: Option<String>,
+    /// Creation time of the most recently created device indexed, the devices created
+    /// after it are added on the next run.
+    high_water_ts: Option<String>,
+    /// Ids of the devices by identity attribute, keyed by `name=value`.
+    ids: HashMap<String, Vec<String>>,
+}
+
+impl IdentityIndex {
+    fn add(&mut self, identity: &MenderIdentity) {
+        for name in identity.identity_data.keys() {
+            let value = identity.identity_value(name).unwrap_or_default();
+            let ids = self.ids.entry(format!("{}={}", name, value)).or_default();
+            if !ids.contains(&identity.id) {
+                ids.push(identity.id.clone());
+            }
+        }
+        if let Some(created_ts) = &identity.created_ts {
+            if is_at_or_after(created_ts, self.high_water_ts.as_deref()) {
+                self.high_water_ts = Some(created_ts.clone());
+            }
+        }
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.ids
+            .values()
+            .any(|ids| ids.iter().any(|indexed| indexed == id))
+    }
+
+    /// Whether the index was built more than IDENTITY_INDEX_MAX_AGE_HOURS ago, or at an
+    /// unknown time.
+    fn is_stale(&self) -> bool {
+        self.built_ts
+            .as_ref()
+            .and_then(|built_ts| chrono::DateTime::parse_from_rfc3339(built_ts).ok())
+            .is_none_or(|built_ts| {
+                chrono::Utc::now().signed_duration_since(built_ts)
+                    > chrono::Duration::hours(IDENTITY_INDEX_MAX_AGE_HOURS)
+            })
+    }
+
+    fn remove(&mut self, id: &str) {
+        for ids in self.ids.values_mut() {
+            ids.retain(|indexed| indexed != id);
+        }
+        self.ids.retain(|_, ids| !ids.is_empty());
+    }
+
+    /// Ids of the devices whose identity attribute matches a value.
+    fn lookup(&self, attribute: &str, value: &str, matching: &SerialNumberMatching) -> Vec<String> {
+        let prefix = format!("{}=", attribute);
+        let mut found: Vec<String> = vec![];
+        for (key, ids) in &self.ids {
+            let matches = key
+                .strip_prefix(&prefix)
+                .is_some_and(|indexed| matching.matches(value, indexed));
+            if matches {
+                for id in ids {
+                    if !found.contains(id) {
+                        found.push(id.clone());
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Whether a RFC3339 time is at or after another one, a time that can't be parsed never
+/// is and any time is after no time.
+fn is_at_or_after(time: &str, than: Option<&str>) -> bool {
+    let time = match chrono::DateTime::parse_from_rfc3339(time) {
+        Ok(time) => time,
+        Err(_) => return false,
+    };
+    match than.and_then(|than| chrono::DateTime::parse_from_rfc3339(than).ok()) {
+        Some(than) => time >= than,
+        None => true,
+    }
+}
+
+/// Get mender id of a device based on one of its attributes, SerialNumber by default.
+/// The command must be getid and a token must be provided.
+pub fn get_id(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::GetId {
+            value, attribute, ..
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        find_device_id(conf, &client, token, attribute, value)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getid and token must be provided in get_id call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderFilterAttribute {
+    name: String,
+}
+
+/// Whether any device reported an inventory attribute. The attributes known to the
+/// filters API are used when the server has it, otherwise the devices are listed.
+fn attribute_reported(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    attribute: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let get_attributes = send(
+        conf,
+        client,
+        client
+            .get(&format!("{}{}/attributes", &conf.server_url, FILTERS_API))
+            .bearer_auth(token),
+    )?;
+    if get_attributes.status().is_success() {
+        return Ok(get_attributes
+            .json::<Vec<MenderFilterAttribute>>()?
+            .iter()
+            .any(|known| known.name == attribute));
+    }
+    Ok(inventory_devices(client, conf, token, &[])?
+        .iter()
+        .any(|device| device.attribute(attribute).is_some()))
+}
+
+/// Find all the devices whose inventory attribute has a value, return their id with the
+/// value of the attribute. The error tells whether no device has the value or the
+/// attribute was never reported.
+pub fn find_ids(conf: &Config) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    if let (Command::FindId { attribute, value }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        progress!(conf, "Searching devices with {} {}", attribute, value);
+        let found: Vec<(String, String)> =
+            inventory_devices(&client, conf, token, &[(attribute, value)])?
+                .into_iter()
+                .filter_map(|device| {
+                    device
+                        .attribute(attribute)
+                        .filter(|found| conf.serial_number_matching.matches(value, found))
+                        .map(|found| (device.id, found))
+                })
+                .collect();
+        progress!(conf, "\n");
+        if !found.is_empty() {
+            return Ok(found);
+        }
+        if attribute_reported(&client, conf, token, attribute)? {
+            Err(Box::new(MenderError::new(format!(
+                "No device has {} {}",
+                attribute, value
+            ))))
+        } else {
+            Err(Box::new(MenderError::new(format!(
+                "Attribute {} was never reported by any device",
+                attribute
+            ))))
+        }
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be findid and token must be provided in find_ids call",
+        ))))
+    }
+}
+
+/// Search the mender id of a device in inventory attributes based on the value of an
+/// attribute, if not found the identity data of accepted devices are searched, in the
+/// identity index with getid --index. All the matching devices are collected, see
+/// pick_device when there are several.
+fn find_device_id(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    attribute: &str,
+    value: &str,
+) -> Result<String, Box<dyn Error>> {
+    progress!(conf, "Searching for device with {} {}\n", attribute, value);
+
+    let matching = &conf.serial_number_matching;
+    let get_device_inventory = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}",
+                &conf.server_url, GET_DEVICES_INVENTORY_API
+            ))
+            .bearer_auth(token)
+            .query(&[(attribute, value)]),
+    )?;
+
+    check_success!(get_device_inventory, "searching device");
+    let mut res: Vec<MenderDevice> = get_device_inventory.json()?;
+    res.retain(|device| {
+        device
+            .attribute(attribute)
+            .is_some_and(|found| matching.matches(value, &found))
+    });
+    if res.is_empty() && matching.normalizes() {
+        progress!(
+            conf,
+            "{} not found as is, comparing it with the {} of all devices.\n",
+            attribute,
+            attribute
+        );
+        res = inventory_devices(client, conf, token, &[])?;
+        res.retain(|device| {
+            device
+                .attribute(attribute)
+                .is_some_and(|found| matching.matches(value, &found))
+        });
+    }
+    let matches = if !res.is_empty() {
+        inventory_matches(attribute, res)
+    } else {
+        progress!(
+            conf,
+            "{} not found in attributes, searching in identity data.\n",
+            attribute
+        );
+        let ids = match &conf.command {
+            Command::GetId {
+                index: true,
+                rebuild_index,
+                ..
+            } => search_identity_index(conf, client, token, attribute, value, *rebuild_index)?,
+            _ => search_identity_data(conf, client, token, attribute, value)?,
+        };
+        ids.into_iter()
+            .map(|id| (id, format!("{} {}", attribute, value)))
+            .collect()
+    };
+    pick_device(conf, client, token, attribute, value, matches)
+}
+
+/// Ids of the devices found in inventory with the details to show for each one.
+fn inventory_matches(attribute: &str, devices: Vec<MenderDevice>) -> Vec<(String, String)> {
+    devices
+        .into_iter()
+        .map(|device| {
+            let details = format!(
+                "{} {} group {} last update {}",
+                attribute,
+                device.attribute(attribute).unwrap_or_default(),
+                device
+                    .attribute("group")
+                    .unwrap_or_else(|| String::from("<none>")),
+                device.updated_ts.as_deref().unwrap_or("<unknown>")
+            );
+            (device.id, details)
+        })
+        .collect()
+}
+
+/// Pick the device to use among the ones matching an attribute value, with the details
+/// to show for each one. When several devices match, the user chooses if possible,
+/// otherwise getid --first takes the first one and an error listing them with their
+/// authentication status is returned in any other case.
+fn pick_device(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    attribute: &str,
+    value: &str,
+    mut matches: Vec<(String, String)>,
+) -> Result<String, Box<dyn Error>> {
+    if matches.len() <= 1 {
+        return matches.pop().map(|(id, _)| id).ok_or_else(|| {
+            Box::new(MenderError::new(format!("{} not found", attribute))) as Box<dyn Error>
+        });
+    }
+    let mut statuses = vec![];
+    for (id, _) in &matches {
+        statuses.push(get_identity(conf, client, token, id)?);
+    }
+    choose_device(conf, attribute, value, matches, statuses)
+}
+
+/// Choose among several matching devices, given with their identity, see pick_device.
+fn choose_device(
+    conf: &Config,
+    attribute: &str,
+    value: &str,
+    mut matches: Vec<(String, String)>,
+    identities: Vec<Option<MenderIdentity>>,
+) -> Result<String, Box<dyn Error>> {
+    let devices: Vec<String> = matches
+        .iter()
+        .zip(identities)
+        .map(|((id, details), identity)| {
+            let status = match identity {
+                Some(identity) => identity.status.unwrap_or_else(|| String::from("<unknown>")),
+                None => String::from("<none>"),
+            };
+            format!("{} {} auth status {}", id, details, status)
+        })
+        .collect();
+    let prompt = format!("Devices with {} {}", attribute, value);
+    if let Some(choice) = choose(&prompt, &devices, conf)? {
+        return Ok(matches.swap_remove(choice).0);
+    }
+    match conf.command {
+        Command::GetId { first: true, .. } => {
+            progress!(
+                conf,
+                "{} devices match, using the first one:\n{}\n",
+                matches.len(),
+                devices.join("\n")
+            );
+            Ok(matches.swap_remove(0).0)
+        }
+        Command::GetId { .. } => Err(Box::new(MenderError::new(format!(
+            "{} devices with {} {}, use --first to take the first one:\n{}",
+            matches.len(),
+            attribute,
+            value,
+            devices.join("\n")
+        )))),
+        _ => Err(Box::new(MenderError::new(format!(
+            "{} devices with {} {}:\n{}",
+            matches.len(),
+            attribute,
+            value,
+            devices.join("\n")
+        )))),
+    }
+}
+
+/// Search the identity data of all accepted devices page by page, return the ids of the
+/// devices having the value of the attribute.
+fn search_identity_data(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    attribute: &str,
+    value: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let matching = &conf.serial_number_matching;
+    let mut found = vec![];
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API),
+        &[("status", "accepted")],
+        "device search",
+        |res: Vec<MenderIdentity>| {
+            progress!(conf, ".");
+            flush_progress!(conf);
+            found.extend(
+                res.into_iter()
+                    .filter(|mender_identity| {
+                        mender_identity
+                            .identity_value(attribute)
+                            .is_some_and(|found| matching.matches(value, &found))
+                    })
+                    .map(|mender_identity| mender_identity.id),
+            );
+            true
+        },
+    )?;
+    progress!(conf, "\n");
+    Ok(found)
+}
+
+/// Search the identity data in the index of the server, refreshed with the devices
+/// created since the last run. The devices found are checked on the server in case the
+/// index is outdated. The index is rebuilt from all accepted devices when it doesn't
+/// exist or can't be refreshed, or when it doesn't know the value and is older than
+/// IDENTITY_INDEX_MAX_AGE_HOURS. Return the ids of the devices having the value.
+fn search_identity_index(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    attribute: &str,
+    value: &str,
+    rebuild: bool,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut index = if rebuild {
+        None
+    } else {
+        cache::load::<IdentityIndex>(conf.cache_dir.as_deref(), IDENTITY_INDEX, &conf.server_url)
+    };
+    if let Some(cached) = &mut index {
+        progress!(conf, "Refreshing the identity index\n");
+        if !refresh_identity_index(conf, client, token, cached)? {
+            progress!(
+                conf,
+                "Creation time of the devices unknown, rebuilding the identity index.\n"
+            );
+            index = None;
+        }
+    }
+    let mut ids = vec![];
+    if let Some(cached) = &mut index {
+        ids = verified_ids(conf, client, token, cached, attribute, value)?;
+        if ids.is_empty() && cached.is_stale() {
+            progress!(
+                conf,
+                "{} not found in the identity index built more than {} hours ago, rebuilding it.\n",
+                attribute,
+                IDENTITY_INDEX_MAX_AGE_HOURS
+            );
+            index = None;
+        }
+    }
+    let index = match index {
+        Some(index) => index,
+        None => {
+            let index = build_identity_index(conf, client, token)?;
+            ids = index.lookup(attribute, value, &conf.serial_number_matching);
+            index
+        }
+    };
+    cache::save(
+        conf.cache_dir.as_deref(),
+        IDENTITY_INDEX,
+        &conf.server_url,
+        &index,
+    )?;
+    Ok(ids)
+}
+
+/// Index the identity data of all accepted devices.
+fn build_identity_index(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+) -> Result<IdentityIndex, Box<dyn Error>> {
+    progress!(conf, "Indexing identity data of accepted devices");
+    let mut index = IdentityIndex {
+        built_ts: Some(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+        ..IdentityIndex::default()
+    };
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API),
+        &[("status", "accepted")],
+        "identity indexing",
+        |res: Vec<MenderIdentity>| {
+            progress!(conf, ".");
+            flush_progress!(conf);
+            for identity in &res {
+                index.add(identity);
+            }
+            true
+        },
+    )?;
+    progress!(conf, "\n");
+    Ok(index)
+}
+
+/// Add to the index the accepted devices created at or after its high-water time, listed
+/// from the inventory sorted by creation time. Return false if the creation times are
+/// unknown, the index must then be rebuilt.
+fn refresh_identity_index(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    index: &mut IdentityIndex,
+) -> Result<bool, Box<dyn Error>> {
+    let high_water_ts = match &index.high_water_ts {
+        Some(high_water_ts) => high_water_ts.clone(),
+        None => return Ok(false),
+    };
+    let mut new_ids = vec![];
+    let mut known_creation = true;
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}", &conf.server_url, GET_DEVICES_INVENTORY_API),
+        &[("sort", "created_ts:desc")],
+        "identity index refresh",
+        |res: Vec<MenderDevice>| {
+            for device in res {
+                match device.attribute("created_ts") {
+                    Some(created_ts) if is_at_or_after(&created_ts, Some(&high_water_ts)) => {
+                        // The devices created in the same second as the high-water one
+                        // may already be indexed
+                        if !index.contains(&device.id) {
+                            new_ids.push(device.id)
+                        }
+                    }
+                    Some(_) => return false,
+                    None => {
+                        known_creation = false;
+                        return false;
+                    }
+                }
+            }
+            true
+        },
+    )?;
+    if !known_creation {
+        return Ok(false);
+    }
+    for id in new_ids {
+        if let Some(identity) = get_identity(conf, client, token, &id)? {
+            if identity.is_accepted() {
+                index.add(&identity);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Ids of the indexed devices matching an identity attribute value which still have it
+/// on the server.
+/// The devices which changed are updated in the index, or removed if they were
+/// decommissioned or aren't accepted anymore.
+fn verified_ids(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    index: &mut IdentityIndex,
+    attribute: &str,
+    value: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let matching = &conf.serial_number_matching;
+    let mut verified = vec![];
+    for id in index.lookup(attribute, value, matching) {
+        let identity =
+            get_identity(conf, client, token, &id)?.filter(|identity| identity.is_accepted());
+        let still_matches = identity.as_ref().is_some_and(|identity| {
+            identity
+                .identity_value(attribute)
+                .is_some_and(|found| matching.matches(value, &found))
+        });
+        if still_matches {
+            verified.push(id);
+        } else {
+            progress!(conf, "Device {} changed since it was indexed\n", id);
+            index.remove(&id);
+            if let Some(identity) = identity {
+                index.add(&identity);
+            }
+        }
+    }
+    Ok(verified)
+}
+
+/// Get the identity of a device from the authentication service, None if there is no
+/// such device.
+fn get_identity(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    id: &str,
+) -> Result<Option<MenderIdentity>, Box<dyn Error>> {
+    let response = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}/{}",
+                &conf.server_url, GET_DEVICES_AUTH_API, id
+            ))
+            .bearer_auth(token),
+    )?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    check_success!(response, "get device identity");
+    Ok(Some(response.json()?))
+}
+
+/// Get the mender id of a device, searching it by SerialNumber if needed.
+fn resolve_device(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    device: &DeviceRef,
+) -> Result<String, Box<dyn Error>> {
+    match device {
+        DeviceRef::Id(id) => Ok(id.clone()),
+        DeviceRef::SerialNumber(serial_number) => {
+            find_device_id(conf, client, token, "SerialNumber", serial_number).map_err(|err| {
+                Box::new(MenderError::new(format!(
+                    "{} was interpreted as a SerialNumber, use the id: prefix for a Mender id: {}",
+                    serial_number, err
+                ))) as Box<dyn Error>
+            })
+        }
+    }
+}
+
+/// Error returned when the inventory doesn't know a device id.
+fn device_not_found(id: &str) -> Box<dyn Error> {
+    Box::new(MenderError::new(format!(
+        "No device with Mender id {}, use the sn: prefix for a SerialNumber",
+        id
+    )))
+}
+
+/// Get the inventory of a device as JSON.
+fn device_info(
+    conf: &Config,
+    client: &reqwest::blocking::Client,
+    token: &str,
+    id: &str,
+) -> Result<serde_json::Value, Box<dyn Error>> {
+    let get_device_inventory = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}/{}",
+                &conf.server_url, GET_DEVICES_INVENTORY_API, id
+            ))
+            .bearer_auth(token),
+    )?;
+
+    if get_device_inventory.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(device_not_found(id));
+    }
+    check_success!(get_device_inventory, "get info");
+    Ok(get_device_inventory.json()?)
+}
+
+/// Print the requested attributes of a device as `name=value` lines, or as a JSON
+/// object. An error is returned if some of them are missing, the others are printed
+/// first.
+fn selected_attributes(
+    conf: &Config,
+    device: &MenderDevice,
+    names: &[String],
+) -> Result<String, Box<dyn Error>> {
+    let mut lines = vec![];
+    let mut values = serde_json::Map::new();
+    let mut missing = vec![];
+    for name in names {
+        match device.attribute(name) {
+            Some(value) => {
+                lines.push(format!("{}={}", name, value));
+                values.insert(name.clone(), serde_json::Value::String(value));
+            }
+            None => missing.push(name.as_str()),
+        }
+    }
+    let info = match conf.output_format {
+        OutputFormat::Text => lines.join("\n"),
+        OutputFormat::Json => serde_json::to_string_pretty(&values)?,
+    };
+    if missing.is_empty() {
+        return Ok(info);
+    }
+    if !lines.is_empty() {
+        progress!(conf, "{}\n", info);
+    }
+    Err(Box::new(MenderError::new(format!(
+        "Device {} has no attribute {}",
+        device.id,
+        missing.join(", ")
+    ))))
+}
+
+/// Get info of a device, all of it or only the requested attributes.
+pub fn get_info(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::GetInfo { device, attributes }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let id = resolve_device(conf, &client, token, device)?;
+        let json = device_info(conf, &client, token, &id)?;
+        if !attributes.is_empty() {
+            return selected_attributes(conf, &serde_json::from_value(json)?, attributes);
+        }
+        let mut info = serde_json::to_string_pretty(&json)?;
+        let history = group_history(&serde_json::from_value(json)?);
+        if !history.is_empty() && conf.output_format == OutputFormat::Text {
+            info.push_str("\nGroup history:");
+            for entry in history {
+                info.push_str(&format!(
+                    "\n{} {} -> {}{}",
+                    entry.timestamp,
+                    entry.from.as_deref().unwrap_or("<no group>"),
+                    entry.to,
+                    entry
+                        .actor
+                        .map(|actor| format!(" by {}", actor))
+                        .unwrap_or_default()
+                ));
+            }
+        }
+        Ok(info)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getinfo and token must be provided in get_info call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderAttribute {
+    name: String,
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderDevice {
+    id: String,
+    attributes: Option<Vec<MenderAttribute>>,
+    updated_ts: Option<String>,
+}
+
+impl MenderDevice {
+    /// Value of an attribute, non string values are returned as their JSON representation.
+    fn attribute(&self, name: &str) -> Option<String> {
+        self.attributes
+            .as_ref()?
+            .iter()
+            .find(|attribute| attribute.name == name)
+            .map(|attribute| match &attribute.value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            })
+    }
+
+    fn artifact_name(&self) -> String {
+        self.attribute("artifact_name").unwrap_or_default()
+    }
+
+    /// Time of the last inventory update, None if the device never reported it or it
+    /// isn't valid RFC3339.
+    fn updated(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        self.updated_ts
+            .as_ref()
+            .and_then(|updated| chrono::DateTime::parse_from_rfc3339(updated).ok())
+    }
+}
+
+/// Number of devices in inventory and update time of the most recently updated one.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+struct InventoryState {
+    total_count: u64,
+    newest_updated_ts: Option<String>,
+}
+
+/// Artifact counts cached by countartifacts with the state of the inventory they were
+/// computed from.
+#[derive(Deserialize, Serialize, Debug)]
+struct CachedArtifactsCount {
+    state: InventoryState,
+    counts: HashMap<String, i32>,
+}
+
+/// Get the number of devices and the most recent inventory update, by requesting a
+/// single device sorted by update time. None if the server doesn't send the count or
+/// doesn't support this request, the state is then unknown.
+fn inventory_state(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    query: &[(&str, &str)],
+) -> Result<Option<InventoryState>, Box<dyn Error>> {
+    let response = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}",
+                &conf.server_url, GET_DEVICES_INVENTORY_API
+            ))
+            .bearer_auth(token)
+            .query(query)
+            .query(&[
+                ("per_page", "1"),
+                ("page", "1"),
+                ("sort", "updated_ts:desc"),
+            ]),
+    )?;
+
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    let total_count = response
+        .headers()
+        .get("X-Total-Count")
+        .and_then(|count| count.to_str().ok())
+        .and_then(|count| count.parse().ok());
+    let newest_updated_ts = response
+        .json::<Vec<MenderDevice>>()?
+        .pop()
+        .and_then(|device| device.updated_ts);
+    Ok(total_count.map(|total_count| InventoryState {
+        total_count,
+        newest_updated_ts,
+    }))
+}
+
+/// Return the list of artifacts with a count of how much devices are using it.
+/// The counts are cached per server and group. The cached counts are considered up to
+/// date when the number of devices and the update time of the most recently updated
+/// device haven't changed: a device reporting a new artifact updates its inventory, and
+/// added or removed devices change the count. Removing a device and adding another one
+/// with no other inventory update in between goes unnoticed, --refresh forces a count.
+pub fn count_artifacts(conf: &Config) -> Result<HashMap<String, i32>, Box<dyn Error>> {
+    if let (Command::CountArtifacts { group, refresh }, Some(token)) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let query: Vec<(&str, &str)> = group
+            .iter()
+            .map(|group| ("group", group.as_str()))
+            .collect();
+        let cache_key = format!("{}|{}", conf.server_url, group.as_deref().unwrap_or(""));
+        let state = inventory_state(&client, conf, token, &query)?;
+        if let (false, Some(state)) = (*refresh, &state) {
+            if let Some(cached) = cache::load::<CachedArtifactsCount>(
+                conf.cache_dir.as_deref(),
+                "countartifacts",
+                &cache_key,
+            ) {
+                if &cached.state == state {
+                    progress!(
+                        conf,
+                        "Inventory unchanged since last count, using cached result\n"
+                    );
+                    return Ok(cached.counts);
+                }
+            }
+        }
+
+        progress!(conf, "Inventoring artifact used by devices");
+        let mut artifacts_count = HashMap::new();
+        paginate(
+            conf,
+            &client,
+            token,
+            &format!("{}{}", &conf.server_url, GET_DEVICES_INVENTORY_API),
+            &query,
+            "artifacts counting",
+            |res: Vec<MenderDevice>| {
+                progress!(conf, ".");
+                flush_progress!(conf);
+                for device in res {
+                    let count = artifacts_count.entry(device.artifact_name()).or_insert(0);
+                    *count += 1;
+                }
+                true
+            },
+        )?;
+        progress!(conf, "\n");
+        if let Some(state) = state {
+            let cached = CachedArtifactsCount {
+                state,
+                counts: artifacts_count,
+            };
+            cache::save(
+                conf.cache_dir.as_deref(),
+                "countartifacts",
+                &cache_key,
+                &cached,
+            )?;
+            artifacts_count = cached.counts;
+        }
+        Ok(artifacts_count)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be countartifacts and token must be provided in count_artifacts call",
+        ))))
+    }
+}
+
+pub fn display_ordered(map: HashMap<String, i32>) -> String {
+    display_ordered_with_unit(map, "")
+}
+
+/// Same as display_ordered with a unit after each count, such as " devices".
+pub fn display_ordered_with_unit(map: HashMap<String, i32>, unit: &str) -> String {
+    let mut vec: Vec<(&String, &i32)> = map.iter().collect();
+    vec.sort_by(|a, b| b.1.cmp(a.1));
+    let mut disp = String::new();
+    for (key, value) in vec {
+        disp.push_str(&format!("{}: {}{}\n", key, value, unit));
+    }
+    disp
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderDeployment {
+    id: String,
+    name: String,
+    artifact_name: String,
+    status: Option<String>,
+    created: Option<String>,
+    finished: Option<String>,
+    device_count: Option<usize>,
+}
+
+/// List the deployments having the given status, or all of them.
+fn list_deployments(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    status: Option<&str>,
+) -> Result<Vec<MenderDeployment>, Box<dyn Error>> {
+    let mut deployments = vec![];
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}", &conf.server_url, DEPLOY_API),
+        &status
+            .map(|status| vec![("status", status)])
+            .unwrap_or_default(),
+        "deployments listing",
+        |mut res| {
+            deployments.append(&mut res);
+            true
+        },
+    )?;
+    Ok(deployments)
+}
+
+/// States of the devices summed up by getpendingdeploymentstatsall.
+const PENDING_DEPLOYMENT_STATES: [&str; 5] =
+    ["success", "failure", "downloading", "installing", "pending"];
+
+/// Sum the statistics of the deployments in progress or pending, one line per deployment
+/// followed by the totals. Statistics are requested max_concurrent_requests at once.
+pub fn get_pending_deployment_stats_all(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::GetPendingDeploymentStatsAll, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let mut deployments = list_deployments(&client, conf, token, Some("inprogress"))?;
+        deployments.append(&mut list_deployments(
+            &client,
+            conf,
+            token,
+            Some("pending"),
+        )?);
+
+        let mut statistics = vec![];
+        for batch in deployments.chunks(conf.execution.max_concurrent_requests) {
+            let results: Vec<Result<HashMap<String, i32>, String>> = std::thread::scope(|scope| {
+                let requests: Vec<_> = batch
+                    .iter()
+                    .map(|deployment| {
+                        let client = &client;
+                        scope.spawn(move || {
+                            deployment_statistics(client, conf, token, &deployment.id)
+                                .map_err(|err| err.to_string())
+                        })
+                    })
+                    .collect();
+                requests
+                    .into_iter()
+                    .map(|request| request.join().unwrap())
+                    .collect()
+            });
+            for result in results {
+                statistics.push(result.map_err(MenderError::new)?);
+            }
+        }
+
+        let mut disp = format!("{:<36} {:<30}", "DEPLOYMENT", "NAME");
+        for state in PENDING_DEPLOYMENT_STATES.iter() {
+            disp.push_str(&format!(" {:>11}", state.to_uppercase()));
+        }
+        disp.push('\n');
+        let mut totals = [0; PENDING_DEPLOYMENT_STATES.len()];
+        for (deployment, statistics) in deployments.iter().zip(&statistics) {
+            disp.push_str(&format!("{:<36} {:<30}", deployment.id, deployment.name));
+            for (total, state) in totals.iter_mut().zip(PENDING_DEPLOYMENT_STATES.iter()) {
+                let count = *statistics.get(*state).unwrap_or(&0);
+                *total += count;
+                disp.push_str(&format!(" {:>11}", count));
+            }
+            disp.push('\n');
+        }
+        disp.push_str(&format!(
+            "{:<36} {:<30}",
+            "TOTAL",
+            format!("{} deployments", deployments.len())
+        ));
+        for total in totals.iter() {
+            disp.push_str(&format!(" {:>11}", total));
+        }
+        disp.push('\n');
+        Ok(disp)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getpendingdeploymentstatsall and token must be provided in get_pending_deployment_stats_all call",
+        ))))
+    }
+}
+
+impl DeploymentFilter {
+    fn matches(&self, deployment: &MenderDeployment) -> bool {
+        let status_matches = self.statuses.is_empty()
+            || deployment.status.as_ref().is_some_and(|status| {
+                let status = DeploymentStatus::parse(status);
+                self.statuses
+                    .iter()
+                    .any(|wanted| DeploymentStatus::parse(wanted) == status)
+            });
+        let devices_match = match (self.max_devices, deployment.device_count) {
+            (Some(max_devices), Some(device_count)) => device_count <= max_devices,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let age_matches = match self.older_than {
+            Some(older_than) => deployment
+                .finished
+                .as_ref()
+                .or(deployment.created.as_ref())
+                .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+                .is_some_and(|date| {
+                    chrono::Utc::now().signed_duration_since(date)
+                        >= chrono::Duration::from_std(older_than).unwrap_or(chrono::Duration::MAX)
+                }),
+            None => true,
+        };
+        status_matches && devices_match && age_matches
+    }
+}
+
+/// List the deployments matching a filter, the status is given to the server when a
+/// single one is requested.
+fn filtered_deployments(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    filter: &DeploymentFilter,
+) -> Result<Vec<MenderDeployment>, Box<dyn Error>> {
+    let status = match filter.statuses.as_slice() {
+        [status] => Some(DeploymentStatus::parse(status)),
+        _ => None,
+    };
+    let status = status.as_ref().map(|status| status.as_str());
+    let deployments = match &filter.device {
+        Some(device) => {
+            let id = resolve_device(conf, client, token, device)?;
+            list_device_deployments(client, conf, token, &id, status)?
+        }
+        None => list_deployments(client, conf, token, status)?,
+    };
+    Ok(deployments
+        .into_iter()
+        .filter(|deployment| filter.matches(deployment))
+        .collect())
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderDeviceDeployment {
+    deployment: MenderDeployment,
+}
+
+/// List the deployments of a device having the given status, or all of them.
+fn list_device_deployments(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+    status: Option<&str>,
+) -> Result<Vec<MenderDeployment>, Box<dyn Error>> {
+    let mut deployments = vec![];
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}/devices/{}", &conf.server_url, DEPLOY_API, id),
+        &status
+            .map(|status| vec![("status", status)])
+            .unwrap_or_default(),
+        "device deployments listing",
+        |res: Vec<MenderDeviceDeployment>| {
+            deployments.extend(res.into_iter().map(|res| res.deployment));
+            true
+        },
+    )?;
+    Ok(deployments)
+}
+
+fn display_deployment(deployment: &MenderDeployment) -> String {
+    format!(
+        "{} {} '{}' using artifact {} created {}{}, {} devices",
+        deployment.id,
+        deployment.status.as_deref().unwrap_or("<unknown>"),
+        deployment.name,
+        deployment.artifact_name,
+        deployment.created.as_deref().unwrap_or("<unknown>"),
+        deployment
+            .finished
+            .as_ref()
+            .map(|finished| format!(" finished {}", finished))
+            .unwrap_or_default(),
+        deployment
+            .device_count
+            .map(|count| count.to_string())
+            .unwrap_or_else(|| String::from("<unknown>"))
+    )
+}
+
+/// List the deployments matching the filter, one per line.
+pub fn list_filtered_deployments(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::ListDeployments { filter }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let mut disp = String::new();
+        for deployment in filtered_deployments(&client, conf, token, filter)? {
+            disp.push_str(&display_deployment(&deployment));
+            disp.push('\n');
+        }
+        Ok(disp)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be listdeployments and token must be provided in list_filtered_deployments call",
+        ))))
+    }
+}
+
+/// Delete a deployment, return false if the server refused it with 404 or 405, as
+/// servers without an endpoint to delete deployments do.
+fn delete_deployment(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let delete_deployment = send(
+        conf,
+        client,
+        client
+            .delete(&format!("{}{}/{}", &conf.server_url, DEPLOY_API, id))
+            .bearer_auth(token),
+    )?;
+    let status = delete_deployment.status();
+    if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::METHOD_NOT_ALLOWED
+    {
+        return Ok(false);
+    }
+    check_success!(delete_deployment, "deployment deletion");
+    Ok(true)
+}
+
+/// Delete the deployments matching the filter after confirmation. Mender servers
+/// usually don't provide any endpoint to delete deployments, when the first deletion
+/// is refused with 404 or 405 the matching deployments are returned to be handled
+/// manually instead. The other deployments are all processed even if some fail, and
+/// an error is returned at the end if any did.
+pub fn cleanup_deployments(conf: &Config) -> Result<String, Box<dyn Error>> {
+    let finished_filter;
+    let filter = match &conf.command {
+        Command::CleanupDeployments { filter } => Some(filter),
+        Command::CleanupFinishedDeployments { older_than_days } => {
+            finished_filter = DeploymentFilter::finished_older_than_days(*older_than_days);
+            Some(&finished_filter)
+        }
+        _ => None,
+    };
+    if let (Some(filter), Some(token)) = (filter, &conf.token) {
+        let client = blocking_client(conf)?;
+        let deployments = filtered_deployments(&client, conf, token, filter)?;
+        for deployment in &deployments {
+            progress!(conf, "{}\n", display_deployment(deployment));
+        }
+        let impact = format!("{} deployments", deployments.len());
+        match confirm("Clean up deployments", &impact, conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Deployments cleanup aborted",
+                ))))
+            }
+            Decision::DryRun => {
+                return Ok(format!("{} deployments to clean up", deployments.len()))
+            }
+            Decision::Proceed => {}
+        }
+
+        let mut report = String::new();
+        let mut failed = 0;
+        for (idx, deployment) in deployments.iter().enumerate() {
+            match delete_deployment(&client, conf, token, &deployment.id) {
+                Ok(true) => report.push_str(&format!("{}: deleted\n", deployment.id)),
+                Ok(false) if idx == 0 => {
+                    let ids: Vec<&str> = deployments
+                        .iter()
+                        .map(|deployment| deployment.id.as_str())
+                        .collect();
+                    return Ok(format!(
+                        "Deleting deployments is not supported by the server, to clean up manually:\n{}",
+                        ids.join("\n")
+                    ));
+                }
+                Ok(false) => {
+                    failed += 1;
+                    report.push_str(&format!(
+                        "{}: failed: deletion refused by the server\n",
+                        deployment.id
+                    ));
+                }
+                Err(err) => {
+                    failed += 1;
+                    report.push_str(&format!("{}: failed: {}\n", deployment.id, err));
+                }
+            }
+        }
+        let summary = format!(
+            "{} deployments deleted, {} failed",
+            deployments.len() - failed,
+            failed
+        );
+        if failed > 0 {
+            progress!(conf, "{}", report);
+            return Err(Box::new(MenderError::new(summary)));
+        }
+        Ok(report + &summary)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be cleanupdeployments or cleanupfinisheddeployments and token must be provided in cleanup_deployments call",
+        ))))
+    }
+}
+
+/// Clean up the finished deployments older than the number of days given to
+/// cleanupfinisheddeployments, see cleanup_deployments.
+pub fn cleanup_finished_deployments(conf: &Config) -> Result<String, Box<dyn Error>> {
+    cleanup_deployments(conf)
+}
+
+/// Get the inventory of a device.
+fn device_inventory(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+) -> Result<MenderDevice, Box<dyn Error>> {
+    let get_device_inventory = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}/{}",
+                &conf.server_url, GET_DEVICES_INVENTORY_API, id
+            ))
+            .bearer_auth(token),
+    )?;
+
+    if get_device_inventory.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(device_not_found(id));
+    }
+    check_success!(get_device_inventory, "device inventory");
+    Ok(get_device_inventory.json()?)
+}
+
+/// List all devices in inventory matching the query, a dot is printed for each page.
+fn inventory_devices(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    query: &[(&str, &str)],
+) -> Result<Vec<MenderDevice>, Box<dyn Error>> {
+    let mut devices = vec![];
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}", &conf.server_url, GET_DEVICES_INVENTORY_API),
+        query,
+        "devices listing",
+        |mut res| {
+            progress!(conf, ".");
+            flush_progress!(conf);
+            devices.append(&mut res);
+            true
+        },
+    )?;
+    Ok(devices)
+}
+
+/// Search devices in inventory matching all the attribute filters, optionally restricted
+/// to a group. The inventory API can't filter on missing attributes so devices lacking
+/// one of the missing attributes are selected while walking through the results.
+pub fn search(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::Search {
+            filters,
+            missing_attributes,
+            group,
+            columns,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        progress!(conf, "Searching devices");
+        let client = blocking_client(conf)?;
+        let mut query: Vec<(&str, &str)> = filters
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if let Some(group) = group {
+            query.push(("group", group));
+        }
+        let mut found = String::new();
+        for device in inventory_devices(&client, conf, token, &query)?
+            .into_iter()
+            .filter(|device| {
+                missing_attributes
+                    .iter()
+                    .all(|name| device.attribute(name).is_none())
+            })
+        {
+            found.push_str(&device_row(&device, columns));
+        }
+        progress!(conf, "\n");
+        Ok(found)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be search and token must be provided in search call",
+        ))))
+    }
+}
+
+/// Value of a column of the device listings.
+fn device_column(device: &MenderDevice, column: DeviceColumn) -> String {
+    match column {
+        DeviceColumn::Id => device.id.clone(),
+        DeviceColumn::SerialNumber => device.attribute("SerialNumber").unwrap_or_default(),
+        DeviceColumn::Artifact => device.artifact_name(),
+        DeviceColumn::Group => device.attribute("group").unwrap_or_default(),
+        DeviceColumn::DeviceType => device.attribute("device_type").unwrap_or_default(),
+        DeviceColumn::Status => device.attribute("status").unwrap_or_default(),
+        DeviceColumn::LastSeen => device
+            .attribute("check_in_time")
+            .or_else(|| device.updated_ts.clone())
+            .unwrap_or_default(),
+        DeviceColumn::ClientVersion => device
+            .attribute("mender_client_version")
+            .unwrap_or_default(),
+    }
+}
+
+/// Line of a device listing, the columns are separated by tabs.
+fn device_row(device: &MenderDevice, columns: &[DeviceColumn]) -> String {
+    let values: Vec<String> = columns
+        .iter()
+        .map(|column| device_column(device, *column))
+        .collect();
+    values.join("\t") + "\n"
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct AttributeUpdate {
+    name: String,
+    value: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+}
+
+/// Set the attributes of a device from a JSON file, all attributes are sent in a single
+/// request. Return the number of updated attributes.
+pub fn set_attributes_from_file(conf: &Config) -> Result<usize, Box<dyn Error>> {
+    if let (Command::SetAttributesFromFile { device, file }, Some(token)) =
+        (&conf.command, &conf.token)
+    {
+        let attributes: Vec<AttributeUpdate> =
+            serde_json::from_reader(File::open(file)?).map_err(|err| {
+                MenderError::new(format!(
+                    "{} must contain a list of {{\"name\", \"value\", \"scope\"}} objects: {}",
+                    file, err
+                ))
+            })?;
+        if let Some(attribute) = attributes
+            .iter()
+            .find(|attribute| attribute.name.is_empty())
+        {
+            return Err(Box::new(MenderError::new(format!(
+                "Attribute with value {} has an empty name",
+                attribute.value
+            ))));
+        }
+
+        let client = blocking_client(conf)?;
+        let device_id = resolve_device(conf, &client, token, device)?;
+        let patch_attributes = send(
+            conf,
+            &client,
+            client
+                .patch(&format!(
+                    "{}{}/{}/tags",
+                    &conf.server_url, GET_DEVICES_INVENTORY_API, device_id
+                ))
+                .bearer_auth(token)
+                .json(&attributes),
+        )?;
+
+        if patch_attributes.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(device_not_found(&device_id));
+        }
+        check_success!(patch_attributes, "attributes update");
+        Ok(attributes.len())
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be setdeviceattributesfromfile and token must be provided in set_attributes_from_file call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct MenderAuthDevice {
+    id: String,
+    status: String,
+    #[serde(default)]
+    identity_data: HashMap<String, serde_json::Value>,
+}
+
+/// Authentication statuses counted by devicecount.
+const AUTH_STATUSES: [&str; 5] = ["accepted", "pending", "rejected", "preauthorized", "noauth"];
+
+#[derive(Deserialize, Debug)]
+struct MenderCount {
+    count: usize,
+}
+
+/// Count the devices having an authentication status. The count endpoint is used when
+/// the server has it, otherwise the devices are listed page by page and counted.
+fn count_auth_devices(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    status: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let get_count = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}/count",
+                &conf.server_url, GET_DEVICES_AUTH_API
+            ))
+            .bearer_auth(token)
+            .query(&[("status", status)]),
+    )?;
+    if get_count.status() != reqwest::StatusCode::NOT_FOUND {
+        check_success!(get_count, "devices count");
+        return Ok(get_count.json::<MenderCount>()?.count);
+    }
+
+    let mut count = 0;
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API),
+        &[("status", status)],
+        "devices count",
+        |res: Vec<MenderAuthDevice>| {
+            count += res.len();
+            true
+        },
+    )?;
+    Ok(count)
+}
+
+/// Count the devices of each authentication status. The statuses are counted
+/// max_concurrent_requests at once.
+pub fn device_count(conf: &Config) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
+    if let (Command::DeviceCount, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let mut counts = vec![];
+        for batch in AUTH_STATUSES.chunks(conf.execution.max_concurrent_requests) {
+            let results: Vec<Result<usize, String>> = std::thread::scope(|scope| {
+                let requests: Vec<_> = batch
+                    .iter()
+                    .map(|status| {
+                        let client = &client;
+                        scope.spawn(move || {
+                            count_auth_devices(client, conf, token, status)
+                                .map_err(|err| err.to_string())
+                        })
+                    })
+                    .collect();
+                requests
+                    .into_iter()
+                    .map(|request| request.join().unwrap())
+                    .collect()
+            });
+            for (status, result) in batch.iter().zip(results) {
+                counts.push((status.to_string(), result.map_err(MenderError::new)?));
+            }
+        }
+        Ok(counts)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be devicecount and token must be provided in device_count call",
+        ))))
+    }
+}
+
+/// Sort counts by decreasing number then by name.
+fn sorted_counts(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+/// List the devices whose inventory hasn't been updated for a number of days, oldest
+/// first with the age in days. Devices which never reported their inventory are listed
+/// first as never seen.
+pub fn stale(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::Stale { days }, Some(token)) = (&conf.command, &conf.token) {
+        progress!(conf, "Listing devices");
+        let client = blocking_client(conf)?;
+        let now = chrono::Utc::now();
+        let limit = now - chrono::Duration::days(*days as i64);
+        let mut stale_devices: Vec<_> = inventory_devices(&client, conf, token, &[])?
+            .into_iter()
+            .filter_map(|device| match device.updated() {
+                Some(updated) if updated >= limit => None,
+                updated => Some((updated, device)),
+            })
+            .collect();
+        progress!(conf, "\n");
+        // None, never seen, sorts before any time
+        stale_devices.sort_by_key(|(updated, _)| *updated);
+
+        let mut disp = String::new();
+        for (updated, device) in stale_devices {
+            disp.push_str(&format!(
+                "{}\t{}\t{}\n",
+                device.id,
+                device.attribute("SerialNumber").unwrap_or_default(),
+                match updated {
+                    Some(updated) => format!(
+                        "{} days, last update {}",
+                        now.signed_duration_since(updated).num_days(),
+                        device.updated_ts.unwrap_or_default()
+                    ),
+                    None => String::from("never seen"),
+                }
+            ));
+        }
+        Ok(disp)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be stale and token must be provided in stale call",
+        ))))
+    }
+}
+
+/// Build a self-contained HTML report of the fleet: devices by authentication status,
+/// artifacts installed in each group, devices whose inventory hasn't been updated for
+/// a number of days and deployments in progress.
+pub fn fleet_report(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::FleetReport { stale_days, .. }, Some(token)) = (&conf.command, &conf.token) {
+        progress!(conf, "Gathering fleet data");
+        let client = blocking_client(conf)?;
+
+        let mut statuses = HashMap::new();
+        paginate(
+            conf,
+            &client,
+            token,
+            &format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API),
+            &[],
+            "devices status",
+            |res: Vec<MenderAuthDevice>| {
+                progress!(conf, ".");
+                flush_progress!(conf);
+                for device in res {
+                    *statuses.entry(device.status).or_insert(0) += 1;
+                }
+                true
+            },
+        )?;
+
+        let stale_limit = chrono::Utc::now() - chrono::Duration::days(*stale_days as i64);
+        let mut artifacts_per_group: HashMap<String, HashMap<String, usize>> = HashMap::new();
+        let mut stale_devices = vec![];
+        for device in inventory_devices(&client, conf, token, &[])? {
+            let group = device
+                .attribute("group")
+                .unwrap_or_else(|| String::from("<no group>"));
+            *artifacts_per_group
+                .entry(group)
+                .or_default()
+                .entry(device.artifact_name())
+                .or_insert(0) += 1;
+            if matches!(device.updated(), Some(updated) if updated < stale_limit) {
+                stale_devices.push([
+                    device.id.clone(),
+                    device.attribute("SerialNumber").unwrap_or_default(),
+                    device.updated_ts.clone().unwrap_or_default(),
+                ]);
+            }
+        }
+        stale_devices.sort_by(|a, b| a[2].cmp(&b[2]));
+        let mut artifacts_per_group: Vec<_> = artifacts_per_group
+            .into_iter()
+            .map(|(group, artifacts)| (group, sorted_counts(artifacts)))
+            .collect();
+        artifacts_per_group.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let deployments = list_deployments(&client, conf, token, Some("inprogress"))?
+            .into_iter()
+            .map(|deployment| [deployment.id, deployment.name, deployment.artifact_name])
+            .collect();
+        progress!(conf, "\n");
+
+        Ok(FleetReport {
+            server_url: conf.server_url.clone(),
+            generated: chrono::Local::now()
+                .format("%Y-%m-%d %H:%M:%S %:z")
+                .to_string(),
+            device_statuses: sorted_counts(statuses),
+            artifacts_per_group,
+            stale_days: *stale_days,
+            stale_devices,
+            deployments,
+        }
+        .render())
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be report fleet and token must be provided in fleet_report call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderAuthSet {
+    id: String,
+    status: String,
+    ts: Option<String>,
+    #[serde(default)]
+    identity_data: serde_json::Value,
+    pubkey: Option<String>,
+}
+
+impl MenderAuthSet {
+    /// SHA256 of the DER encoded public key, as colon separated hex bytes.
+    fn pubkey_fingerprint(&self) -> Option<String> {
+        let der: String = self
+            .pubkey
+            .as_ref()?
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let der = base64::decode(der.trim()).ok()?;
+        let fingerprint: Vec<String> = Sha256::digest(&der)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Some(fingerprint.join(":"))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderAuthDeviceSets {
+    status: String,
+    #[serde(default)]
+    auth_sets: Vec<MenderAuthSet>,
+}
+
+#[derive(Serialize)]
+struct AuthSetStatus<'a> {
+    status: &'a str,
+}
+
+/// Get the id of a pending device from its SerialNumber. Pending devices have no
+/// inventory yet so only the identity data of pending devices are searched.
+fn find_pending_device_id(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    serial_number: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut found = None;
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API),
+        &[("status", "pending")],
+        "pending device search",
+        |res: Vec<MenderAuthDevice>| {
+            found = res.into_iter().find(|device| {
+                device
+                    .identity_data
+                    .get("SerialNumber")
+                    .and_then(|sn| sn.as_str())
+                    .is_some_and(|sn| conf.serial_number_matching.matches(serial_number, sn))
+            });
+            found.is_none()
+        },
+    )?;
+    found.map(|device| device.id).ok_or_else(|| {
+        Box::new(MenderError::new(format!(
+            "{} was interpreted as a SerialNumber and no pending device has it, use the id: prefix for a Mender id",
+            serial_number
+        ))) as Box<dyn Error>
+    })
+}
+
+/// List the devices waiting for their authentication to be accepted, with all their
+/// identity data and the time of their pending authentication request.
+pub fn list_pending(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::ListPending, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let mut disp = String::new();
+        paginate(
+            conf,
+            &client,
+            token,
+            &format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API),
+            &[("status", "pending")],
+            "pending devices listing",
+            |res: Vec<MenderIdentity>| {
+                for device in res {
+                    disp.push_str(&format!("Device {}\n", device.id));
+                    let requested = device
+                        .auth_sets
+                        .iter()
+                        .find(|auth_set| auth_set.status == "pending")
+                        .and_then(|auth_set| auth_set.ts.as_deref());
+                    if let Some(requested) = requested {
+                        disp.push_str(&format!("  requested: {}\n", requested));
+                    }
+                    for (name, value) in &device.identity_data {
+                        let value = match value.as_str() {
+                            Some(value) => value.to_string(),
+                            None => value.to_string(),
+                        };
+                        disp.push_str(&format!("  {}: {}\n", name, value));
+                    }
+                    disp.push('\n');
+                }
+                true
+            },
+        )?;
+        Ok(disp)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be listpending and token must be provided in list_pending call",
+        ))))
+    }
+}
+
+/// Get the authentication data of a device, with its auth sets.
+fn auth_device(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+) -> Result<MenderAuthDeviceSets, Box<dyn Error>> {
+    let get_device = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}/{}",
+                &conf.server_url, GET_DEVICES_AUTH_API, id
+            ))
+            .bearer_auth(token),
+    )?;
+    if get_device.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(device_not_found(id));
+    }
+    check_success!(get_device, "device authentication");
+    Ok(get_device.json()?)
+}
+
+/// Show the auth sets of a device with their status, public key fingerprint and identity
+/// data, as pretty JSON or one line per auth set.
+pub fn get_auth(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::GetAuth { device, compact }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let id = match device {
+            DeviceRef::SerialNumber(serial_number) => resolve_device(conf, &client, token, device)
+                .or_else(|_| find_pending_device_id(&client, conf, token, serial_number))?,
+            DeviceRef::Id(id) => id.clone(),
+        };
+        let auth_device = auth_device(&client, conf, token, &id)?;
+        if auth_device.auth_sets.is_empty() {
+            return Ok(format!(
+                "Device {} has no auth set, its status is {}",
+                id, auth_device.status
+            ));
+        }
+        if *compact {
+            let lines: Vec<String> = auth_device
+                .auth_sets
+                .iter()
+                .map(|auth_set| {
+                    format!(
+                        "{} {} {} {}",
+                        auth_set.id,
+                        auth_set.status,
+                        auth_set
+                            .pubkey_fingerprint()
+                            .unwrap_or_else(|| String::from("<no key>")),
+                        auth_set.identity_data
+                    )
+                })
+                .collect();
+            return Ok(lines.join("\n"));
+        }
+        let auth_sets: Vec<serde_json::Value> = auth_device
+            .auth_sets
+            .iter()
+            .map(|auth_set| {
+                serde_json::json!({
+                    "id": auth_set.id,
+                    "status": auth_set.status,
+                    "ts": auth_set.ts,
+                    "pubkey_fingerprint": auth_set.pubkey_fingerprint(),
+                    "identity_data": auth_set.identity_data,
+                })
+            })
+            .collect();
+        Ok(serde_json::to_string_pretty(&auth_sets)?)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getauth and token must be provided in get_auth call",
+        ))))
+    }
+}
+
+/// Select the auth set to update among the ones having one of the given states. The
+/// auth set given by the user is used if any, otherwise there must be a single one.
+fn select_auth_set<'a>(
+    conf: &Config,
+    id: &str,
+    auth_device: &'a MenderAuthDeviceSets,
+    authset: &Option<String>,
+    states: &[&str],
+    action: &str,
+) -> Result<&'a MenderAuthSet, Box<dyn Error>> {
+    if let Some(authset) = authset {
+        let auth_set = auth_device
+            .auth_sets
+            .iter()
+            .find(|auth_set| &auth_set.id == authset)
+            .ok_or_else(|| {
+                MenderError::new(format!("Device {} has no auth set {}", id, authset))
+            })?;
+        if !states.contains(&auth_set.status.as_str()) {
+            return Err(Box::new(MenderError::new(format!(
+                "Auth set {} of device {} is {} and can't be {}",
+                auth_set.id, id, auth_set.status, action
+            ))));
+        }
+        return Ok(auth_set);
+    }
+
+    let candidates: Vec<&MenderAuthSet> = auth_device
+        .auth_sets
+        .iter()
+        .filter(|auth_set| states.contains(&auth_set.status.as_str()))
+        .collect();
+    match candidates.as_slice() {
+        [auth_set] => Ok(auth_set),
+        [] => Err(Box::new(MenderError::new(format!(
+            "Device {} has no auth set which can be {}, its status is {}",
+            id, action, auth_device.status
+        )))),
+        _ => {
+            let auth_sets: Vec<String> = candidates
+                .iter()
+                .map(|auth_set| {
+                    format!(
+                        "{} {} created {} identity {}",
+                        auth_set.id,
+                        auth_set.status,
+                        auth_set.ts.as_deref().unwrap_or("<unknown>"),
+                        auth_set.identity_data
+                    )
+                })
+                .collect();
+            let prompt = format!("Auth set of device {} to be {}", id, action);
+            if let Some(choice) = choose(&prompt, &auth_sets, conf)? {
+                return Ok(candidates[choice]);
+            }
+            Err(Box::new(MenderError::new(format!(
+                "Device {} has {} auth sets which can be {}, choose one with --authset:\n{}",
+                id,
+                candidates.len(),
+                action,
+                auth_sets
+                    .iter()
+                    .map(|auth_set| format!("  {}", auth_set))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ))))
+        }
+    }
+}
+
+/// Set the status of an auth set of a device.
+fn set_auth_set_status(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+    auth_set_id: &str,
+    status: &str,
+) -> Result<(), Box<dyn Error>> {
+    let put_status = send(
+        conf,
+        client,
+        client
+            .put(&format!(
+                "{}{}/{}/auth/{}/status",
+                &conf.server_url, GET_DEVICES_AUTH_API, id, auth_set_id
+            ))
+            .bearer_auth(token)
+            .json(&AuthSetStatus { status }),
+    )?;
+    check_success!(put_status, format!("setting auth set status to {}", status));
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PreauthData {
+    identity_data: serde_json::Map<String, serde_json::Value>,
+    pubkey: String,
+}
+
+/// Preauthorize a device from its identity data and public key, so that it's accepted
+/// when it first connects. Return the id of the created device when the server gives it.
+pub fn preauth(conf: &Config) -> Result<Option<String>, Box<dyn Error>> {
+    if let (
+        Command::Preauth {
+            identity_file,
+            pubkey_file,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        let identity_data: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_reader(File::open(identity_file)?).map_err(|err| {
+                MenderError::new(format!(
+                    "{} must contain the identity data as a JSON object: {}",
+                    identity_file, err
+                ))
+            })?;
+        if identity_data.is_empty() {
+            return Err(Box::new(MenderError::new(format!(
+                "{} contains no identity data",
+                identity_file
+            ))));
+        }
+        let pubkey = std::fs::read_to_string(pubkey_file)?;
+        if !pubkey.trim_start().starts_with("-----BEGIN ")
+            || !pubkey.trim_end().ends_with("-----")
+            || !pubkey.contains("-----END ")
+        {
+            return Err(Box::new(MenderError::new(format!(
+                "{} is not a PEM encoded public key",
+                pubkey_file
+            ))));
+        }
+
+        let client = blocking_client(conf)?;
+        let post_device = send(
+            conf,
+            &client,
+            client
+                .post(&format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API))
+                .bearer_auth(token)
+                .json(&PreauthData {
+                    identity_data,
+                    pubkey,
+                }),
+        )?;
+        if post_device.status() == reqwest::StatusCode::CONFLICT {
+            return Err(Box::new(MenderError::new(format!(
+                "A device with the identity data of {} or the key of {} already exists",
+                identity_file, pubkey_file
+            ))));
+        }
+        check_success!(post_device, "device preauthorization");
+        Ok(post_device
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| location.rsplit('/').next())
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_string()))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be preauth and token must be provided in preauth call",
+        ))))
+    }
+}
+
+/// Accept the pending auth set of a device and return the device id. When the device
+/// has several pending auth sets the one to accept must be given.
+pub fn accept(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::Accept { device, authset }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let id = match device {
+            DeviceRef::Id(id) => id.clone(),
+            DeviceRef::SerialNumber(serial_number) => {
+                find_pending_device_id(&client, conf, token, serial_number)?
+            }
+        };
+        let auth_device = auth_device(&client, conf, token, &id)?;
+        let has_pending = auth_device
+            .auth_sets
+            .iter()
+            .any(|auth_set| auth_set.status == "pending");
+        if authset.is_none() && !has_pending && auth_device.status == "accepted" {
+            return Err(Box::new(MenderError::new(format!(
+                "Device {} is already accepted",
+                id
+            ))));
+        }
+        let auth_set = select_auth_set(conf, &id, &auth_device, authset, &["pending"], "accepted")?;
+        set_auth_set_status(&client, conf, token, &id, &auth_set.id, "accepted")?;
+        Ok(id)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be accept and token must be provided in accept call",
+        ))))
+    }
+}
+
+/// Reject the accepted or pending auth set of a device, the rejected auth set is
+/// described with its identity data. When the device has several such auth sets the one
+/// to reject must be given.
+pub fn reject(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::Reject { device, authset }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let id = match device {
+            DeviceRef::SerialNumber(serial_number) => resolve_device(conf, &client, token, device)
+                .or_else(|_| find_pending_device_id(&client, conf, token, serial_number))?,
+            DeviceRef::Id(id) => id.clone(),
+        };
+        let auth_device = auth_device(&client, conf, token, &id)?;
+        let auth_set = select_auth_set(
+            conf,
+            &id,
+            &auth_device,
+            authset,
+            &["accepted", "pending"],
+            "rejected",
+        )?;
+        set_auth_set_status(&client, conf, token, &id, &auth_set.id, "rejected")?;
+        Ok(format!(
+            "Auth set {} of device {} rejected, its identity data is {}",
+            auth_set.id, id, auth_set.identity_data
+        ))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be reject and token must be provided in reject call",
+        ))))
+    }
+}
+
+/// Decommission a device: its authentication is removed, then its inventory. The
+/// identity data of the device are shown before asking for confirmation.
+pub fn decommission(conf: &Config) -> Result<Option<String>, Box<dyn Error>> {
+    if let (Command::Decommission { device }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let id = resolve_device(conf, &client, token, device)?;
+        let info = device_info(conf, &client, token, &id)?;
+        let identity: serde_json::Map<String, serde_json::Value> = info["attributes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|attribute| attribute["scope"] == "identity")
+            .filter_map(|attribute| {
+                Some((
+                    attribute["name"].as_str()?.to_string(),
+                    attribute["value"].clone(),
+                ))
+            })
+            .collect();
+        let impact = format!(
+            "device {} with identity data {}",
+            id,
+            serde_json::Value::Object(identity)
+        );
+        match confirm("Decommission", &impact, conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Decommission aborted",
+                ))))
+            }
+            Decision::DryRun => return Ok(None),
+            Decision::Proceed => {}
+        }
+
+        let delete_device = send(
+            conf,
+            &client,
+            client
+                .delete(&format!(
+                    "{}{}/{}",
+                    &conf.server_url, GET_DEVICES_AUTH_API, id
+                ))
+                .bearer_auth(token),
+        )?;
+        if delete_device.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(device_not_found(&id));
+        }
+        check_success!(delete_device, "device decommission");
+        // The inventory is also cleaned up by the server after the decommission, so
+        // it may already be gone.
+        let delete_inventory = send(
+            conf,
+            &client,
+            client
+                .delete(&format!(
+                    "{}{}/{}",
+                    &conf.server_url, GET_DEVICES_INVENTORY_API, id
+                ))
+                .bearer_auth(token),
+        )?;
+        if delete_inventory.status() != reqwest::StatusCode::NOT_FOUND {
+            check_success!(delete_inventory, "device inventory removal");
+        }
+        Ok(Some(id))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be decommission and token must be provided in decommission call",
+        ))))
+    }
+}
+
+/// Remove the inventory of a device, whatever its authentication. The artifact and group
+/// of the removed inventory are printed before asking for confirmation.
+pub fn delete_inventory(conf: &Config) -> Result<Option<String>, Box<dyn Error>> {
+    if let (Command::DeleteInventory { device }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let id = resolve_device(conf, &client, token, device)?;
+        let inventory = device_inventory(&client, conf, token, &id)?;
+        let impact = format!(
+            "device {} with artifact {} in group {}",
+            id,
+            inventory
+                .attribute("artifact_name")
+                .unwrap_or_else(|| String::from("<none>")),
+            inventory
+                .attribute("group")
+                .unwrap_or_else(|| String::from("<no group>"))
+        );
+        match confirm("Delete inventory", &impact, conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Inventory deletion aborted",
+                ))))
+            }
+            Decision::DryRun => return Ok(None),
+            Decision::Proceed => {}
+        }
+
+        let delete_inventory = send(
+            conf,
+            &client,
+            client
+                .delete(&format!(
+                    "{}{}/{}",
+                    &conf.server_url, GET_DEVICES_INVENTORY_API, id
+                ))
+                .bearer_auth(token),
+        )?;
+        if delete_inventory.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(device_not_found(&id));
+        }
+        check_success!(delete_inventory, "inventory deletion");
+        Ok(Some(id))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be deleteinventory and token must be provided in delete_inventory call",
+        ))))
+    }
+}
+
+/// Return the groups where the percentage of devices having the artifact installed is
+/// above the threshold. Empty groups are never returned.
+pub fn list_groups_by_artifact(conf: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    if let (
+        Command::ListGroupsByArtifact {
+            artifact_name,
+            threshold_percent,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        progress!(conf, "Checking artifact of groups");
+        let client = blocking_client(conf)?;
+        let mut groups = vec![];
+        for group in group_names(&client, conf, token)? {
+            let devices = inventory_devices(&client, conf, token, &[("group", &group)])?;
+            if devices.is_empty() {
+                continue;
+            }
+            let with_artifact = devices
+                .iter()
+                .filter(|device| &device.artifact_name() == artifact_name)
+                .count();
+            if with_artifact as f64 * 100.0 / devices.len() as f64 > *threshold_percent {
+                groups.push(group);
+            }
+        }
+        progress!(conf, "\n");
+        Ok(groups)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be listgroupsbyartifact and token must be provided in list_groups_by_artifact call",
+        ))))
+    }
+}
+
+/// List the devices which are not in any group. Servers not supporting the has_group
+/// parameter return all devices, so devices having a group attribute are filtered out.
+fn ungrouped_devices(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+) -> Result<Vec<MenderDevice>, Box<dyn Error>> {
+    Ok(
+        inventory_devices(client, conf, token, &[("has_group", "false")])?
+            .into_iter()
+            .filter(|device| device.attribute("group").is_none())
+            .collect(),
+    )
+}
+
+/// List the names of all groups.
+fn group_names(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let get_groups = send(
+        conf,
+        client,
+        client
+            .get(&format!("{}{}", &conf.server_url, GROUPS_API))
+            .bearer_auth(token),
+    )?;
+
+    check_success!(get_groups, "groups listing");
+    Ok(get_groups.json()?)
+}
+
+/// Url of a group, followed by the given path segments. The group name is percent
+/// encoded, so that it can't change the path or add a query.
+fn group_url(conf: &Config, group: &str, segments: &[&str]) -> Result<String, Box<dyn Error>> {
+    let mut url = reqwest::Url::parse(&format!("{}{}", &conf.server_url, GROUPS_API))?;
+    url.path_segments_mut()
+        .map_err(|_| MenderError::new(format!("Invalid server url {}", &conf.server_url)))?
+        .push(group)
+        .extend(segments);
+    Ok(url.into_string())
+}
+
+/// Count the devices of a group, from the X-Total-Count header of a single device page
+/// or else by listing all of them.
+fn group_device_count(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    group: &str,
+) -> Result<i32, Box<dyn Error>> {
+    let get_devices = send(
+        conf,
+        client,
+        client
+            .get(&group_url(conf, group, &["devices"])?)
+            .bearer_auth(token)
+            .query(&[("per_page", "1"), ("page", "1")]),
+    )?;
+    check_success!(get_devices, "group devices count");
+    if let Some(count) = get_devices
+        .headers()
+        .get("X-Total-Count")
+        .and_then(|count| count.to_str().ok())
+        .and_then(|count| count.parse().ok())
+    {
+        return Ok(count);
+    }
+
+    Ok(list_group_devices(client, conf, token, group)?.len() as i32)
+}
+
+/// Count the devices of each group, and the accepted devices which are in none under
+/// "ungrouped".
+pub fn count_groups(conf: &Config) -> Result<HashMap<String, i32>, Box<dyn Error>> {
+    if let (Command::CountGroups, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let mut counts = group_counts(&client, conf, token)?;
+        let accepted = count_auth_devices(&client, conf, token, "accepted")? as i32;
+        let grouped: i32 = counts.values().sum();
+        counts.insert(String::from("ungrouped"), (accepted - grouped).max(0));
+        Ok(counts)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be countgroups and token must be provided in count_groups call",
+        ))))
+    }
+}
+
+/// List the ids of the devices of a group, page by page.
+fn list_group_devices(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    group: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut devices = vec![];
+    paginate(
+        conf,
+        client,
+        token,
+        &group_url(conf, group, &["devices"])?,
+        &[],
+        "group devices",
+        |mut res: Vec<String>| {
+            devices.append(&mut res);
+            true
+        },
+    )?;
+    Ok(devices)
+}
+
+/// List the ids of the devices of a group, an error is returned if there is none.
+pub fn group_devices(conf: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    if let (Command::GroupDevices { group }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let devices = list_group_devices(&client, conf, token, group)?;
+        if devices.is_empty() {
+            return Err(Box::new(MenderError::new(format!(
+                "Group {} not found or empty",
+                group
+            ))));
+        }
+        Ok(devices)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be groupdevices and token must be provided in group_devices call",
+        ))))
+    }
+}
+
+/// Count the devices of each group.
+fn group_counts(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+) -> Result<HashMap<String, i32>, Box<dyn Error>> {
+    let mut counts = HashMap::new();
+    for group in group_names(client, conf, token)? {
+        let count = group_device_count(client, conf, token, &group)?;
+        counts.insert(group, count);
+    }
+    Ok(counts)
+}
+
+/// List the groups with their number of devices.
+pub fn list_groups(conf: &Config) -> Result<HashMap<String, i32>, Box<dyn Error>> {
+    if let (Command::ListGroups, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        group_counts(&client, conf, token)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be listgroups and token must be provided in list_groups call",
+        ))))
+    }
+}
+
+/// List devices with their SerialNumber and group, one device per line. Devices can be
+/// selected by group, by a regex matching group names or by not being in any group.
+/// When an authentication status is given, devices having this status are listed as
+/// one JSON object per line with their id, status and identity data.
+pub fn list_devices(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::ListDevices {
+            group,
+            group_regex,
+            ungrouped,
+            status,
+            columns,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        if let Some(status) = status {
+            let mut devices: Vec<MenderAuthDevice> = vec![];
+            paginate(
+                conf,
+                &client,
+                token,
+                &format!("{}{}", &conf.server_url, GET_DEVICES_AUTH_API),
+                &[("status", status)],
+                "devices listing",
+                |mut res| {
+                    devices.append(&mut res);
+                    true
+                },
+            )?;
+            let mut disp = String::new();
+            for device in devices {
+                disp.push_str(&serde_json::to_string(&device)?);
+                disp.push('\n');
+            }
+            return Ok(disp);
+        }
+        progress!(conf, "Listing devices");
+        let devices = if *ungrouped {
+            ungrouped_devices(&client, conf, token)?
+        } else if let Some(group_regex) = group_regex {
+            let group_regex = regex::Regex::new(group_regex)?;
+            let mut devices = vec![];
+            for group in group_names(&client, conf, token)?
+                .iter()
+                .filter(|group| group_regex.is_match(group))
+            {
+                devices.append(&mut inventory_devices(
+                    &client,
+                    conf,
+                    token,
+                    &[("group", group)],
+                )?);
+            }
+            devices
+        } else if let Some(group) = group {
+            inventory_devices(&client, conf, token, &[("group", group)])?
+        } else {
+            inventory_devices(&client, conf, token, &[])?
+        };
+        progress!(conf, "\n");
+
+        let mut disp = String::new();
+        for device in devices {
+            disp.push_str(&device_row(&device, columns));
+        }
+        Ok(disp)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be listdevices and token must be provided in list_devices call",
+        ))))
+    }
+}
+
+/// List the devices which are not in any group, one device per line with its SerialNumber,
+/// followed by their total.
+pub fn get_devices_not_in_any_group(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::GetDevicesNotInAnyGroup, Some(token)) = (&conf.command, &conf.token) {
+        progress!(conf, "Searching devices not in any group");
+        let client = blocking_client(conf)?;
+        let devices = ungrouped_devices(&client, conf, token)?;
+        let mut found = String::new();
+        for device in &devices {
+            found.push_str(&format!(
+                "{}\t{}\n",
+                device.id,
+                device.attribute("SerialNumber").unwrap_or_default()
+            ));
+        }
+        found.push_str(&format!("Total: {} devices\n", devices.len()));
+        progress!(conf, "\n");
+        Ok(found)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getdevicesnotinanygroup and token must be provided in get_devices_not_in_any_group call",
+        ))))
+    }
+}
+
+/// Upload an artifact file and return its id. The file is streamed so that its size
+/// isn't limited by the memory, and the default timeout of the client is disabled as
+/// large files take long to upload, --request-timeout and --deadline still apply.
+/// The size of the file is first checked against the limits exposed by the server, so
+/// that an oversized artifact fails before being transmitted.
+pub fn upload_artifact(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::UploadArtifact { path, description }, Some(token)) =
+        (&conf.command, &conf.token)
+    {
+        let client = blocking_client_builder(conf)?.timeout(None).build()?;
+        let size = std::fs::metadata(path)?.len();
+        let max_artifact_size =
+            deployment_limits(&client, conf, token)?.and_then(|limits| limits.max_artifact_size);
+        let remaining_storage =
+            storage_limits(&client, conf, token)?.and_then(|limits| limits.remaining());
+        check_artifact_size(size, max_artifact_size, remaining_storage)
+            .map_err(MenderError::new)?;
+        let mut form = reqwest::blocking::multipart::Form::new().text("size", size.to_string());
+        if let Some(description) = description {
+            form = form.text("description", description.clone());
+        }
+        let artifact =
+            reqwest::blocking::multipart::Part::file(path)?.mime_str("application/octet-stream")?;
+        form = form.part("artifact", artifact);
+
+        progress!(
+            conf,
+            "Uploading {} ({} bytes){}\n",
+            path.display(),
+            size,
+            remaining_storage
+                .map(|remaining| format!(", {} bytes of storage left", remaining))
+                .unwrap_or_default()
+        );
+        let post_artifact = send(
+            conf,
+            &client,
+            client
+                .post(&format!("{}{}", &conf.server_url, ARTIFACTS_API))
+                .bearer_auth(token)
+                .multipart(form),
+        )?;
+        check_success!(post_artifact, "artifact upload");
+        post_artifact
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| location.rsplit('/').next())
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_string())
+            .ok_or_else(|| {
+                Box::new(MenderError::new(String::from(
+                    "Artifact uploaded but its id was not returned by the server",
+                ))) as Box<dyn Error>
+            })
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be uploadartifact and token must be provided in upload_artifact call",
+        ))))
+    }
+}
+
+/// Get the artifact_provides fields of an artifact, non string values are
+/// returned as their JSON representation.
+pub fn get_artifact_provides(conf: &Config) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    if let (Command::GetArtifactProvides { artifact_name }, Some(token)) =
+        (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let get_artifacts = send(
+            conf,
+            &client,
+            client
+                .get(&format!("{}{}", &conf.server_url, ARTIFACTS_API))
+                .bearer_auth(token),
+        )?;
+
+        check_success!(get_artifacts, "get artifact provides");
+        let artifacts: Vec<serde_json::Value> = get_artifacts.json()?;
+        let artifact = artifacts
+            .into_iter()
+            .find(|artifact| artifact["name"] == artifact_name.as_str())
+            .ok_or_else(|| MenderError::new(format!("Artifact {} not found", artifact_name)))?;
+        let mut provides = HashMap::new();
+        if let Some(artifact_provides) = artifact["artifact_provides"].as_object() {
+            for (key, value) in artifact_provides {
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                provides.insert(key.clone(), value);
+            }
+        }
+        Ok(provides)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getartifactprovides and token must be provided in get_artifact_provides call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderGroup {
+    group: Option<String>,
+}
+
+/// Get the static group of a device, None if it is in no group.
+fn device_group(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let get_group = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}/{}/group",
+                &conf.server_url, GET_DEVICES_INVENTORY_API, id
+            ))
+            .bearer_auth(token),
+    )?;
+
+    if get_group.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(device_not_found(id));
+    }
+    check_success!(get_group, "get device group");
+    Ok(get_group.json::<MenderGroup>()?.group)
+}
+
+/// List the groups of a device. Mender only supports one static group per device
+/// so the list holds at most one group.
+pub fn list_groups_for_device(conf: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    if let (Command::ListGroupsForDevice { device }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let device_id = resolve_device(conf, &client, token, device)?;
+        Ok(device_group(&client, conf, token, &device_id)?
+            .into_iter()
+            .collect())
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be listgroupsfordevice and token must be provided in list_groups_for_device call",
+        ))))
+    }
+}
+
+/// Get the static group of a device, "ungrouped" if it is in no group.
+pub fn get_group(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::GetGroup { device }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let device_id = resolve_device(conf, &client, token, device)?;
+        Ok(device_group(&client, conf, token, &device_id)?
+            .unwrap_or_else(|| String::from("ungrouped")))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getgroup and token must be provided in get_group call",
+        ))))
+    }
+}
+
+/// Tag holding the group changes of a device made with setgroup --record-history.
+const GROUP_HISTORY_TAG: &str = "group_history";
+/// Maximum number of entries kept in the group history, oldest ones are dropped.
+const GROUP_HISTORY_MAX_ENTRIES: usize = 20;
+/// Number of attempts to update the group history when it is modified concurrently.
+const GROUP_HISTORY_ATTEMPTS: usize = 3;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct GroupHistoryEntry {
+    timestamp: String,
+    from: Option<String>,
+    to: String,
+    actor: Option<String>,
+}
+
+/// Get the subject of a JWT token, None if it can't be decoded.
+fn token_actor(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    claims["sub"].as_str().map(|sub| sub.to_string())
+}
+
+/// Parse the group history tag, entries are sorted chronologically.
+fn group_history(device: &MenderDevice) -> Vec<GroupHistoryEntry> {
+    let mut history: Vec<GroupHistoryEntry> = device
+        .attribute(GROUP_HISTORY_TAG)
+        .and_then(|history| serde_json::from_str(&history).ok())
+        .unwrap_or_default();
+    history.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    history
+}
+
+/// Append a group change to the group history tag of a device. The tag is read and
+/// written back with the ETag of the device, the update is retried if the device has
+/// been modified in between.
+fn record_group_change(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+    from: Option<String>,
+    to: &str,
+) -> Result<(), Box<dyn Error>> {
+    let device_url = format!("{}{}/{}", &conf.server_url, GET_DEVICES_INVENTORY_API, id);
+    let entry = GroupHistoryEntry {
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        from,
+        to: to.to_string(),
+        actor: token_actor(token),
+    };
+    for _ in 0..GROUP_HISTORY_ATTEMPTS {
+        let get_device = send(conf, client, client.get(&device_url).bearer_auth(token))?;
+        check_success!(get_device, "group history read");
+        let etag = get_device.headers().get(reqwest::header::ETAG).cloned();
+        let mut history = group_history(&get_device.json()?);
+        history.push(entry.clone());
+        if history.len() > GROUP_HISTORY_MAX_ENTRIES {
+            history.drain(..history.len() - GROUP_HISTORY_MAX_ENTRIES);
+        }
+
+        let mut request = client
+            .patch(&format!("{}/tags", device_url))
+            .bearer_auth(token)
+            .json(&[AttributeUpdate {
+                name: GROUP_HISTORY_TAG.to_string(),
+                value: serde_json::Value::String(serde_json::to_string(&history)?),
+                scope: None,
+            }]);
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_MATCH, etag);
+        }
+        let patch_tags = send(conf, client, request)?;
+        if patch_tags.status() == reqwest::StatusCode::PRECONDITION_FAILED {
+            continue;
+        }
+        check_success!(patch_tags, "group history update");
+        return Ok(());
+    }
+    Err(Box::new(MenderError::new(format!(
+        "Group history of device {} modified concurrently, not updated after {} attempts",
+        id, GROUP_HISTORY_ATTEMPTS
+    ))))
+}
+
+#[derive(Serialize)]
+struct GroupUpdate<'a> {
+    group: &'a str,
+}
+
+/// Move a device to a group and return its id, the change is appended to the group
+/// history of the device if requested.
+pub fn set_group(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::SetGroup { device, group }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let id = resolve_device(conf, &client, token, device)?;
+        let previous_group = if conf.record_group_history {
+            device_inventory(&client, conf, token, &id)?.attribute("group")
+        } else {
+            None
+        };
+
+        if !put_group(&client, conf, token, &id, group)? {
+            return Err(device_not_found(&id));
+        }
+
+        if conf.record_group_history {
+            record_group_change(&client, conf, token, &id, previous_group, group)?;
+        }
+        Ok(id)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be setgroup and token must be provided in set_group call",
+        ))))
+    }
+}
+
+/// Add a device to a group and return its id with its previous group, if any.
+pub fn add_to_group(conf: &Config) -> Result<(String, Option<String>), Box<dyn Error>> {
+    if let (Command::AddToGroup { device, group }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let id = resolve_device(conf, &client, token, device)?;
+        let previous_group = device_group(&client, conf, token, &id)?;
+
+        if !put_group(&client, conf, token, &id, group)? {
+            return Err(device_not_found(&id));
+        }
+
+        if conf.record_group_history {
+            record_group_change(&client, conf, token, &id, previous_group.clone(), group)?;
+        }
+        Ok((id, previous_group))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be addtogroup and token must be provided in add_to_group call",
+        ))))
+    }
+}
+
+/// Remove a device from its group and return its id with the group it was removed from,
+/// None if it wasn't in any group. When a group is given the device must be in it.
+pub fn remove_from_group(conf: &Config) -> Result<(String, Option<String>), Box<dyn Error>> {
+    if let (Command::RemoveFromGroup { device, group }, Some(token)) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let id = resolve_device(conf, &client, token, device)?;
+        let current_group = match device_group(&client, conf, token, &id)? {
+            Some(current_group) => current_group,
+            None => return Ok((id, None)),
+        };
+        if let Some(group) = group.as_ref().filter(|group| *group != &current_group) {
+            return Err(Box::new(MenderError::new(format!(
+                "Device {} is in group {}, not in {}",
+                id, current_group, group
+            ))));
+        }
+
+        let delete_group = send(
+            conf,
+            &client,
+            client
+                .delete(&format!(
+                    "{}{}/{}/group/{}",
+                    &conf.server_url, GET_DEVICES_INVENTORY_API, id, current_group
+                ))
+                .bearer_auth(token),
+        )?;
+        if delete_group.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(device_not_found(&id));
+        }
+        check_success!(delete_group, "remove from group");
+        Ok((id, Some(current_group)))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be removefromgroup and token must be provided in remove_from_group call",
+        ))))
+    }
+}
+
+/// Set the group of a device, return false if the inventory doesn't know the device.
+fn put_group(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+    group: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let put_group = send(
+        conf,
+        client,
+        client
+            .put(&format!(
+                "{}{}/{}/group",
+                &conf.server_url, GET_DEVICES_INVENTORY_API, id
+            ))
+            .bearer_auth(token)
+            .json(&GroupUpdate { group }),
+    )?;
+    if put_group.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    check_success!(put_group, "set group");
+    Ok(true)
+}
+
+/// Move the devices of a deployment to a group, optionally only the ones whose
+/// deployment ended with the given status. Devices which are not in the inventory
+/// anymore, because they were decommissioned, are skipped. The other devices are all
+/// processed even if some fail, and an error is returned at the end if any did.
+pub fn set_group_from_deployment(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::SetGroupFromDeployment {
+            deployment_id,
+            group,
+            status,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let mut ids = vec![];
+        paginate(
+            conf,
+            &client,
+            token,
+            &format!(
+                "{}{}/{}/devices/list",
+                &conf.server_url, DEPLOY_API, deployment_id
+            ),
+            &[],
+            "deployment devices",
+            |res: Vec<MenderDeploymentDevice>| {
+                ids.extend(
+                    res.into_iter()
+                        .filter(|device| status.as_ref().is_none_or(|s| *s == device.status))
+                        .map(|device| device.id),
+                );
+                true
+            },
+        )?;
+        for id in &ids {
+            progress!(conf, "{}\n", id);
+        }
+        let impact = format!("{} devices to group {}", ids.len(), group);
+        match confirm("Set group from deployment", &impact, conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Group change aborted",
+                ))))
+            }
+            Decision::DryRun => return Ok(format!("{} devices would be moved", ids.len())),
+            Decision::Proceed => {}
+        }
+
+        let (mut moved, mut skipped, mut failed) = (0, 0, 0);
+        for (idx, id) in ids.iter().enumerate() {
+            progress!(conf, "[{}/{}] {}: ", idx + 1, ids.len(), id);
+            let previous_group = if conf.record_group_history {
+                device_inventory(&client, conf, token, id)
+                    .ok()
+                    .and_then(|device| device.attribute("group"))
+            } else {
+                None
+            };
+            let result = put_group(&client, conf, token, id, group).and_then(|found| {
+                if found && conf.record_group_history {
+                    record_group_change(&client, conf, token, id, previous_group, group)?;
+                }
+                Ok(found)
+            });
+            match result {
+                Ok(true) => {
+                    progress!(conf, "moved\n");
+                    moved += 1;
+                }
+                Ok(false) => {
+                    progress!(conf, "not in inventory, skipped\n");
+                    skipped += 1;
+                }
+                Err(err) => {
+                    progress!(conf, "failed: {}\n", err);
+                    failed += 1;
+                }
+            }
+        }
+        let summary = format!(
+            "{} devices moved to group {}, {} decommissioned devices skipped",
+            moved, group, skipped
+        );
+        if failed > 0 {
+            return Err(Box::new(MenderError::new(format!(
+                "{}, {} devices failed",
+                summary, failed
+            ))));
+        }
+        Ok(summary)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be setgroupfromdeployment and token must be provided in set_group_from_deployment call",
+        ))))
+    }
+}
+
+/// Create a group by moving devices into it. Groups only exist through their devices,
+/// so at least one is needed and a group which already has devices is refused. All the
+/// devices are processed even if some fail, an error is returned at the end if any did.
+pub fn create_group(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::CreateGroup { name, devices }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        if group_names(&client, conf, token)?.contains(name) {
+            return Err(Box::new(MenderError::new(format!(
+                "Group {} already exists, use addtogroup or assigngroup to add devices to it",
+                name
+            ))));
+        }
+        let impact = format!("group {} with {} devices", name, devices.len());
+        match confirm("Create group", &impact, conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Group creation aborted",
+                ))))
+            }
+            Decision::DryRun => return Ok(format!("Group {} would be created", name)),
+            Decision::Proceed => {}
+        }
+
+        let mut failed = 0;
+        for id in devices {
+            let previous_group = if conf.record_group_history {
+                device_inventory(&client, conf, token, id)
+                    .ok()
+                    .and_then(|device| device.attribute("group"))
+            } else {
+                None
+            };
+            let result = put_group(&client, conf, token, id, name).and_then(|found| {
+                if found && conf.record_group_history {
+                    record_group_change(&client, conf, token, id, previous_group, name)?;
+                }
+                Ok(found)
+            });
+            match result {
+                Ok(true) => progress!(conf, "{}: added\n", id),
+                Ok(false) => {
+                    progress!(conf, "{}: not in inventory\n", id);
+                    failed += 1;
+                }
+                Err(err) => {
+                    progress!(conf, "{}: failed: {}\n", id, err);
+                    failed += 1;
+                }
+            }
+        }
+        let summary = format!(
+            "Group {} created with {} devices",
+            name,
+            devices.len() - failed
+        );
+        if failed > 0 {
+            return Err(Box::new(MenderError::new(format!(
+                "{}, {} devices failed",
+                summary, failed
+            ))));
+        }
+        Ok(summary)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be creategroup and token must be provided in create_group call",
+        ))))
+    }
+}
+
+/// Delete a group after confirmation, its devices are left without a group.
+pub fn delete_group(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::DeleteGroup { name }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        if !group_names(&client, conf, token)?.contains(name) {
+            return Err(Box::new(MenderError::new(format!("No group {}", name))));
+        }
+        let nb_devices = group_device_count(&client, conf, token, name)?;
+        let impact = format!(
+            "group {}, its {} devices will have no group",
+            name, nb_devices
+        );
+        match confirm("Delete group", &impact, conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Group deletion aborted",
+                ))))
+            }
+            Decision::DryRun => return Ok(format!("Group {} would be deleted", name)),
+            Decision::Proceed => {}
+        }
+
+        let delete_group = send(
+            conf,
+            &client,
+            client
+                .delete(&group_url(conf, name, &[])?)
+                .bearer_auth(token),
+        )?;
+        if delete_group.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(Box::new(MenderError::new(format!("No group {}", name))));
+        }
+        check_success!(delete_group, "delete group");
+        Ok(format!(
+            "Group {} deleted, {} devices removed from it",
+            name, nb_devices
+        ))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be deletegroup and token must be provided in delete_group call",
+        ))))
+    }
+}
+
+/// Move all the devices of a group to another group, the progress is printed every
+/// progress_every devices. All the devices are processed even if some fail, the old
+/// group is then checked to be empty and an error listing the devices left in it is
+/// returned otherwise, the command can be run again to move them.
+pub fn rename_group(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::RenameGroup {
+            old,
+            new,
+            progress_every,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        if old == new {
+            return Err(Box::new(MenderError::new(String::from(
+                "The new group name must be different from the old one",
+            ))));
+        }
+        let client = blocking_client(conf)?;
+        let ids = list_group_devices(&client, conf, token, old)?;
+        if ids.is_empty() {
+            return Err(Box::new(MenderError::new(format!(
+                "Group {} has no devices",
+                old
+            ))));
+        }
+        let impact = format!("{} devices from group {} to group {}", ids.len(), old, new);
+        match confirm("Rename group", &impact, conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Group rename aborted",
+                ))))
+            }
+            Decision::DryRun => return Ok(format!("{} devices would be moved", ids.len())),
+            Decision::Proceed => {}
+        }
+
+        let mut failed = vec![];
+        for (idx, id) in ids.iter().enumerate() {
+            let result = put_group(&client, conf, token, id, new).and_then(|found| {
+                if found && conf.record_group_history {
+                    record_group_change(&client, conf, token, id, Some(old.clone()), new)?;
+                }
+                Ok(())
+            });
+            if let Err(err) = result {
+                progress!(conf, "{}: failed: {}\n", id, err);
+                failed.push(id.as_str());
+            }
+            if (idx + 1) % progress_every == 0 || idx + 1 == ids.len() {
+                progress!(
+                    conf,
+                    "{}/{} devices processed, {} failed\n",
+                    idx + 1,
+                    ids.len(),
+                    failed.len()
+                );
+            }
+        }
+
+        // The group disappears from the list once its last device is moved
+        let remaining = if group_names(&client, conf, token)?.contains(old) {
+            list_group_devices(&client, conf, token, old)?
+        } else {
+            vec![]
+        };
+        let summary = format!(
+            "{} devices moved from group {} to group {}",
+            ids.len() - failed.len(),
+            old,
+            new
+        );
+        if !remaining.is_empty() {
+            return Err(Box::new(MenderError::new(format!(
+                "{}, devices not moved, run renamegroup again to move them: {}",
+                summary,
+                remaining.join(" ")
+            ))));
+        }
+        if !failed.is_empty() {
+            // Failed requests may have been applied all the same
+            progress!(
+                conf,
+                "Devices which failed but are not in group {} anymore: {}\n",
+                old,
+                failed.join(" ")
+            );
+        }
+        Ok(summary)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be renamegroup and token must be provided in rename_group call",
+        ))))
+    }
+}
+
+/// Move the devices listed in a file to a group, one id or SerialNumber per line, empty
+/// lines and lines starting with # being skipped. All the lines are processed even if
+/// some fail, the result of each line is reported at the end and an error is returned
+/// if any failed.
+pub fn assign_group(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::AssignGroup { file, group }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let content = std::fs::read_to_string(file)?;
+        let lines: Vec<(usize, &str)> = content
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| (idx + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+
+        let mut results: Vec<(usize, &str, Result<String, String>)> = lines
+            .iter()
+            .map(|&(line_nb, line)| {
+                let id = DeviceRef::new(line)
+                    .map_err(|err| err.to_string())
+                    .and_then(|device| {
+                        resolve_device(conf, &client, token, &device).map_err(|err| err.to_string())
+                    });
+                (line_nb, line, id)
+            })
+            .collect();
+        let nb_devices = results.iter().filter(|(_, _, id)| id.is_ok()).count();
+        for (line_nb, line, id) in &results {
+            if let Ok(id) = id {
+                progress!(conf, "line {} {}: {}\n", line_nb, line, id);
+            }
+        }
+        let impact = format!("{} devices to group {}", nb_devices, group);
+        match confirm("Assign group", &impact, conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Group assignment aborted",
+                ))))
+            }
+            Decision::DryRun => return Ok(format!("{} devices would be assigned", nb_devices)),
+            Decision::Proceed => {}
+        }
+
+        for (_, _, result) in results.iter_mut() {
+            if let Ok(id) = result {
+                match put_group(&client, conf, token, id, group) {
+                    Ok(true) => {}
+                    Ok(false) => *result = Err(format!("device {} not in inventory", id)),
+                    Err(err) => *result = Err(err.to_string()),
+                }
+            }
+        }
+
+        let mut report = String::new();
+        let mut failed = 0;
+        for (line_nb, line, result) in &results {
+            match result {
+                Ok(id) => report.push_str(&format!("line {} {}: {} assigned\n", line_nb, line, id)),
+                Err(err) => {
+                    failed += 1;
+                    report.push_str(&format!("line {} {}: failed: {}\n", line_nb, line, err));
+                }
+            }
+        }
+        let summary = format!(
+            "{} devices assigned to group {}, {} failed",
+            results.len() - failed,
+            group,
+            failed
+        );
+        if failed > 0 {
+            progress!(conf, "{}", report);
+            return Err(Box::new(MenderError::new(summary)));
+        }
+        Ok(report + &summary)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be assigngroup and token must be provided in assign_group call",
+        ))))
+    }
+}
+
+impl AttributeCondition {
+    fn is_met(&self, value: &Option<String>) -> Result<bool, Box<dyn Error>> {
+        Ok(match (&self.operator, value) {
+            (Operator::Equal, Some(value)) => *value == self.value,
+            (Operator::NotEqual, value) => value.as_ref() != Some(&self.value),
+            (Operator::Match, Some(value)) => regex::Regex::new(&self.value)?.is_match(value),
+            (_, None) => false,
+        })
+    }
+}
+
+/// Poll the inventory of a device until the condition on one of its attributes is met,
+/// each change of the attribute value is printed. An error is returned on timeout.
+pub fn wait_attr(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::WaitAttr {
+            device,
+            condition,
+            timeout,
+            interval,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let id = resolve_device(conf, &client, token, device)?;
+
+        let start = std::time::Instant::now();
+        let mut last_value = None;
+        loop {
+            let value = device_inventory(&client, conf, token, &id)?.attribute(&condition.name);
+            if last_value.as_ref() != Some(&value) {
+                progress!(
+                    conf,
+                    "{} {} = {}\n",
+                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    condition.name,
+                    value.as_deref().unwrap_or("<not reported>")
+                );
+            }
+            if condition.is_met(&value)? {
+                return Ok(id);
+            }
+            if start.elapsed() + *interval > *timeout {
+                return Err(Box::new(MenderError::new(format!(
+                    "timeout waiting for {} of device {}",
+                    condition.name, id
+                ))));
+            }
+            last_value = Some(value);
+            std::thread::sleep(*interval);
+        }
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be waitattr and token must be provided in wait_attr call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct MenderDeploymentDevice {
+    id: String,
+    status: String,
+    /// Only reported by newer servers, like attempts.
+    substate: Option<String>,
+    attempts: Option<u32>,
+    created: Option<String>,
+    started: Option<String>,
+    finished: Option<String>,
+}
+
+impl MenderDeploymentDevice {
+    /// Whether the device hasn't finished the deployment after the given duration,
+    /// counted from its start or else from its creation.
+    fn is_stuck(&self, duration: std::time::Duration) -> bool {
+        let since = self
+            .started
+            .as_ref()
+            .or(self.created.as_ref())
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok());
+        !DeviceStatus::parse(&self.status).is_terminal()
+            && since.is_some_and(|since| {
+                chrono::Utc::now().signed_duration_since(since)
+                    >= chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX)
+            })
+    }
+}
+
+/// List the devices of a deployment with their status, substate, attempts and start
+/// time, the values not reported by the server are left blank. With --stuck only the
+/// unfinished devices started longer ago than the given duration are listed.
+pub fn deployment_devices(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::DeploymentDevices {
+            deployment_id,
+            stuck,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let mut devices = vec![];
+        paginate(
+            conf,
+            &client,
+            token,
+            &format!(
+                "{}{}/{}/devices/list",
+                &conf.server_url, DEPLOY_API, deployment_id
+            ),
+            &[],
+            "deployment devices",
+            |res: Vec<MenderDeploymentDevice>| {
+                devices.extend(
+                    res.into_iter()
+                        .filter(|device| stuck.is_none_or(|stuck| device.is_stuck(stuck))),
+                );
+                true
+            },
+        )?;
+        if conf.output_format == OutputFormat::Json {
+            return Ok(serde_json::to_string_pretty(&devices)? + "\n");
+        }
+        let mut disp = String::new();
+        for device in devices {
+            disp.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                device.id,
+                device.status,
+                device.substate.unwrap_or_default(),
+                device
+                    .attempts
+                    .map(|attempts| attempts.to_string())
+                    .unwrap_or_default(),
+                device.started.unwrap_or_default()
+            ));
+        }
+        Ok(disp)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be deploymentdevices and token must be provided in deployment_devices call",
+        ))))
+    }
+}
+
+/// Get the log of a device for a deployment.
+fn get_deployment_log(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    deployment_id: &str,
+    device_id: &str,
+) -> Result<String, Box<dyn Error>> {
+    let get_log = send(
+        conf,
+        client,
+        client
+            .get(&format!(
+                "{}{}/{}/devices/{}/log",
+                &conf.server_url, DEPLOY_API, deployment_id, device_id
+            ))
+            .bearer_auth(token),
+    )?;
+
+    check_success!(get_log, "deployment log");
+    Ok(get_log.text()?)
+}
+
+/// Return the last error line of the deployment log of each failed device.
+pub fn get_deployment_errors(conf: &Config) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    if let (Command::GetDeploymentErrors { deployment_id }, Some(token)) =
+        (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let get_devices = send(
+            conf,
+            &client,
+            client
+                .get(&format!(
+                    "{}{}/{}/devices",
+                    &conf.server_url, DEPLOY_API, deployment_id
+                ))
+                .bearer_auth(token),
+        )?;
+
+        check_success!(get_devices, "deployment errors");
+        let mut errors = HashMap::new();
+        for device in get_devices
+            .json::<Vec<MenderDeploymentDevice>>()?
+            .into_iter()
+            .filter(|device| device.status == "failure")
+        {
+            let log = get_deployment_log(&client, conf, token, deployment_id, &device.id)?;
+            let error = log
+                .lines()
+                .rev()
+                .find(|line| line.contains("error") || line.contains("ERROR"))
+                .unwrap_or("no error found in log");
+            errors.insert(device.id, error.trim().to_string());
+        }
+        Ok(errors)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getdeploymenterrors and token must be provided in get_deployment_errors call",
+        ))))
+    }
+}
+
+/// Check the health of one or all Mender services, an error is returned if one
+/// of them is unhealthy.
+pub fn check_health(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let Command::CheckHealth { service } = &conf.command {
+        let client = blocking_client(conf)?;
+        let mut disp = String::new();
+        let mut healthy = true;
+        for (name, api) in HEALTH_APIS
+            .iter()
+            .filter(|(name, _)| service.as_ref().is_none_or(|service| service == name))
+        {
+            let mut request = client.get(&format!("{}{}", &conf.server_url, api));
+            if let Some(token) = &conf.token {
+                request = request.bearer_auth(token);
+            }
+            let status = send(conf, &client, request)?.status();
+            if status.is_success() {
+                disp.push_str(&format!("{}: OK\n", name));
+            } else {
+                healthy = false;
+                disp.push_str(&format!(
+                    "{}: UNHEALTHY (status {})\n",
+                    name,
+                    status.as_u16()
+                ));
+            }
+        }
+        if healthy {
+            Ok(disp)
+        } else {
+            progress!(conf, "{}", disp);
+            Err(Box::new(MenderError::new(String::from(
+                "Some services are unhealthy",
+            ))))
+        }
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be checkhealth in check_health call",
+        ))))
+    }
+}
+
+/// What the selftest steps work on.
+struct SelfTest<'a> {
+    conf: &'a Config,
+    client: reqwest::blocking::Client,
+    token: &'a str,
+    device: &'a str,
+    artifact: &'a str,
+}
+
+/// A selftest step, returning a short description of what it found.
+type SelfTestStep = fn(&SelfTest) -> Result<String, Box<dyn Error>>;
+
+/// Steps run by selftest in this order, with whether they change anything on the server.
+/// The steps changing something are skipped unless --allow-mutations is given.
+const SELFTEST_STEPS: [(&str, bool, SelfTestStep); 7] = [
+    ("whoami", false, selftest_whoami),
+    ("getinfo", false, selftest_get_info),
+    ("listgroups", false, selftest_list_groups),
+    ("listartifacts", false, selftest_list_artifacts),
+    ("listdeployments", false, selftest_list_deployments),
+    ("deploy --dry-run", false, selftest_dry_run_deploy),
+    ("deploy and abort", true, selftest_deploy_and_abort),
+];
+
+#[derive(Deserialize, Debug)]
+struct MenderUser {
+    email: String,
+}
+
+fn selftest_whoami(test: &SelfTest) -> Result<String, Box<dyn Error>> {
+    let get_user = send(
+        test.conf,
+        &test.client,
+        test.client
+            .get(&format!("{}{}", &test.conf.server_url, CURRENT_USER_API))
+            .bearer_auth(test.token),
+    )?;
+    check_success!(get_user, "whoami");
+    Ok(get_user.json::<MenderUser>()?.email)
+}
+
+fn selftest_get_info(test: &SelfTest) -> Result<String, Box<dyn Error>> {
+    let info = device_info(test.conf, &test.client, test.token, test.device)?;
+    Ok(format!(
+        "{} attributes",
+        info["attributes"]
+            .as_array()
+            .map_or(0, |attributes| attributes.len())
+    ))
+}
+
+fn selftest_list_groups(test: &SelfTest) -> Result<String, Box<dyn Error>> {
+    let groups = group_names(&test.client, test.conf, test.token)?;
+    Ok(format!("{} groups", groups.len()))
+}
+
+fn selftest_list_artifacts(test: &SelfTest) -> Result<String, Box<dyn Error>> {
+    let artifacts = stored_artifacts(&test.client, test.conf, test.token)?;
+    if !artifacts
+        .iter()
+        .any(|artifact| artifact.name == test.artifact)
+    {
+        return Err(Box::new(MenderError::new(format!(
+            "artifact {} not found among {} artifacts",
+            test.artifact,
+            artifacts.len()
+        ))));
+    }
+    Ok(format!("{} artifacts", artifacts.len()))
+}
+
+fn selftest_list_deployments(test: &SelfTest) -> Result<String, Box<dyn Error>> {
+    let deployments = list_deployments(&test.client, test.conf, test.token, None)?;
+    Ok(format!("{} deployments", deployments.len()))
+}
+
+/// Run the checks done by deploy before posting a deployment of the artifact to the
+/// device.
+fn selftest_dry_run_deploy(test: &SelfTest) -> Result<String, Box<dyn Error>> {
+    check_deployment_limits(&test.client, test.conf, test.token, 1)?;
+    Ok(String::from("deployment to 1 device within the limits"))
+}
+
+/// Deploy the artifact to the device and abort the deployment right away.
+fn selftest_deploy_and_abort(test: &SelfTest) -> Result<String, Box<dyn Error>> {
+    let name = format!("selftest-{}", chrono::Utc::now().timestamp());
+    let post_deploy = send(
+        test.conf,
+        &test.client,
+        test.client
+            .post(&format!("{}{}", &test.conf.server_url, DEPLOY_API))
+            .bearer_auth(test.token)
+            .json(&DeployData {
+                artifact_name: test.artifact,
+                name: &name,
+                devices: vec![test.device.to_string()],
+                phases: None,
+            }),
+    )?;
+    check_success!(post_deploy, "deployment");
+    let id = deployment_id(&test.client, test.conf, test.token, &post_deploy, &name)?.ok_or_else(
+        || {
+            MenderError::new(format!(
+                "deployment {} created but its id is unknown, it must be aborted by hand",
+                name
+            ))
+        },
+    )?;
+    abort(&test.client, test.conf, test.token, &id)?;
+    Ok(format!("deployment {} created and aborted", id))
+}
+
+/// Run the selftest steps against the server and report for each one whether it passed
+/// and how long it took. The steps go on after a failure, an error is returned at the
+/// end if one of them failed.
+pub fn selftest(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::SelfTest {
+            device,
+            artifact,
+            allow_mutations,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        let test = SelfTest {
+            conf,
+            client: blocking_client(conf)?,
+            token,
+            device,
+            artifact,
+        };
+        let mut disp = String::new();
+        let mut failures = 0;
+        for (name, mutating, step) in SELFTEST_STEPS.iter() {
+            if *mutating && (!allow_mutations || conf.dry_run) {
+                disp.push_str(&format!("{:<20} SKIPPED  changes the server\n", name));
+                continue;
+            }
+            let started = std::time::Instant::now();
+            let result = step(&test);
+            let elapsed = started.elapsed().as_millis();
+            match result {
+                Ok(found) => {
+                    disp.push_str(&format!("{:<20} PASS {:>6} ms  {}\n", name, elapsed, found))
+                }
+                Err(err) => {
+                    failures += 1;
+                    disp.push_str(&format!("{:<20} FAIL {:>6} ms  {}\n", name, elapsed, err))
+                }
+            }
+        }
+        if failures == 0 {
+            Ok(disp)
+        } else {
+            progress!(conf, "{}", disp);
+            Err(Box::new(MenderError::new(format!(
+                "{} selftest steps failed",
+                failures
+            ))))
+        }
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be selftest and token must be provided in selftest call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderDeploymentDetails {
+    name: String,
+    artifact_name: String,
+    groups: Option<Vec<String>>,
+    retries: Option<u64>,
+    max_devices: Option<u64>,
+    phases: Option<Vec<serde_json::Value>>,
+}
+
+/// Deployment specification as written in YAML files.
+#[derive(Serialize, Debug)]
+struct DeploymentSpec {
+    name: String,
+    artifact_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    devices: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retries: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_devices: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phases: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Serialize)]
+struct DeploymentStatusUpdate<'a> {
+    status: &'a str,
+}
+
+/// Set the status of a deployment to aborted.
+fn abort(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    deployment_id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let put_status = send(
+        conf,
+        client,
+        client
+            .put(&format!(
+                "{}{}/{}/status",
+                &conf.server_url, DEPLOY_API, deployment_id
+            ))
+            .bearer_auth(token)
+            .json(&DeploymentStatusUpdate { status: "aborted" }),
+    )?;
+    if put_status.status() == reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+        return Err(Box::new(MenderError::new(format!(
+            "Deployment {} can't be aborted anymore",
+            deployment_id
+        ))));
+    }
+    check_success!(put_status, "abort deployment");
+    Ok(())
+}
+
+/// Abort a deployment after confirmation, the deployment is described first. Return
+/// None on dry run.
+pub fn abort_deployment(conf: &Config) -> Result<Option<String>, Box<dyn Error>> {
+    if let (Command::AbortDeployment { deployment_id }, Some(token)) = (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let get_deployment = send(
+            conf,
+            &client,
+            client
+                .get(&format!(
+                    "{}{}/{}",
+                    &conf.server_url, DEPLOY_API, deployment_id
+                ))
+                .bearer_auth(token),
+        )?;
+        if get_deployment.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(Box::new(MenderError::new(format!(
+                "No deployment with id {}",
+                deployment_id
+            ))));
+        }
+        check_success!(get_deployment, "get deployment");
+        let deployment = get_deployment.json::<MenderDeployment>()?;
+        if deployment
+            .status
+            .as_deref()
+            .is_some_and(|status| DeploymentStatus::parse(status) == DeploymentStatus::Finished)
+        {
+            return Err(Box::new(MenderError::new(format!(
+                "Deployment {} is already finished",
+                deployment_id
+            ))));
+        }
+        match confirm("Abort deployment", &display_deployment(&deployment), conf)? {
+            Decision::Abort => {
+                return Err(Box::new(MenderError::new(String::from(
+                    "Deployment abort cancelled",
+                ))))
+            }
+            Decision::DryRun => return Ok(None),
+            Decision::Proceed => {}
+        }
+
+        abort(&client, conf, token, deployment_id)?;
+        Ok(Some(deployment.id))
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be abortdeployment and token must be provided in abort_deployment call",
+        ))))
+    }
+}
+
+/// Export a deployment as a YAML specification. Deployments to a single group are
+/// exported with their group, others with the list of their devices.
+pub fn get_deployment_yaml(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::GetDeploymentYaml { deployment_id, .. }, Some(token)) =
+        (&conf.command, &conf.token)
+    {
+        let client = blocking_client(conf)?;
+        let get_deployment = send(
+            conf,
+            &client,
+            client
+                .get(&format!(
+                    "{}{}/{}",
+                    &conf.server_url, DEPLOY_API, deployment_id
+                ))
+                .bearer_auth(token),
+        )?;
+
+        check_success!(get_deployment, "get deployment");
+        let deployment = get_deployment.json::<MenderDeploymentDetails>()?;
+        let group = match deployment.groups {
+            Some(groups) if groups.len() == 1 => groups.into_iter().next(),
+            _ => None,
+        };
+        let devices = if group.is_none() {
+            let get_devices = send(
+                conf,
+                &client,
+                client
+                    .get(&format!(
+                        "{}{}/{}/devices",
+                        &conf.server_url, DEPLOY_API, deployment_id
+                    ))
+                    .bearer_auth(token),
+            )?;
+
+            check_success!(get_devices, "get deployment devices");
+            Some(
+                get_devices
+                    .json::<Vec<MenderDeploymentDevice>>()?
+                    .into_iter()
+                    .map(|device| device.id)
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        let spec = DeploymentSpec {
+            name: deployment.name,
+            artifact_name: deployment.artifact_name,
+            group,
+            devices,
+            retries: deployment.retries,
+            max_devices: deployment.max_devices,
+            phases: deployment.phases,
+        };
+        Ok(serde_yaml::to_string(&spec)?)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getdeploymentyaml and token must be provided in get_deployment_yaml call",
+        ))))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FilterDefinition {
+    name: String,
+    terms: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderSavedFilter {
+    id: String,
+    #[serde(flatten)]
+    definition: FilterDefinition,
+}
+
+fn saved_filters(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+) -> Result<Vec<MenderSavedFilter>, Box<dyn Error>> {
+    let get_filters = send(
+        conf,
+        client,
+        client
+            .get(&format!("{}{}", &conf.server_url, FILTERS_API))
+            .bearer_auth(token),
+    )?;
+
+    check_success!(get_filters, "filters listing");
+    Ok(get_filters.json()?)
+}
+
+/// Export the definitions of all saved filters as JSON.
+pub fn export_filters(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::ExportFilters { .. }, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let definitions: Vec<FilterDefinition> = saved_filters(&client, conf, token)?
+            .into_iter()
+            .map(|filter| filter.definition)
+            .collect();
+        Ok(serde_json::to_string_pretty(&definitions)?)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be exportfilters and token must be provided in export_filters call",
+        ))))
+    }
+}
+
+/// Create a saved filter, the server response is returned as error message on failure.
+fn create_filter(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    definition: &FilterDefinition,
+) -> Result<(), Box<dyn Error>> {
+    let post_filter = send(
+        conf,
+        client,
+        client
+            .post(&format!("{}{}", &conf.server_url, FILTERS_API))
+            .bearer_auth(token)
+            .json(definition),
+    )?;
+
+    check_success!(post_filter, "filter creation");
+    Ok(())
+}
+
+fn delete_filter(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let delete_filter = send(
+        conf,
+        client,
+        client
+            .delete(&format!("{}{}/{}", &conf.server_url, FILTERS_API, id))
+            .bearer_auth(token),
+    )?;
+
+    check_success!(delete_filter, "filter deletion");
+    Ok(())
+}
+
+/// Import saved filters from a file written by export_filters. Filters whose name
+/// already exists are skipped unless overwrite is set, in which case they are deleted
+/// and created again. The result of each filter is printed and an error is returned
+/// if any of them failed.
+pub fn import_filters(conf: &Config) -> Result<usize, Box<dyn Error>> {
+    if let (Command::ImportFilters { file, overwrite }, Some(token)) = (&conf.command, &conf.token)
+    {
+        let definitions: Vec<FilterDefinition> = serde_json::from_reader(File::open(file)?)?;
+        let client = blocking_client(conf)?;
+        let existing = saved_filters(&client, conf, token)?;
+        let mut nb_imported = 0;
+        let mut nb_failed = 0;
+        for definition in definitions {
+            let previous = existing
+                .iter()
+                .find(|filter| filter.definition.name == definition.name);
+            let result = match previous {
+                Some(_) if !overwrite => {
+                    progress!(conf, "{}: skipped, already exists\n", definition.name);
+                    continue;
+                }
+                Some(previous) => delete_filter(&client, conf, token, &previous.id)
+                    .and_then(|_| create_filter(&client, conf, token, &definition)),
+                None => create_filter(&client, conf, token, &definition),
+            };
+            match result {
+                Ok(()) => {
+                    nb_imported += 1;
+                    progress!(
+                        conf,
+                        "{}: {}\n",
+                        definition.name,
+                        if previous.is_some() {
+                            "overwritten"
+                        } else {
+                            "imported"
+                        }
+                    );
+                }
+                Err(err) => {
+                    nb_failed += 1;
+                    progress!(conf, "{}: failed, {}\n", definition.name, err);
+                }
+            }
+        }
+        if nb_failed > 0 {
+            return Err(Box::new(MenderError::new(format!(
+                "{} filters imported, {} failed",
+                nb_imported, nb_failed
+            ))));
+        }
+        Ok(nb_imported)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be importfilters and token must be provided in import_filters call",
+        ))))
+    }
+}
+
+/// Summary of the numeric values of an attribute with a text histogram, devices not
+/// reporting the attribute and non numeric values are counted apart.
+pub fn stats_attr(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (
+        Command::StatsAttr {
+            attribute,
+            group,
+            buckets,
+        },
+        Some(token),
+    ) = (&conf.command, &conf.token)
+    {
+        progress!(conf, "Inventoring {} of devices", attribute);
+        let client = blocking_client(conf)?;
+        let query: Vec<(&str, &str)> = group
+            .iter()
+            .map(|group| ("group", group.as_str()))
+            .collect();
+        let mut values = vec![];
+        let (mut nb_missing, mut nb_non_numeric) = (0, 0);
+        for device in inventory_devices(&client, conf, token, &query)? {
+            match device
+                .attribute(attribute)
+                .map(|value| value.parse::<f64>())
+            {
+                Some(Ok(value)) if value.is_finite() => values.push(value),
+                Some(_) => nb_non_numeric += 1,
+                None => nb_missing += 1,
+            }
+        }
+        progress!(conf, "\n");
+
+        let mut disp = format!(
+            "count: {}\nmissing: {}\nnon numeric: {}\n",
+            values.len(),
+            nb_missing,
+            nb_non_numeric
+        );
+        if values.is_empty() {
+            return Ok(disp);
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let (min, max) = (values[0], values[values.len() - 1]);
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let median = if values.len() % 2 == 0 {
+            (values[values.len() / 2 - 1] + values[values.len() / 2]) / 2.0
+        } else {
+            values[values.len() / 2]
+        };
+        disp.push_str(&format!(
+            "min: {}\nmax: {}\nmean: {:.2}\nmedian: {}\n",
+            min, max, mean, median
+        ));
+
+        let width = (max - min) / *buckets as f64;
+        let mut histogram = vec![0; *buckets];
+        for value in &values {
+            let bucket = if width > 0.0 {
+                (((value - min) / width) as usize).min(buckets - 1)
+            } else {
+                0
+            };
+            histogram[bucket] += 1;
+        }
+        let largest = *histogram.iter().max().unwrap();
+        for (idx, count) in histogram.iter().enumerate() {
+            disp.push_str(&format!(
+                "{:>14.2} - {:<14.2} {:>6} {}\n",
+                min + width * idx as f64,
+                min + width * (idx + 1) as f64,
+                count,
+                "#".repeat(count * 40 / largest)
+            ));
+        }
+        Ok(disp)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be statsattr and token must be provided in stats_attr call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct MenderArtifact {
+    id: String,
+    name: String,
+    description: Option<String>,
+    device_types_compatible: Vec<String>,
+    modified: String,
+}
+
+/// List all artifacts stored on the server, page by page.
+fn stored_artifacts(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+) -> Result<Vec<MenderArtifact>, Box<dyn Error>> {
+    let mut artifacts = vec![];
+    paginate(
+        conf,
+        client,
+        token,
+        &format!("{}{}", &conf.server_url, ARTIFACTS_LIST_API),
+        &[],
+        "artifacts listing",
+        |mut res| {
+            artifacts.append(&mut res);
+            true
+        },
+    )?;
+    Ok(artifacts)
+}
+
+/// List the artifacts stored on the server sorted by name, one per line with their
+/// compatible device types, modification date and description, or as JSON.
+pub fn list_artifacts(conf: &Config) -> Result<String, Box<dyn Error>> {
+    if let (Command::ListArtifacts, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let mut artifacts = stored_artifacts(&client, conf, token)?;
+        artifacts.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| a.modified.cmp(&b.modified))
+        });
+        if conf.output_format == OutputFormat::Json {
+            return Ok(serde_json::to_string_pretty(&artifacts)? + "\n");
+        }
+        let mut disp = String::new();
+        for artifact in artifacts {
+            disp.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                artifact.name,
+                artifact.device_types_compatible.join(","),
+                artifact.modified,
+                artifact.description.unwrap_or_default()
+            ));
+        }
+        Ok(disp)
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be listartifacts and token must be provided in list_artifacts call",
+        ))))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MenderDownloadLink {
+    uri: String,
+}
+
+/// Compute the SHA-256 of the body of a response as lowercase hexadecimal, the progress
+/// is printed when the size of the body is known.
+fn sha256_hex(
+    conf: &Config,
+    mut content: reqwest::blocking::Response,
+    size: Option<u64>,
+) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    let mut chunk = [0; 64 * 1024];
+    let (mut read, mut percent) = (0, None);
+    loop {
+        let len = content.read(&mut chunk)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&chunk[..len]);
+        read += len as u64;
+        if let Some(size) = size.filter(|size| *size > 0) {
+            let new_percent = read * 100 / size;
+            if percent != Some(new_percent) {
+                progress!(conf, "\r{}%", new_percent);
+                percent = Some(new_percent);
+            }
+        }
+    }
+    if percent.is_some() {
+        progress!(conf, "\n");
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Download the artifacts with the given name and check their SHA-256. Mender doesn't
+/// store the checksum of whole artifacts, only the one of their payloads, so artifacts
+/// are hashed locally. All the artifacts with this name, one per set of device types,
+/// must match.
+fn check_artifact_checksum(
+    client: &reqwest::blocking::Client,
+    conf: &Config,
+    token: &str,
+    artifact_name: &str,
+    expected: &str,
+) -> Result<(), Box<dyn Error>> {
+    let artifacts: Vec<MenderArtifact> = stored_artifacts(client, conf, token)?
+        .into_iter()
+        .filter(|artifact| artifact.name == artifact_name)
+        .collect();
+    if artifacts.is_empty() {
+        return Err(Box::new(MenderError::new(format!(
+            "Artifact {} not found",
+            artifact_name
+        ))));
+    }
+    // Artifacts can take longer than the default timeout of the client to download
+    let download_client = blocking_client_builder(conf)?.timeout(None).build()?;
+    for artifact in artifacts {
+        let get_link = send(
+            conf,
+            client,
+            client
+                .get(&format!(
+                    "{}{}/{}/download",
+                    &conf.server_url, ARTIFACTS_API, artifact.id
+                ))
+                .bearer_auth(token),
+        )?;
+        check_success!(get_link, "artifact download link");
+        let link: MenderDownloadLink = get_link.json()?;
+
+        progress!(
+            conf,
+            "Downloading artifact {} for {} to check its checksum\n",
+            artifact.name,
+            artifact.device_types_compatible.join(", ")
+        );
+        let download = send(conf, &download_client, download_client.get(&link.uri))?;
+        check_success!(download, "artifact download");
+        let size = download.content_length();
+        let checksum = sha256_hex(conf, download, size)?;
+        if checksum != expected {
+            return Err(Box::new(MenderError::new(format!(
+                "Checksum of artifact {} ({}) is {} instead of {}, not deploying",
+                artifact.name, artifact.id, checksum, expected
+            ))));
+        }
+    }
+    progress!(conf, "Artifact checksum verified\n");
+    Ok(())
+}
+
+/// Return the most recently modified artifact of each device type.
+pub fn get_latest_artifact_per_device_type(
+    conf: &Config,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    if let (Command::GetLatestArtifactPerDeviceType, Some(token)) = (&conf.command, &conf.token) {
+        let client = blocking_client(conf)?;
+        let mut latest: HashMap<String, (chrono::DateTime<chrono::FixedOffset>, String)> =
+            HashMap::new();
+        for artifact in stored_artifacts(&client, conf, token)? {
+            let modified = chrono::DateTime::parse_from_rfc3339(&artifact.modified)?;
+            for device_type in artifact.device_types_compatible {
+                match latest.get(&device_type) {
+                    Some((latest_modified, _)) if *latest_modified >= modified => (),
+                    _ => {
+                        latest.insert(device_type, (modified, artifact.name.clone()));
+                    }
+                }
+            }
+        }
+        Ok(latest
+            .into_iter()
+            .map(|(device_type, (_, name))| (device_type, name))
+            .collect())
+    } else {
+        Err(Box::new(MenderError::new(String::from(
+            "Command must be getlatestartifactperdevicetype and token must be provided in get_latest_artifact_per_device_type call",
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{self, MockMenderServer};
+    use mockito::Matcher;
+
+    #[test]
+    fn devices_not_in_any_group_use_has_group() {
+        let mut server = MockMenderServer::new();
+        let devices = server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("has_group".into(), "false".into()),
+                Matcher::UrlEncoded("page".into(), "1".into()),
+            ]))
+            .with_body(r#"[{"id": "a1", "attributes": [{"name": "SerialNumber", "value": "S1"}]}]"#)
+            .create();
+        server.empty_page(GET_DEVICES_INVENTORY_API, 2);
+        let conf = server.config(&["getdevicesnotinanygroup"]);
+
+        let found = get_devices_not_in_any_group(&conf).unwrap();
+        devices.assert();
+        assert_eq!(found, "a1\tS1\nTotal: 1 devices\n");
+    }
+
+    #[test]
+    fn devices_not_in_any_group_without_has_group_support() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(
+                r#"[
+                    {"id": "a1", "attributes": [{"name": "group", "value": "prod"}]},
+                    {"id": "a2", "attributes": [{"name": "SerialNumber", "value": "S2"}]}
+                ]"#,
+            )
+            .create();
+        server.empty_page(GET_DEVICES_INVENTORY_API, 2);
+        let conf = server.config(&["getdevicesnotinanygroup"]);
+
+        let found = get_devices_not_in_any_group(&conf).unwrap();
+        assert_eq!(found, "a2\tS2\nTotal: 1 devices\n");
+    }
+
+    #[test]
+    fn find_link_among_multiple_rels() {
+        let header = r#"<https://mender.test/items?page=1>; rel="first", <https://mender.test/items?page=3>; rel="next", <https://mender.test/items?page=1>; rel="prev""#;
+        assert_eq!(
+            find_link(header, "next"),
+            Some("https://mender.test/items?page=3".to_string())
+        );
+        assert_eq!(
+            find_link(r#"</items?page=2>; rel="prev next""#, "next"),
+            Some("/items?page=2".to_string())
+        );
+    }
+
+    #[test]
+    fn find_link_quoted_and_unquoted_rel() {
+        assert_eq!(
+            find_link(r#"</items?page=2>; rel="next""#, "next"),
+            Some("/items?page=2".to_string())
+        );
+        assert_eq!(
+            find_link("</items?page=2>; rel=next", "next"),
+            Some("/items?page=2".to_string())
+        );
+        assert_eq!(
+            find_link("</items?page=2>; REL=Next", "next"),
+            Some("/items?page=2".to_string())
+        );
+        // Commas and semicolons in the URI or in quoted strings don't split the links
+        assert_eq!(
+            find_link(r#"</items?a=1,2;3>; title="a, b; c"; rel="next""#, "next"),
+            Some("/items?a=1,2;3".to_string())
+        );
+    }
+
+    #[test]
+    fn find_link_without_next() {
+        assert_eq!(
+            find_link(
+                r#"</items?page=1>; rel="first", </items?page=1>; rel="prev""#,
+                "next"
+            ),
+            None
+        );
+        assert_eq!(find_link("</items?page=2>", "next"), None);
+        assert_eq!(find_link("", "next"), None);
+        assert_eq!(find_link(r#"/items?page=2; rel="next""#, "next"), None);
+    }
+
+    #[test]
+    fn find_link_whitespace() {
+        assert_eq!(
+            find_link(
+                "  </items?page=1> ; rel = \"prev\" ,\t</items?page=3>  ;  rel=\"next\"  ",
+                "next"
+            ),
+            Some("/items?page=3".to_string())
+        );
+    }
+
+    #[test]
+    fn paginate_mixes_page_numbers_and_links() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", "/items")
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body("[1, 2]")
+            .create();
+        server
+            .mock("GET", "/items")
+            .match_query(Matcher::UrlEncoded("page".into(), "2".into()))
+            .with_header("link", r#"</items?cursor=c3>; rel="next""#)
+            .with_body("[3]")
+            .create();
+        server
+            .mock("GET", "/items")
+            .match_query(Matcher::UrlEncoded("cursor".into(), "c3".into()))
+            .with_header("link", r#"</items?page=1>; rel="first""#)
+            .with_body("[4]")
+            .create();
+        let conf = server.config(&["showconfig"]);
+        let client = blocking_client(&conf).unwrap();
+
+        let mut items = vec![];
+        paginate(
+            &conf,
+            &client,
+            mock_server::TOKEN,
+            &format!("{}/items", conf.server_url),
+            &[],
+            "items listing",
+            |mut page: Vec<u32>| {
+                items.append(&mut page);
+                true
+            },
+        )
+        .unwrap();
+        assert_eq!(items, vec![1, 2, 3, 4]);
+    }
+
+    /// Serve the items by pages of per_page items, pages after last_page are the same
+    /// as it when given, to simulate a server ignoring the page parameter.
+    fn serve_pages(
+        server: &mut MockMenderServer,
+        items: usize,
+        last_page: Option<usize>,
+    ) -> mockito::Mock {
+        server
+            .mock("GET", "/items")
+            .match_query(Matcher::Any)
+            .with_body_from_request(move |request| {
+                let url = reqwest::Url::parse("http://mender.test")
+                    .unwrap()
+                    .join(request.path_and_query())
+                    .unwrap();
+                let param = |name: &str| -> usize {
+                    url.query_pairs()
+                        .find(|(key, _)| key == name)
+                        .unwrap()
+                        .1
+                        .parse()
+                        .unwrap()
+                };
+                let per_page = param("per_page");
+                let page = last_page.map_or(param("page"), |last| param("page").min(last));
+                let page: Vec<usize> = (1..=items)
+                    .skip((page - 1) * per_page)
+                    .take(per_page)
+                    .collect();
+                serde_json::to_vec(&page).unwrap()
+            })
+    }
+
+    fn list_items(conf: &Config) -> Result<Vec<usize>, Box<dyn Error>> {
+        let client = blocking_client(conf)?;
+        let mut items = vec![];
+        paginate(
+            conf,
+            &client,
+            mock_server::TOKEN,
+            &format!("{}/items", conf.server_url),
+            &[],
+            "items listing",
+            |mut page: Vec<usize>| {
+                items.append(&mut page);
+                true
+            },
+        )?;
+        Ok(items)
+    }
+
+    #[test]
+    fn paginate_with_1_and_4_concurrent_requests() {
+        for concurrent_requests in &["1", "4"] {
+            for nb_items in &[0, 1, 6, 7, 23] {
+                let mut server = MockMenderServer::new();
+                serve_pages(&mut server, *nb_items, None).create();
+                let mut conf =
+                    server.config(&["showconfig", "--concurrent-requests", concurrent_requests]);
+                conf.pagination.per_page = 2;
+
+                assert_eq!(
+                    list_items(&conf).unwrap(),
+                    (1..=*nb_items).collect::<Vec<_>>(),
+                    "{} items with {} concurrent requests",
+                    nb_items,
+                    concurrent_requests
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn concurrent_pagination_stops_at_first_short_page() {
+        let mut server = MockMenderServer::new();
+        // Pages after the 4th one repeat it, so only the short page can end the listing
+        let pages = serve_pages(&mut server, 7, Some(4))
+            .expect_at_most(4 + 4)
+            .create();
+        let mut conf = server.config(&["showconfig", "--concurrent-requests", "4"]);
+        conf.pagination.per_page = 2;
+
+        assert_eq!(list_items(&conf).unwrap(), (1..=7).collect::<Vec<_>>());
+        pages.assert();
+    }
+
+    #[test]
+    fn concurrent_pagination_follows_links() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", "/items")
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_header("link", r#"</items?cursor=c2>; rel="next""#)
+            .with_body("[1, 2]")
+            .create();
+        server
+            .mock("GET", "/items")
+            .match_query(Matcher::UrlEncoded("cursor".into(), "c2".into()))
+            .with_body("[3]")
+            .with_header("link", r#"</items?page=1>; rel="first""#)
+            .create();
+        let mut conf = server.config(&["showconfig", "--concurrent-requests", "4"]);
+        conf.pagination.per_page = 2;
+
+        assert_eq!(list_items(&conf).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn deadline_aborts_listing() {
+        for concurrent_requests in &["1", "4"] {
+            let mut server = MockMenderServer::new();
+            // Pages 1 to 3 are served at once, the next ones after the deadline
+            server
+                .mock("GET", "/items")
+                .match_query(Matcher::Any)
+                .with_body_from_request(|request| {
+                    let page: usize = request
+                        .path_and_query()
+                        .rsplit("page=")
+                        .next()
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if page > 3 {
+                        std::thread::sleep(std::time::Duration::from_secs(2));
+                    }
+                    serde_json::to_vec(&[page * 2 - 1, page * 2]).unwrap()
+                })
+                .create();
+            let mut conf = server.config(&[
+                "showconfig",
+                "--deadline",
+                "1s",
+                "--concurrent-requests",
+                concurrent_requests,
+            ]);
+            conf.pagination.per_page = 2;
+
+            let start = std::time::Instant::now();
+            let err = list_items(&conf).unwrap_err();
+            assert!(start.elapsed() < std::time::Duration::from_secs(2));
+            assert_eq!(
+                err.to_string(),
+                "Deadline of 1s reached\nCompleted before the deadline: items listing, 6 items \
+                 in 3 pages",
+                "{} concurrent requests",
+                concurrent_requests
+            );
+            assert_eq!(exit_code(err.as_ref()), 4);
+        }
+    }
+
+    #[test]
+    fn requests_fail_once_deadline_is_reached() {
+        let server = MockMenderServer::new();
+        let mut conf = server.config(&["showconfig", "--deadline", "0s"]);
+        conf.pagination.per_page = 2;
+        let err = list_items(&conf).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Deadline of 0s reached\nCompleted before the deadline: items listing, 0 items in 0 \
+             pages"
+        );
+        assert_eq!(exit_code(err.as_ref()), 4);
+        assert_eq!(
+            exit_code(&UnexpectedCount {
+                count: 1,
+                expected: String::from("2"),
+            }),
+            3
+        );
+        assert_eq!(exit_code(&MenderError::new(String::from("failed"))), 2);
+    }
+
+    /// Serve a page of a listing by 2 items, with the extra query parameters given.
+    fn serve_page(
+        server: &mut MockMenderServer,
+        path: &str,
+        query: &[(&str, &str)],
+        page: usize,
+        body: serde_json::Value,
+    ) -> mockito::Mock {
+        let mut matchers = vec![
+            Matcher::UrlEncoded("per_page".into(), "2".into()),
+            Matcher::UrlEncoded("page".into(), page.to_string()),
+        ];
+        for (name, value) in query {
+            matchers.push(Matcher::UrlEncoded(name.to_string(), value.to_string()));
+        }
+        server
+            .mock("GET", path)
+            .match_query(Matcher::AllOf(matchers))
+            .with_body(body.to_string())
+            .create()
+    }
+
+    fn device_with_artifact(id: &str, artifact_name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "attributes": [{"name": "artifact_name", "value": artifact_name}],
+        })
+    }
+
+    #[test]
+    fn count_artifacts_with_concurrent_requests() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("sort".into(), "updated_ts:desc".into()))
+            .with_body("[]")
+            .create();
+        let pages = [
+            vec![("d1", "release-1"), ("d2", "release-2")],
+            vec![("d3", "release-1"), ("d4", "release-1")],
+            vec![("d5", "release-2")],
+            vec![],
+        ]
+        .iter()
+        .enumerate()
+        .map(|(idx, devices)| {
+            let devices: Vec<_> = devices
+                .iter()
+                .map(|(id, artifact_name)| device_with_artifact(id, artifact_name))
+                .collect();
+            serve_page(
+                &mut server,
+                GET_DEVICES_INVENTORY_API,
+                &[],
+                idx + 1,
+                devices.into(),
+            )
+        })
+        .collect::<Vec<_>>();
+        let mut conf = server.config(&["countartifacts", "--concurrent-requests", "3"]);
+        conf.pagination.per_page = 2;
+
+        let counts = count_artifacts(&conf).unwrap();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["release-1"], 3);
+        assert_eq!(counts["release-2"], 2);
+        for page in &pages {
+            page.assert();
+        }
+    }
+
+    #[test]
+    fn get_id_searches_identity_data_concurrently() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("SerialNumber".into(), "SN3".into()))
+            .with_body("[]")
+            .create();
+        let identity = |id: &str, serial_number: &str| serde_json::json!({"id": id, "identity_data": {"SerialNumber": serial_number}});
+        let accepted = [("status", "accepted")];
+        let pages = [
+            serve_page(
+                &mut server,
+                GET_DEVICES_AUTH_API,
+                &accepted,
+                1,
+                serde_json::json!([identity("a", "SN1"), identity("b", "SN2")]),
+            ),
+            serve_page(
+                &mut server,
+                GET_DEVICES_AUTH_API,
+                &accepted,
+                2,
+                serde_json::json!([identity("c", "SN3"), identity("d", "SN4")]),
+            ),
+            serve_page(
+                &mut server,
+                GET_DEVICES_AUTH_API,
+                &accepted,
+                3,
+                serde_json::json!([]),
+            ),
+        ];
+        let mut conf = server.config(&["getid", "SN3", "--concurrent-requests", "2"]);
+        conf.pagination.per_page = 2;
+
+        assert_eq!(get_id(&conf).unwrap(), "c");
+        for page in &pages {
+            page.assert();
+        }
+    }
+
+    #[test]
+    fn deploy_lists_group_devices_concurrently() {
+        let mut server = MockMenderServer::new();
+        let group_devices = format!("{}/g1/devices", GROUPS_API);
+        let pages = [
+            serve_page(
+                &mut server,
+                &group_devices,
+                &[],
+                1,
+                serde_json::json!(["d1", "d2"]),
+            ),
+            serve_page(
+                &mut server,
+                &group_devices,
+                &[],
+                2,
+                serde_json::json!(["d3"]),
+            ),
+            serve_page(&mut server, &group_devices, &[], 3, serde_json::json!([])),
+            serve_page(&mut server, &group_devices, &[], 4, serde_json::json!([])),
+        ];
+        server
+            .mock("GET", DEPLOYMENT_LIMITS_API)
+            .with_status(404)
+            .create();
+        let posted = server
+            .mock("POST", DEPLOY_API)
+            .match_body(Matcher::Json(serde_json::json!({
+                "artifact_name": "release-2",
+                "name": "g1",
+                "devices": ["d1", "d2", "d3"],
+            })))
+            .with_status(201)
+            .create();
+        let mut conf = server.config(&[
+            "deploy",
+            "--group",
+            "g1",
+            "release-2",
+            "--allow-downgrade",
+            "--concurrent-requests",
+            "3",
+        ]);
+        conf.pagination.per_page = 2;
+
+        assert_eq!(deploy(&conf).unwrap(), 3);
+        posted.assert();
+        for page in &pages {
+            page.assert();
+        }
+    }
+
+    const SAVED_FILTERS: &str = r#"[
+        {"id": "f1", "name": "arm", "terms": [
+            {"scope": "inventory", "attribute": "device_type", "type": "$eq", "value": "arm"}
+        ]},
+        {"id": "f2", "name": "old", "terms": [
+            {"scope": "inventory", "attribute": "artifact_name", "type": "$in", "value": ["r1", "r2"]},
+            {"scope": "system", "attribute": "group", "type": "$exists", "value": false}
+        ]}
+    ]"#;
+
+    fn export_to_file(source: &mut MockMenderServer) -> tempfile::NamedTempFile {
+        source
+            .mock("GET", FILTERS_API)
+            .with_body(SAVED_FILTERS)
+            .create();
+        let exported = export_filters(&source.config(&["exportfilters"])).unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), exported).unwrap();
+        file
+    }
+
+    /// Expect the creation of the saved filter at index idx of SAVED_FILTERS.
+    fn expect_creation(target: &mut MockMenderServer, idx: usize) -> mockito::Mock {
+        let mut filter: serde_json::Value = serde_json::from_str(SAVED_FILTERS).unwrap();
+        let filter = filter[idx].as_object_mut().unwrap();
+        filter.remove("id");
+        target
+            .mock("POST", FILTERS_API)
+            .match_body(Matcher::Json(serde_json::Value::Object(filter.clone())))
+            .with_status(201)
+            .create()
+    }
+
+    #[test]
+    fn filters_round_trip() {
+        let file = export_to_file(&mut MockMenderServer::new());
+        let mut target = MockMenderServer::new();
+        target.mock("GET", FILTERS_API).with_body("[]").create();
+        let created = [
+            expect_creation(&mut target, 0),
+            expect_creation(&mut target, 1),
+        ];
+        let path = file.path().to_str().unwrap();
+
+        assert_eq!(
+            import_filters(&target.config(&["importfilters", path])).unwrap(),
+            2
+        );
+        for mock in &created {
+            mock.assert();
+        }
+    }
+
+    #[test]
+    fn import_skips_or_overwrites_existing_filters() {
+        let file = export_to_file(&mut MockMenderServer::new());
+        let path = file.path().to_str().unwrap();
+        let mut target = MockMenderServer::new();
+        target
+            .mock("GET", FILTERS_API)
+            .with_body(r#"[{"id": "t1", "name": "old", "terms": []}]"#)
+            .create();
+        let created = expect_creation(&mut target, 0);
+
+        assert_eq!(
+            import_filters(&target.config(&["importfilters", path])).unwrap(),
+            1
+        );
+        created.assert();
+
+        let deleted = target
+            .mock("DELETE", &*format!("{}/t1", FILTERS_API))
+            .with_status(204)
+            .create();
+        let overwritten = expect_creation(&mut target, 1);
+        let conf = target.config(&["importfilters", path, "--overwrite"]);
+        assert_eq!(import_filters(&conf).unwrap(), 2);
+        deleted.assert();
+        overwritten.assert();
+    }
+
+    #[test]
+    fn import_reports_failed_filters() {
+        let file = export_to_file(&mut MockMenderServer::new());
+        let mut target = MockMenderServer::new();
+        target.mock("GET", FILTERS_API).with_body("[]").create();
+        expect_creation(&mut target, 0);
+        target
+            .mock("POST", FILTERS_API)
+            .with_status(400)
+            .with_body(r#"{"error": "unsupported filter type $exists"}"#)
+            .create();
+        let conf = target.config(&["importfilters", file.path().to_str().unwrap()]);
+
+        assert_eq!(
+            import_filters(&conf).unwrap_err().to_string(),
+            "1 filters imported, 1 failed"
+        );
+    }
+
+    #[test]
+    fn downgrade_is_reported_on_dry_run() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", &*format!("{}/d1", GET_DEVICES_INVENTORY_API))
+            .with_body(
+                r#"{"id": "d1", "attributes": [{"name": "artifact_name", "value": "imx8mm-release-2.0.0"}]}"#,
+            )
+            .expect(2)
+            .create();
+        let devices = vec!["d1".to_string()];
+        for args in &[["--dry-run"], ["--yes"]] {
+            let conf =
+                server.config(&[args[0], "deploy", "--device", "d1", "imx8mm-release-1.9.0"]);
+            let client = blocking_client(&conf).unwrap();
+            check_downgrade(
+                &client,
+                &conf,
+                mock_server::TOKEN,
+                "imx8mm-release-1.9.0",
+                &None,
+                &devices,
+            )
+            .unwrap();
+        }
+    }
+
+    /// Mock the requests of a deployment of artifact release-2 to group g1 with devices
+    /// d1 and d2, the deployment post response is left to the caller.
+    fn mock_group_deployment(server: &mut MockMenderServer) {
+        server
+            .mock("GET", &*format!("{}/g1/devices", GROUPS_API))
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(r#"["d1", "d2"]"#)
+            .create();
+        server.empty_page(&format!("{}/g1/devices", GROUPS_API), 2);
+        server
+            .mock("GET", DEPLOYMENT_LIMITS_API)
+            .with_status(404)
+            .create();
+    }
+
+    #[test]
+    fn deploy_summary_file() {
+        let mut server = MockMenderServer::new();
+        mock_group_deployment(&mut server);
+        let posted = server
+            .mock("POST", DEPLOY_API)
+            .match_body(Matcher::Json(serde_json::json!({
+                "artifact_name": "release-2",
+                "name": "g1",
+                "devices": ["d1", "d2"],
+            })))
+            .with_status(201)
+            .with_header("location", &format!("{}/dep1", DEPLOY_API))
+            .create();
+        server
+            .mock("GET", &*format!("{}/dep1", DEPLOY_API))
+            .with_body(r#"{"status": "finished"}"#)
+            .create();
+        server
+            .mock("GET", &*format!("{}/dep1/statistics", DEPLOY_API))
+            .with_body(r#"{"success": 1, "failure": 1}"#)
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let summary_file = dir.path().join("summary.json");
+        let conf = server.config(&[
+            "deploy",
+            "--group",
+            "g1",
+            "release-2",
+            "--allow-downgrade",
+            "--wait",
+            "--wait-interval",
+            "0s",
+            "--summary-file",
+            summary_file.to_str().unwrap(),
+        ]);
+
+        assert_eq!(deploy(&conf).unwrap(), 2);
+        posted.assert();
+        let summary: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&summary_file).unwrap()).unwrap();
+        assert_eq!(summary["schema_version"], DEPLOY_SUMMARY_VERSION);
+        assert_eq!(summary["tool_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(summary["request"]["Deploy"]["group"], "g1");
+        assert_eq!(summary["request"]["Deploy"]["artifact"], "release-2");
+        assert_eq!(summary["device_count"], 2);
+        assert_eq!(summary["deployment_id"], "dep1");
+        assert_eq!(summary["statistics"]["success"], 1);
+        assert_eq!(summary["statistics"]["failure"], 1);
+        for time in &["started", "finished"] {
+            assert!(chrono::DateTime::parse_from_rfc3339(summary[time].as_str().unwrap()).is_ok());
+        }
+        // The file is renamed in place, no temporary file is left
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn deployment_id_without_location_header() {
+        let mut server = MockMenderServer::new();
+        mock_group_deployment(&mut server);
+        server.mock("POST", DEPLOY_API).with_status(204).create();
+        let search = server
+            .mock("GET", DEPLOY_API)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("search".into(), "g1".into()),
+                Matcher::Regex("created_after=[0-9]+".into()),
+            ]))
+            .with_body(
+                r#"[
+                    {"id": "dep0", "name": "g1-old", "artifact_name": "release-1"},
+                    {"id": "dep1", "name": "g1", "artifact_name": "release-2"}
+                ]"#,
+            )
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let summary_file = dir.path().join("summary.json");
+        let conf = server.config(&[
+            "deploy",
+            "--group",
+            "g1",
+            "release-2",
+            "--allow-downgrade",
+            "--summary-file",
+            summary_file.to_str().unwrap(),
+        ]);
+
+        assert_eq!(deploy(&conf).unwrap(), 2);
+        search.assert();
+        let summary: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&summary_file).unwrap()).unwrap();
+        assert_eq!(summary["deployment_id"], "dep1");
+    }
+
+    #[test]
+    fn deployment_id_unknown_is_not_an_error() {
+        let mut server = MockMenderServer::new();
+        mock_group_deployment(&mut server);
+        server.mock("POST", DEPLOY_API).with_status(204).create();
+        server
+            .mock("GET", DEPLOY_API)
+            .match_query(Matcher::Any)
+            .with_body(r#"[{"id": "dep0", "name": "g1-old", "artifact_name": "release-1"}]"#)
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let summary_file = dir.path().join("summary.json");
+        let conf = server.config(&[
+            "deploy",
+            "--group",
+            "g1",
+            "release-2",
+            "--allow-downgrade",
+            "--summary-file",
+            summary_file.to_str().unwrap(),
+        ]);
+
+        assert_eq!(deploy(&conf).unwrap(), 2);
+        let summary: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&summary_file).unwrap()).unwrap();
+        assert_eq!(summary["deployment_id"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn deployment_id_unknown_fails_wait() {
+        let mut server = MockMenderServer::new();
+        mock_group_deployment(&mut server);
+        let posted = server.mock("POST", DEPLOY_API).with_status(204).create();
+        server
+            .mock("GET", DEPLOY_API)
+            .match_query(Matcher::Any)
+            .with_body("[]")
+            .create();
+        let statistics = server
+            .mock("GET", Matcher::Regex("/statistics$".into()))
+            .expect(0)
+            .create();
+        let conf = server.config(&[
+            "deploy",
+            "--group",
+            "g1",
+            "release-2",
+            "--allow-downgrade",
+            "--wait",
+            "--max-failures",
+            "0",
+        ]);
+
+        assert_eq!(
+            deploy(&conf).unwrap_err().to_string(),
+            "Deployment created but its id is unknown, can't wait for it nor check its failure \
+             thresholds"
+        );
+        posted.assert();
+        statistics.assert();
+    }
+
+    fn limits(json: &str) -> DeploymentLimits {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn parse_deployment_limits() {
+        let all = limits(
+            r#"{"max_devices_per_deployment": 100, "max_active_deployments": 5, "max_artifact_size": 1024, "other": 1}"#,
+        );
+        assert_eq!(all.max_devices_per_deployment, Some(100));
+        assert_eq!(all.max_active_deployments, Some(5));
+        assert_eq!(all.max_artifact_size, Some(1024));
+        let none = limits("{}");
+        assert_eq!(none.max_devices_per_deployment, None);
+        assert_eq!(none.max_active_deployments, None);
+        assert_eq!(none.max_artifact_size, None);
+    }
+
+    #[test]
+    fn plan_deployment_within_limits() {
+        let not_counted =
+            || -> Result<usize, Box<dyn Error>> { panic!("active deployments counted") };
+        assert_eq!(
+            plan_deployment(&limits("{}"), 10_000, not_counted).unwrap(),
+            None
+        );
+        let max_devices = limits(r#"{"max_devices_per_deployment": 100}"#);
+        assert_eq!(
+            plan_deployment(&max_devices, 100, not_counted).unwrap(),
+            None
+        );
+        let both = limits(r#"{"max_devices_per_deployment": 100, "max_active_deployments": 5}"#);
+        assert_eq!(plan_deployment(&both, 1, || Ok(4)).unwrap(), None);
+    }
+
+    #[test]
+    fn plan_deployment_above_limits() {
+        let not_counted =
+            || -> Result<usize, Box<dyn Error>> { panic!("active deployments counted") };
+        let both = limits(r#"{"max_devices_per_deployment": 100, "max_active_deployments": 5}"#);
+        assert_eq!(
+            plan_deployment(&both, 101, not_counted)
+                .unwrap_err()
+                .to_string(),
+            "Deployment to 101 devices is above the server limit of 100 devices per deployment"
+        );
+        for active in &[5, 6] {
+            assert_eq!(
+                plan_deployment(&both, 100, || Ok(*active)).unwrap(),
+                Some(format!(
+                    "{} deployments are already active and the server allows 5, the deployment may be rejected",
+                    active
+                ))
+            );
+        }
+        assert!(plan_deployment(&both, 1, || Err("listing failed".into())).is_err());
+    }
+
+    #[test]
+    fn fleet_report_sections() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", GET_DEVICES_AUTH_API)
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(
+                r#"[{"id": "d1", "status": "accepted"}, {"id": "d2", "status": "accepted"},
+                    {"id": "d3", "status": "pending"}]"#,
+            )
+            .create();
+        server.empty_page(GET_DEVICES_AUTH_API, 2);
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(
+                serde_json::json!([
+                    {
+                        "id": "d1",
+                        "attributes": [
+                            {"name": "group", "value": "g1"},
+                            {"name": "artifact_name", "value": "release-2"},
+                            {"name": "SerialNumber", "value": "SN1"},
+                        ],
+                        "updated_ts": "2020-01-01T00:00:00Z",
+                    },
+                    {
+                        "id": "d2",
+                        "attributes": [
+                            {"name": "group", "value": "g1"},
+                            {"name": "artifact_name", "value": "release-1"},
+                        ],
+                        "updated_ts": chrono::Utc::now().to_rfc3339(),
+                    },
+                    {"id": "d3", "attributes": [{"name": "artifact_name", "value": "release-1"}]},
+                ])
+                .to_string(),
+            )
+            .create();
+        server.empty_page(GET_DEVICES_INVENTORY_API, 2);
+        server
+            .mock("GET", DEPLOY_API)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("page".into(), "1".into()),
+                Matcher::UrlEncoded("status".into(), "inprogress".into()),
+            ]))
+            .with_body(r#"[{"id": "dep1", "name": "g1", "artifact_name": "release-2"}]"#)
+            .create();
+        server.empty_page(DEPLOY_API, 2);
+        let conf = server.config(&["report", "fleet", "--stale-days", "30"]);
+
+        let html = fleet_report(&conf).unwrap();
+        let section = |id: &str| {
+            let start = html
+                .find(&format!("<h2 id=\"{}\">", id))
+                .unwrap_or_else(|| panic!("no {} section", id));
+            let end = html[start + 1..]
+                .find("<h2")
+                .map_or(html.len(), |end| start + 1 + end);
+            html[start..end].to_string()
+        };
+        assert!(html.contains(&format!(
+            "<title>Fleet report - {}</title>",
+            server.server.url()
+        )));
+        assert!(!html.contains("{{"));
+        let device_status = section("device-status");
+        assert!(device_status.contains("<svg"));
+        assert!(device_status.contains("<tr><td>accepted</td><td class=\"count\">2</td></tr>"));
+        assert!(device_status.contains("<tr><td>pending</td><td class=\"count\">1</td></tr>"));
+        let artifact_adoption = section("artifact-adoption");
+        assert!(artifact_adoption.contains("<h3>g1</h3>"));
+        assert!(artifact_adoption.contains("<h3>&lt;no group&gt;</h3>"));
+        assert!(artifact_adoption.contains("<tr><td>release-2</td><td class=\"count\">1</td></tr>"));
+        let stale_devices = section("stale-devices");
+        assert!(stale_devices.contains("not updated for 30 days"));
+        assert!(stale_devices.contains("<td>d1</td><td>SN1</td><td>2020-01-01T00:00:00Z</td>"));
+        assert!(!stale_devices.contains("d2"));
+        assert!(!stale_devices.contains("d3"));
+        let deployments = section("deployments");
+        assert!(deployments.contains("<td>dep1</td><td>g1</td><td>release-2</td>"));
+    }
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    /// Serve the stored artifacts, given by id, name and content, and their download.
+    fn mock_artifacts(server: &mut MockMenderServer, artifacts: &[(&str, &str, &[u8])]) {
+        let listed: Vec<_> = artifacts
+            .iter()
+            .map(|(id, name, _)| {
+                serde_json::json!({
+                    "id": id,
+                    "name": name,
+                    "device_types_compatible": ["rpi4"],
+                    "modified": "2030-01-01T00:00:00Z",
+                })
+            })
+            .collect();
+        server
+            .mock("GET", ARTIFACTS_LIST_API)
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(serde_json::Value::from(listed).to_string())
+            .create();
+        server.empty_page(ARTIFACTS_LIST_API, 2);
+        for (id, _, content) in artifacts {
+            server
+                .mock("GET", &*format!("{}/{}/download", ARTIFACTS_API, id))
+                .with_body(
+                    serde_json::json!({ "uri": format!("{}/download/{}", server.server.url(), id) })
+                        .to_string(),
+                )
+                .create();
+            server
+                .server
+                .mock("GET", &*format!("/download/{}", id))
+                .with_body(content)
+                .create();
+        }
+    }
+
+    fn check_checksum(server: &MockMenderServer, name: &str, expected: &str) -> Result<(), String> {
+        let conf = server.config(&["showconfig"]);
+        let client = blocking_client(&conf).unwrap();
+        check_artifact_checksum(&client, &conf, mock_server::TOKEN, name, expected)
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn artifact_checksum_of_downloads() {
+        let large: Vec<u8> = (0..200_000).map(|idx| (idx % 251) as u8).collect();
+        let large_sha256: String = Sha256::digest(&large)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        for (content, sha256) in &[
+            (&b""[..], EMPTY_SHA256),
+            (&b"abc"[..], ABC_SHA256),
+            (&large[..], large_sha256.as_str()),
+        ] {
+            let mut server = MockMenderServer::new();
+            mock_artifacts(&mut server, &[("a1", "release-2", content)]);
+            assert_eq!(check_checksum(&server, "release-2", sha256), Ok(()));
+        }
+    }
+
+    #[test]
+    fn artifact_checksum_mismatch() {
+        let mut server = MockMenderServer::new();
+        mock_artifacts(
+            &mut server,
+            &[
+                ("a1", "release-2", b"abc"),
+                ("a2", "release-2", b""),
+                ("a3", "release-3", b"abc"),
+            ],
+        );
+
+        assert_eq!(
+            check_checksum(&server, "release-2", ABC_SHA256),
+            Err(format!(
+                "Checksum of artifact release-2 (a2) is {} instead of {}, not deploying",
+                EMPTY_SHA256, ABC_SHA256
+            ))
+        );
+        assert_eq!(check_checksum(&server, "release-3", ABC_SHA256), Ok(()));
+        assert_eq!(
+            check_checksum(&server, "release-4", ABC_SHA256),
+            Err(String::from("Artifact release-4 not found"))
+        );
+    }
+
+    #[test]
+    fn deploy_refused_on_checksum_mismatch() {
+        let mut server = MockMenderServer::new();
+        mock_group_deployment(&mut server);
+        mock_artifacts(&mut server, &[("a1", "release-2", b"abc")]);
+        let posted = server.mock("POST", DEPLOY_API).expect(0).create();
+        let conf = server.config(&[
+            "deploy",
+            "--group",
+            "g1",
+            "release-2",
+            "--allow-downgrade",
+            "--artifact-checksum",
+            EMPTY_SHA256,
+        ]);
+
+        assert!(deploy(&conf)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Checksum of artifact release-2 (a1) is"));
+        posted.assert();
+    }
+
+    /// Serve the state of the inventory of a group, as requested before counting artifacts.
+    fn mock_inventory_state(
+        server: &mut MockMenderServer,
+        group: &str,
+        total_count: usize,
+        newest_updated_ts: &str,
+    ) -> mockito::Mock {
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("group".into(), group.into()),
+                Matcher::UrlEncoded("per_page".into(), "1".into()),
+                Matcher::UrlEncoded("sort".into(), "updated_ts:desc".into()),
+            ]))
+            .with_header("X-Total-Count", &total_count.to_string())
+            .with_body(
+                serde_json::json!([{"id": "d1", "updated_ts": newest_updated_ts}]).to_string(),
+            )
+            .create()
+    }
+
+    /// Serve the devices of a group, return the mock of its first page.
+    fn mock_group_inventory(server: &mut MockMenderServer, group: &str) -> mockito::Mock {
+        let page = |page: &str| {
+            Matcher::AllOf(vec![
+                Matcher::UrlEncoded("group".into(), group.into()),
+                Matcher::UrlEncoded("per_page".into(), "500".into()),
+                Matcher::UrlEncoded("page".into(), page.into()),
+            ])
+        };
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(page("2"))
+            .with_body("[]")
+            .create();
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(page("1"))
+            .with_body(
+                serde_json::json!([
+                    device_with_artifact("d1", "release-1"),
+                    device_with_artifact("d2", "release-2"),
+                ])
+                .to_string(),
+            )
+            .create()
+    }
+
+    #[test]
+    fn count_artifacts_cache_hit_and_miss() {
+        let mut server = MockMenderServer::new();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let count = |server: &MockMenderServer, args: &[&str]| {
+            let mut conf = server.config(args);
+            conf.cache_dir = Some(cache_dir.path().to_path_buf());
+            let counts = count_artifacts(&conf).unwrap();
+            assert_eq!(counts["release-1"], 1);
+            assert_eq!(counts["release-2"], 1);
+        };
+        let count_production = ["countartifacts", "--group", "production"];
+        let state = mock_inventory_state(&mut server, "production", 2, "2030-01-01T00:00:00Z");
+        mock_inventory_state(&mut server, "staging", 2, "2030-01-01T00:00:00Z");
+        let production = mock_group_inventory(&mut server, "production").expect(3);
+        let staging = mock_group_inventory(&mut server, "staging").expect(1);
+
+        // Miss on the first count, then hit while the inventory is unchanged
+        count(&server, &count_production);
+        count(&server, &count_production);
+        // Counts are cached per group
+        count(&server, &["countartifacts", "--group", "staging"]);
+        // --refresh always counts
+        count(
+            &server,
+            &["countartifacts", "--group", "production", "--refresh"],
+        );
+        // Miss when a device updated its inventory
+        state.remove();
+        mock_inventory_state(&mut server, "production", 2, "2030-01-02T00:00:00Z");
+        count(&server, &count_production);
+        count(&server, &count_production);
+        production.assert();
+        staging.assert();
+    }
+
+    #[test]
+    fn count_artifacts_without_total_count_is_not_cached() {
+        let mut server = MockMenderServer::new();
+        let cache_dir = tempfile::tempdir().unwrap();
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("sort".into(), "updated_ts:desc".into()))
+            .with_body("[]")
+            .create();
+        let production = mock_group_inventory(&mut server, "production").expect(2);
+        for _ in 0..2 {
+            let mut conf = server.config(&["countartifacts", "--group", "production"]);
+            conf.cache_dir = Some(cache_dir.path().to_path_buf());
+            assert_eq!(count_artifacts(&conf).unwrap().len(), 2);
+        }
+        production.assert();
+        assert_eq!(std::fs::read_dir(cache_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn get_info_json_output_round_trips() {
+        let mut server = MockMenderServer::new();
+        let inventory = serde_json::json!({
+            "id": "d1",
+            "attributes": [
+                { "name": "artifact_name", "value": "release-1" },
+                { "name": "cpu_count", "value": 4 },
+            ],
+            "updated_ts": "2030-01-01T00:00:00Z",
+        });
+        server
+            .mock("GET", format!("{}/d1", GET_DEVICES_INVENTORY_API).as_str())
+            .with_body(inventory.to_string())
+            .expect(2)
+            .create();
+
+        let info = get_info(&server.config(&["--output", "json", "getinfo", "id:d1"])).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&info).unwrap(),
+            inventory
+        );
+        let attributes = get_info(&server.config(&[
+            "--output",
+            "json",
+            "getinfo",
+            "id:d1",
+            "--attr",
+            "artifact_name",
+            "--attr",
+            "cpu_count",
+        ]))
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&attributes).unwrap(),
+            serde_json::json!({ "artifact_name": "release-1", "cpu_count": "4" })
+        );
+    }
+
+    #[test]
+    fn list_artifacts_json_output_round_trips() {
+        let mut server = MockMenderServer::new();
+        let artifact = |id: &str, name: &str| {
+            serde_json::json!({
+                "id": id,
+                "name": name,
+                "description": null,
+                "device_types_compatible": ["rpi4"],
+                "modified": "2030-01-01T00:00:00Z",
+            })
+        };
+        server
+            .mock("GET", ARTIFACTS_LIST_API)
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(
+                serde_json::json!([artifact("a2", "release-2"), artifact("a1", "release-1")])
+                    .to_string(),
+            )
+            .create();
+        server.empty_page(ARTIFACTS_LIST_API, 2);
+
+        let listing =
+            list_artifacts(&server.config(&["--output", "json", "listartifacts"])).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&listing).unwrap(),
+            serde_json::json!([artifact("a1", "release-1"), artifact("a2", "release-2")])
+        );
+    }
+
+    fn deployment(id: &str, status: &str, finished: &str, device_count: usize) -> MenderDeployment {
+        MenderDeployment {
+            id: id.to_string(),
+            name: format!("deployment {}", id),
+            artifact_name: String::from("release-1"),
+            status: Some(status.to_string()),
+            created: Some(String::from("2020-01-01T00:00:00Z")),
+            finished: Some(finished.to_string()).filter(|finished| !finished.is_empty()),
+            device_count: Some(device_count),
+        }
+    }
+
+    #[test]
+    fn deployment_filter_by_status_devices_and_age() {
+        let recent = chrono::Utc::now().to_rfc3339();
+        let old_finished = deployment("d1", "finished", "2020-01-02T00:00:00Z", 0);
+        let old_in_progress = deployment("d2", "inprogress", "", 3);
+        let recent_finished = deployment("d3", "finished", &recent, 5);
+        let all = [&old_finished, &old_in_progress, &recent_finished];
+        let matching = |filter: &DeploymentFilter| -> Vec<&str> {
+            all.iter()
+                .filter(|deployment| filter.matches(deployment))
+                .map(|deployment| deployment.id.as_str())
+                .collect()
+        };
+
+        assert_eq!(matching(&DeploymentFilter::default()), ["d1", "d2", "d3"]);
+        let statuses = |statuses: &[&str]| DeploymentFilter {
+            statuses: statuses.iter().map(|status| status.to_string()).collect(),
+            ..DeploymentFilter::default()
+        };
+        assert_eq!(matching(&statuses(&["finished"])), ["d1", "d3"]);
+        // Statuses are compared whatever their spelling
+        assert_eq!(matching(&statuses(&["in progress", "aborted"])), ["d2"]);
+        assert_eq!(
+            matching(&DeploymentFilter {
+                max_devices: Some(3),
+                ..DeploymentFilter::default()
+            }),
+            ["d1", "d2"]
+        );
+        // The age is counted from the end of the deployment, or else its creation
+        assert_eq!(
+            matching(&DeploymentFilter {
+                older_than: Some(std::time::Duration::from_secs(90 * 86400)),
+                ..DeploymentFilter::default()
+            }),
+            ["d1", "d2"]
+        );
+        assert_eq!(
+            matching(&DeploymentFilter {
+                older_than: Some(std::time::Duration::from_secs(90 * 86400)),
+                statuses: vec![String::from("finished")],
+                max_devices: Some(0),
+                device: None,
+            }),
+            ["d1"]
+        );
+    }
+
+    #[test]
+    fn deployment_filter_without_device_count() {
+        let mut unknown_count = deployment("d1", "finished", "", 0);
+        unknown_count.device_count = None;
+        assert!(DeploymentFilter::default().matches(&unknown_count));
+        assert!(!DeploymentFilter {
+            max_devices: Some(10),
+            ..DeploymentFilter::default()
+        }
+        .matches(&unknown_count));
+    }
+
+    /// Serve the given deployments to the deployments listing.
+    fn mock_deployments(server: &mut MockMenderServer, ids: &[&str]) {
+        server
+            .mock("GET", DEPLOY_API)
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(
+                serde_json::json!(ids
+                    .iter()
+                    .map(|id| serde_json::json!({
+                        "id": id,
+                        "name": id,
+                        "artifact_name": "release-1",
+                        "status": "finished",
+                        "created": "2020-01-01T00:00:00Z",
+                        "device_count": 0,
+                    }))
+                    .collect::<Vec<_>>())
+                .to_string(),
+            )
+            .create();
+        server.empty_page(DEPLOY_API, 2);
+    }
+
+    #[test]
+    fn cleanup_deployments_reports_deleted_and_failed() {
+        let mut server = MockMenderServer::new();
+        mock_deployments(&mut server, &["d1", "d2", "d3"]);
+        let deletions: Vec<_> = [("d1", 204), ("d2", 409), ("d3", 204)]
+            .iter()
+            .map(|(id, status)| {
+                server
+                    .mock("DELETE", format!("{}/{}", DEPLOY_API, id).as_str())
+                    .with_status(*status)
+                    .create()
+            })
+            .collect();
+
+        let conf = server.config(&["--yes", "cleanupdeployments"]);
+        match cleanup_deployments(&conf) {
+            Ok(report) => panic!("cleanup succeeded: {}", report),
+            Err(err) => assert_eq!(err.to_string(), "2 deployments deleted, 1 failed"),
+        }
+        // The failure doesn't stop the deletion of the other deployments
+        for deletion in deletions {
+            deletion.assert();
+        }
+    }
+
+    #[test]
+    fn cleanup_deployments_deletes_all() {
+        let mut server = MockMenderServer::new();
+        mock_deployments(&mut server, &["d1", "d2"]);
+        for id in &["d1", "d2"] {
+            server
+                .mock("DELETE", format!("{}/{}", DEPLOY_API, id).as_str())
+                .with_status(204)
+                .create();
+        }
+
+        let conf = server.config(&["--yes", "cleanupdeployments"]);
+        assert_eq!(
+            cleanup_deployments(&conf).unwrap(),
+            "d1: deleted\nd2: deleted\n2 deployments deleted, 0 failed"
+        );
+    }
+
+    #[test]
+    fn cleanup_deployments_unsupported_by_server() {
+        let mut server = MockMenderServer::new();
+        mock_deployments(&mut server, &["d1", "d2"]);
+        let deletion = server
+            .mock("DELETE", format!("{}/d1", DEPLOY_API).as_str())
+            .with_status(405)
+            .create();
+
+        let conf = server.config(&["--yes", "cleanupdeployments"]);
+        assert_eq!(
+            cleanup_deployments(&conf).unwrap(),
+            "Deleting deployments is not supported by the server, to clean up manually:\nd1\nd2"
+        );
+        deletion.assert();
+    }
+
+    #[test]
+    fn cleanup_finished_deployments_older_than_days() {
+        let mut server = MockMenderServer::new();
+        let recent = (chrono::Utc::now() - chrono::Duration::days(2))
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        server
+            .mock("GET", DEPLOY_API)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("status".into(), "finished".into()),
+                Matcher::UrlEncoded("page".into(), "1".into()),
+            ]))
+            .with_body(
+                serde_json::json!([
+                    {"id": "d1", "name": "d1", "artifact_name": "release-1", "status": "finished",
+                     "created": "2020-01-01T00:00:00Z", "finished": "2020-01-02T00:00:00Z"},
+                    {"id": "d2", "name": "d2", "artifact_name": "release-2", "status": "finished",
+                     "created": recent, "finished": recent},
+                ])
+                .to_string(),
+            )
+            .create();
+        server.empty_page(DEPLOY_API, 2);
+        let deletion = server
+            .mock("DELETE", format!("{}/d1", DEPLOY_API).as_str())
+            .with_status(204)
+            .create();
+        let recent_deletion = server
+            .mock("DELETE", format!("{}/d2", DEPLOY_API).as_str())
+            .expect(0)
+            .create();
+
+        let conf = server.config(&["--yes", "cleanupfinisheddeployments", "30"]);
+        assert_eq!(
+            cleanup_finished_deployments(&conf).unwrap(),
+            "d1: deleted\n1 deployments deleted, 0 failed"
+        );
+        deletion.assert();
+        recent_deletion.assert();
+    }
+
+    #[test]
+    fn device_rows_of_column_presets() {
+        let device: MenderDevice = serde_json::from_value(serde_json::json!({
+            "id": "d1",
+            "attributes": [
+                { "name": "SerialNumber", "value": "SN1" },
+                { "name": "artifact_name", "value": "release-1" },
+                { "name": "group", "value": "production" },
+                { "name": "device_type", "value": "rpi4" },
+                { "name": "status", "value": "accepted" },
+                { "name": "mender_client_version", "value": "3.5.0" },
+            ],
+            "updated_ts": "2030-01-01T00:00:00Z",
+        }))
+        .unwrap();
+        use DeviceColumn::*;
+        assert_eq!(
+            device_row(&device, &[Id, SerialNumber, Artifact]),
+            "d1\tSN1\trelease-1\n"
+        );
+        assert_eq!(
+            device_row(
+                &device,
+                &[
+                    Id,
+                    SerialNumber,
+                    Artifact,
+                    Group,
+                    DeviceType,
+                    Status,
+                    LastSeen
+                ]
+            ),
+            "d1\tSN1\trelease-1\tproduction\trpi4\taccepted\t2030-01-01T00:00:00Z\n"
+        );
+        assert_eq!(device_row(&device, &[ClientVersion]), "3.5.0\n");
+
+        // Missing attributes leave their column empty, the shape of the row is kept
+        let bare: MenderDevice = serde_json::from_value(serde_json::json!({ "id": "d2" })).unwrap();
+        assert_eq!(device_row(&bare, &[Id, SerialNumber, Artifact]), "d2\t\t\n");
+    }
+
+    #[test]
+    fn artifact_size_within_limits() {
+        assert_eq!(check_artifact_size(100, Some(1000), Some(500)), Ok(()));
+        // Limits are inclusive
+        assert_eq!(check_artifact_size(500, Some(500), Some(500)), Ok(()));
+        // Unknown limits are not checked
+        assert_eq!(check_artifact_size(u64::MAX, None, None), Ok(()));
+        assert_eq!(check_artifact_size(100, None, Some(100)), Ok(()));
+        assert_eq!(check_artifact_size(100, Some(100), None), Ok(()));
+    }
+
+    #[test]
+    fn artifact_size_above_limits() {
+        assert_eq!(
+            check_artifact_size(1001, Some(1000), None),
+            Err(String::from(
+                "Artifact of 1001 bytes is above the server limit of 1000 bytes per artifact"
+            ))
+        );
+        assert_eq!(
+            check_artifact_size(501, None, Some(500)),
+            Err(String::from(
+                "Artifact of 501 bytes is above the 500 bytes of storage left"
+            ))
+        );
+        // The artifact limit is reported first when both are exceeded
+        assert_eq!(
+            check_artifact_size(2000, Some(1000), Some(500)),
+            Err(String::from(
+                "Artifact of 2000 bytes is above the server limit of 1000 bytes per artifact"
+            ))
+        );
+    }
+
+    #[test]
+    fn storage_left() {
+        let limits = |limit, usage| StorageLimits { limit, usage };
+        assert_eq!(limits(1000, 400).remaining(), Some(600));
+        assert_eq!(limits(1000, 1200).remaining(), Some(0));
+        // A limit of 0 means unlimited
+        assert_eq!(limits(0, 400).remaining(), None);
+    }
+
+    #[test]
+    fn upload_above_limit_fails_before_sending() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", DEPLOYMENT_LIMITS_API)
+            .with_body(r#"{"max_artifact_size": 10}"#)
+            .create();
+        server
+            .mock("GET", STORAGE_LIMITS_API)
+            .with_status(404)
+            .create();
+        let upload = server.mock("POST", ARTIFACTS_API).expect(0).create();
+        let artifact = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(artifact.path(), [0; 11]).unwrap();
+
+        let conf = server.config(&[
+            "uploadartifact",
+            "--file",
+            artifact.path().to_str().unwrap(),
+        ]);
+        match upload_artifact(&conf) {
+            Ok(id) => panic!("artifact {} uploaded", id),
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "Artifact of 11 bytes is above the server limit of 10 bytes per artifact"
+            ),
+        }
+        upload.assert();
+    }
+
+    #[test]
+    fn upload_without_limits() {
+        let mut server = MockMenderServer::new();
+        for path in &[DEPLOYMENT_LIMITS_API, STORAGE_LIMITS_API] {
+            server.mock("GET", *path).with_status(404).create();
+        }
+        let upload = server
+            .mock("POST", ARTIFACTS_API)
+            .with_status(201)
+            .with_header("location", &format!("{}/a1", ARTIFACTS_API))
+            .create();
+        let artifact = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(artifact.path(), [0; 11]).unwrap();
+
+        let conf = server.config(&[
+            "uploadartifact",
+            "--file",
+            artifact.path().to_str().unwrap(),
+        ]);
+        assert_eq!(upload_artifact(&conf).unwrap(), "a1");
+        upload.assert();
+    }
+
+    fn device_with_serial_number(id: &str, serial_number: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "attributes": [{"name": "SerialNumber", "value": serial_number}],
+        })
+    }
+
+    /// Serve the inventory with the given devices, nothing matches the SerialNumber query
+    /// as is.
+    fn mock_serial_numbers(server: &mut MockMenderServer, devices: serde_json::Value) {
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("SerialNumber".into(), "SN-ABC".into()))
+            .with_body("[]")
+            .create();
+        serve_page(server, GET_DEVICES_INVENTORY_API, &[], 1, devices);
+        serve_page(
+            server,
+            GET_DEVICES_INVENTORY_API,
+            &[],
+            2,
+            serde_json::json!([]),
+        );
+    }
+
+    #[test]
+    fn get_id_normalizes_serial_numbers_of_inventory() {
+        let mut server = MockMenderServer::new();
+        mock_serial_numbers(
+            &mut server,
+            serde_json::json!([
+                device_with_serial_number("a", "sn-abd"),
+                device_with_serial_number("b", " sn-abc  "),
+            ]),
+        );
+        serve_page(
+            &mut server,
+            GET_DEVICES_AUTH_API,
+            &[("status", "accepted")],
+            1,
+            serde_json::json!([]),
+        );
+        let get_id_with = |args: &[&str]| {
+            let mut conf = server.config(&[&["getid", "SN-ABC"], args].concat());
+            conf.pagination.per_page = 2;
+            get_id(&conf)
+        };
+
+        assert_eq!(get_id_with(&["--ignore-case", "--trim"]).unwrap(), "b");
+        // Both normalizations are needed for the padded lowercase SerialNumber
+        for args in &[&["--ignore-case"][..], &["--trim"][..]] {
+            assert!(get_id_with(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn get_id_normalizes_serial_numbers_of_identity_data() {
+        let mut server = MockMenderServer::new();
+        mock_serial_numbers(&mut server, serde_json::json!([]));
+        let identity = |id: &str, serial_number: &str| serde_json::json!({"id": id, "identity_data": {"SerialNumber": serial_number}});
+        serve_page(
+            &mut server,
+            GET_DEVICES_AUTH_API,
+            &[("status", "accepted")],
+            1,
+            serde_json::json!([identity("a", "Sn-Abc\t"), identity("b", "SN-ABCD")]),
+        );
+        serve_page(
+            &mut server,
+            GET_DEVICES_AUTH_API,
+            &[("status", "accepted")],
+            2,
+            serde_json::json!([]),
+        );
+
+        let mut conf = server.config(&["getid", "SN-ABC", "--ignore-case", "--trim"]);
+        conf.pagination.per_page = 2;
+        assert_eq!(get_id(&conf).unwrap(), "a");
+    }
+
+    #[test]
+    fn deploy_aborted_on_unexpected_count() {
+        let mut server = MockMenderServer::new();
+        mock_group_deployment(&mut server);
+        let posted = server.mock("POST", DEPLOY_API).expect(0).create();
+        for expected_count in &["3", "3..10", "0..1"] {
+            let conf = server.config(&[
+                "deploy",
+                "--group",
+                "g1",
+                "release-2",
+                "--allow-downgrade",
+                "--expect-count",
+                expected_count,
+            ]);
+            match deploy(&conf) {
+                Ok(nb_devices) => panic!("deployed to {} devices", nb_devices),
+                Err(err) => {
+                    assert!(err.is::<UnexpectedCount>(), "{}", err);
+                    assert_eq!(
+                        err.to_string(),
+                        format!("Deployment targets 2 devices, {} expected", expected_count)
+                    );
+                }
+            }
+        }
+        posted.assert();
+    }
+
+    #[test]
+    fn dry_run_with_expected_count() {
+        let mut server = MockMenderServer::new();
+        mock_group_deployment(&mut server);
+        let posted = server.mock("POST", DEPLOY_API).expect(0).create();
+        let conf = server.config(&[
+            "--dry-run",
+            "deploy",
+            "--group",
+            "g1",
+            "release-2",
+            "--allow-downgrade",
+            "--expect-count",
+            "1..2",
+        ]);
+        assert_eq!(deploy(&conf).unwrap(), 2);
+        posted.assert();
+    }
+
+    /// Self-signed certificate of mender.test in both formats.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+    fn fixture(name: &str) -> String {
+        format!("{}/{}", FIXTURES, name)
+    }
+
+    #[test]
+    fn detect_der_and_pem_certificates() {
+        let der = std::fs::read(fixture("self_signed.der")).unwrap();
+        let pem = std::fs::read(fixture("self_signed.pem")).unwrap();
+        assert_eq!(detect_cert_format(&der), CertFormat::Der);
+        assert_eq!(detect_cert_format(&pem), CertFormat::Pem);
+        assert_eq!(detect_cert_format(b""), CertFormat::Pem);
+    }
+
+    #[test]
+    fn clients_trust_der_and_pem_certificates() {
+        for name in &["self_signed.der", "self_signed.pem"] {
+            let mut conf = mock_server::config("https://mender.test", &["showconfig"]);
+            conf.cert_file = Some(fixture(name));
+            assert!(root_certificate(&conf).unwrap().is_some(), "{}", name);
+            assert!(blocking_client(&conf).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn invalid_der_certificate() {
+        let cert_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(cert_file.path(), [0x30, 0x82, 0x00]).unwrap();
+        let mut conf = mock_server::config("https://mender.test", &["showconfig"]);
+        conf.cert_file = Some(cert_file.path().to_str().unwrap().to_string());
+        assert!(root_certificate(&conf).is_err());
+    }
+
+    fn redirect(status: u16, location: &str) -> Option<String> {
+        let url = reqwest::Url::parse("http://mender.test/api/devices?page=1").unwrap();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::LOCATION, location.parse().unwrap());
+        followed_redirect(
+            reqwest::StatusCode::from_u16(status).unwrap(),
+            &headers,
+            &url,
+        )
+        .map(|url| url.to_string())
+    }
+
+    #[test]
+    fn same_path_307_and_308_redirects_are_followed() {
+        for status in &[307, 308] {
+            for location in &[
+                "https://mender.test/api/devices?page=1",
+                "http://mender.test:8080/api/devices?page=1",
+            ] {
+                assert_eq!(
+                    redirect(*status, location).as_deref(),
+                    Some(*location),
+                    "{} {}",
+                    status,
+                    location
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn other_redirects_are_not_followed() {
+        for (status, location) in &[
+            (301, "https://mender.test/api/devices?page=1"),
+            (302, "https://mender.test/api/devices?page=1"),
+            (303, "https://mender.test/api/devices?page=1"),
+            (302, "/ui/login"),
+            (307, "/ui/login"),
+            (307, "https://mender.test/api/devices?page=2"),
+            (308, "https://ui.mender.test/api/devices?page=1"),
+            // A redirect to the same url would loop
+            (307, "http://mender.test/api/devices?page=1"),
+        ] {
+            assert_eq!(redirect(*status, location), None, "{} {}", status, location);
+        }
+        let url = reqwest::Url::parse("http://mender.test/api/devices").unwrap();
+        assert_eq!(
+            followed_redirect(
+                reqwest::StatusCode::TEMPORARY_REDIRECT,
+                &reqwest::header::HeaderMap::new(),
+                &url
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn redirect_to_login_page_is_reported() {
+        let mut server = MockMenderServer::new();
+        server
+            .mock("GET", "/items")
+            .match_query(Matcher::Any)
+            .with_status(302)
+            .with_header("location", "/ui/login")
+            .create();
+        let conf = server.config(&["showconfig"]);
+        let expected = format!(
+            "server redirected {}/items?per_page=500&page=1 to /ui/login; SERVER_URL probably \
+             points at the UI, not the API",
+            server.server.url()
+        );
+        match list_items(&conf) {
+            Ok(items) => panic!("redirect followed to {:?}", items),
+            Err(err) => assert_eq!(err.to_string(), expected),
+        }
+    }
+
+    #[test]
+    fn same_path_redirect_is_followed() {
+        let mut server = MockMenderServer::new();
+        let mut moved = MockMenderServer::new();
+        serve_pages(&mut moved, 7, None).create();
+        let moved_url = moved.server.url();
+        server
+            .mock("GET", "/items")
+            .match_query(Matcher::Any)
+            .with_status(308)
+            .with_header_from_request("location", move |request| {
+                format!("{}{}", moved_url, request.path_and_query())
+            })
+            .create();
+        let conf = server.config(&["showconfig"]);
+        assert_eq!(list_items(&conf).unwrap(), (1..=7).collect::<Vec<_>>());
+    }
+
+    fn mock_deployment_devices(server: &mut MockMenderServer, fixture_name: &str) {
+        let path = format!("{}/d1/devices/list", DEPLOY_API);
+        server
+            .mock("GET", path.as_str())
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body_from_file(fixture(fixture_name))
+            .create();
+        server.empty_page(&path, 2);
+    }
+
+    #[test]
+    fn deployment_devices_of_3_6_server() {
+        let mut server = MockMenderServer::new();
+        mock_deployment_devices(&mut server, "deployment_devices_3.6.json");
+        assert_eq!(
+            deployment_devices(&server.config(&["deploymentdevices", "d1"])).unwrap(),
+            "5f2b0a1e9c2d4b0001a1b2c3\tdownloading\tDownloading rootfs image: 43%\t2\t2023-05-02T08:01:12.345Z\n\
+             5f2b0a1e9c2d4b0001a1b2c4\tsuccess\t\t1\t2023-05-02T08:00:30.000Z\n\
+             5f2b0a1e9c2d4b0001a1b2c5\tpending\t\t\t\n"
+        );
+    }
+
+    #[test]
+    fn deployment_devices_of_older_server_are_blank() {
+        let mut server = MockMenderServer::new();
+        mock_deployment_devices(&mut server, "deployment_devices_2.6.json");
+        assert_eq!(
+            deployment_devices(&server.config(&["deploymentdevices", "d1"])).unwrap(),
+            "5f2b0a1e9c2d4b0001a1b2c3\tdownloading\t\t\t2023-05-02T08:01:12.345Z\n"
+        );
+    }
+
+    #[test]
+    fn deployment_devices_as_json() {
+        let mut server = MockMenderServer::new();
+        mock_deployment_devices(&mut server, "deployment_devices_3.6.json");
+        let conf = server.config(&["--output", "json", "deploymentdevices", "d1"]);
+        let devices: serde_json::Value =
+            serde_json::from_str(&deployment_devices(&conf).unwrap()).unwrap();
+        assert_eq!(devices[0]["substate"], "Downloading rootfs image: 43%");
+        assert_eq!(devices[0]["attempts"], 2);
+        assert_eq!(devices[1]["finished"], "2023-05-02T08:10:00.000Z");
+        assert!(devices[2]["substate"].is_null());
+        assert!(devices[2]["attempts"].is_null());
+    }
+
+    #[test]
+    fn stuck_deployment_devices() {
+        let mut server = MockMenderServer::new();
+        mock_deployment_devices(&mut server, "deployment_devices_3.6.json");
+        // The finished device is not stuck, the pending one is counted from its creation
+        let conf = server.config(&["deploymentdevices", "d1", "--stuck", "1h"]);
+        let ids: Vec<String> = deployment_devices(&conf)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            ids,
+            ["5f2b0a1e9c2d4b0001a1b2c3", "5f2b0a1e9c2d4b0001a1b2c5"]
+        );
+    }
+
+    #[test]
+    fn recently_started_devices_are_not_stuck() {
+        let started = chrono::Utc::now() - chrono::Duration::minutes(10);
+        let device: MenderDeploymentDevice = serde_json::from_value(serde_json::json!({
+            "id": "d1",
+            "status": "installing",
+            "created": "2023-05-02T08:00:00Z",
+            "started": started.to_rfc3339(),
+        }))
+        .unwrap();
+        let minutes = |minutes: u64| std::time::Duration::from_secs(minutes * 60);
+        assert!(!device.is_stuck(minutes(60)));
+        assert!(device.is_stuck(minutes(5)));
+
+        let device: MenderDeploymentDevice =
+            serde_json::from_value(serde_json::json!({"id": "d1", "status": "pending"})).unwrap();
+        assert!(!device.is_stuck(minutes(0)));
+    }
+
+    fn mock_auth_sets(server: &mut MockMenderServer, auth_sets: &[(&str, &str)]) {
+        server
+            .mock("GET", format!("{}/d1", GET_DEVICES_AUTH_API).as_str())
+            .with_body(
+                serde_json::json!({
+                    "status": "accepted",
+                    "auth_sets": auth_sets
+                        .iter()
+                        .map(|(id, status)| serde_json::json!({
+                            "id": id,
+                            "status": status,
+                            "identity_data": {"mac": id},
+                        }))
+                        .collect::<Vec<_>>(),
+                })
+                .to_string(),
+            )
+            .create();
+    }
+
+    fn mock_rejection(server: &mut MockMenderServer, auth_set: &str) -> mockito::Mock {
+        server
+            .mock(
+                "PUT",
+                format!("{}/d1/auth/{}/status", GET_DEVICES_AUTH_API, auth_set).as_str(),
+            )
+            .match_body(Matcher::Json(serde_json::json!({"status": "rejected"})))
+            .with_status(204)
+            .create()
+    }
+
+    #[test]
+    fn rejectdevice_with_auth_set_id() {
+        let mut server = MockMenderServer::new();
+        mock_auth_sets(&mut server, &[("a1", "accepted"), ("a2", "pending")]);
+        let rejection = mock_rejection(&mut server, "a2");
+        let conf = server.config(&["rejectdevice", "id:d1", "--auth-set-id", "a2"]);
+        assert_eq!(
+            reject(&conf).unwrap(),
+            r#"Auth set a2 of device d1 rejected, its identity data is {"mac":"a2"}"#
+        );
+        rejection.assert();
+    }
+
+    #[test]
+    fn accept_and_reject_with_device_id_and_auth_set_id() {
+        let mut server = MockMenderServer::new();
+        mock_auth_sets(&mut server, &[("a1", "accepted"), ("a2", "pending")]);
+        let acceptance = server
+            .mock(
+                "PUT",
+                format!("{}/d1/auth/a2/status", GET_DEVICES_AUTH_API).as_str(),
+            )
+            .match_body(Matcher::Json(serde_json::json!({"status": "accepted"})))
+            .with_status(204)
+            .create();
+        let rejection = mock_rejection(&mut server, "a1");
+
+        let conf = server.config(&["acceptdevice", "--device-id", "d1", "--auth-set-id", "a2"]);
+        assert_eq!(accept(&conf).unwrap(), "d1");
+        acceptance.assert();
+        let conf = server.config(&["rejectdevice", "--device-id", "d1", "--auth-set-id", "a1"]);
+        assert_eq!(
+            reject(&conf).unwrap(),
+            r#"Auth set a1 of device d1 rejected, its identity data is {"mac":"a1"}"#
+        );
+        rejection.assert();
+    }
+
+    #[test]
+    fn reject_single_candidate() {
+        let mut server = MockMenderServer::new();
+        mock_auth_sets(&mut server, &[("a1", "rejected"), ("a2", "accepted")]);
+        let rejection = mock_rejection(&mut server, "a2");
+        reject(&server.config(&["reject", "id:d1"])).unwrap();
+        rejection.assert();
+    }
+
+    #[test]
+    fn reject_needs_auth_set_id_of_several_candidates() {
+        let mut server = MockMenderServer::new();
+        mock_auth_sets(&mut server, &[("a1", "accepted"), ("a2", "pending")]);
+        let rejection = mock_rejection(&mut server, "a2").expect(0);
+        let conf = server.config(&["--no-interactive", "reject", "id:d1"]);
+        let err = reject(&conf).unwrap_err().to_string();
+        assert!(err.contains("choose one with --authset"), "{}", err);
+        assert!(
+            err.contains("a1 accepted") && err.contains("a2 pending"),
+            "{}",
+            err
+        );
+        rejection.assert();
+
+        let conf = server.config(&["reject", "id:d1", "--authset", "a3"]);
+        assert_eq!(
+            reject(&conf).unwrap_err().to_string(),
+            "Device d1 has no auth set a3"
+        );
+    }
+
+    fn check_fixture(name: &str) -> Result<(Vec<MenderDeploymentDevice>, Vec<String>), String> {
+        let text = std::fs::read_to_string(fixture(name)).unwrap();
+        check_page("/devices/list", &text).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn strict_schema_reports_unknown_fields() {
+        let (page, unknown_fields) = check_fixture("deployment_devices_3.6.json").unwrap();
+        assert_eq!(page.len(), 3);
+        assert_eq!(unknown_fields, ["deleted", "device_type", "image", "log"]);
+
+        let (_, unknown_fields) = check_fixture("deployment_devices_2.6.json").unwrap();
+        assert_eq!(unknown_fields, ["device_type", "log"]);
+    }
+
+    #[test]
+    fn strict_schema_reports_mismatched_field() {
+        assert_eq!(
+            check_fixture("deployment_devices_mismatched.json").unwrap_err(),
+            "/devices/list item 1: field attempts: invalid type: string \"2\", expected u32"
+        );
+        let err = check_page::<MenderDeploymentDevice>("/devices/list", r#"[{"id": "d1"}]"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("/devices/list item 0: field "), "{}", err);
+        assert!(err.ends_with("missing field `status`"), "{}", err);
+        let err = check_page::<MenderDeploymentDevice>("/devices/list", r#"{"id": "d1"}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("/devices/list: not a JSON array"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn strict_schema_listing() {
+        let mut server = MockMenderServer::new();
+        mock_deployment_devices(&mut server, "deployment_devices_mismatched.json");
+        // The lenient parsing fails without telling where
+        let err = deployment_devices(&server.config(&["deploymentdevices", "d1"]))
+            .unwrap_err()
+            .to_string();
+        assert!(!err.contains("item 1"), "{}", err);
+        let conf = server.config(&["--strict-schema", "deploymentdevices", "d1"]);
+        assert_eq!(
+            deployment_devices(&conf).unwrap_err().to_string(),
+            format!(
+                "{}/d1/devices/list item 1: field attempts: invalid type: string \"2\", expected u32",
+                DEPLOY_API
+            )
+        );
+
+        let mut server = MockMenderServer::new();
+        mock_deployment_devices(&mut server, "deployment_devices_3.6.json");
+        let conf = server.config(&["--strict-schema", "deploymentdevices", "d1"]);
+        assert_eq!(deployment_devices(&conf).unwrap().lines().count(), 3);
+    }
+
+    /// Accepted devices listed when the identity index is built, with the mac of their
+    /// identity data.
+    fn mock_accepted_identities(
+        server: &mut MockMenderServer,
+        devices: &[(&str, &str, &str)],
+    ) -> mockito::Mock {
+        server.empty_page(GET_DEVICES_AUTH_API, 2);
+        server
+            .mock("GET", GET_DEVICES_AUTH_API)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("status".into(), "accepted".into()),
+                Matcher::UrlEncoded("page".into(), "1".into()),
+            ]))
+            .with_body(
+                serde_json::json!(devices
+                    .iter()
+                    .map(|(id, mac, created_ts)| identity(id, mac, created_ts))
+                    .collect::<Vec<_>>())
+                .to_string(),
+            )
+            .create()
+    }
+
+    fn identity(id: &str, mac: &str, created_ts: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "status": "accepted",
+            "created_ts": created_ts,
+            "identity_data": {"mac": mac},
+        })
+    }
+
+    fn mock_identity(
+        server: &mut MockMenderServer,
+        id: &str,
+        mac: &str,
+        created_ts: &str,
+    ) -> mockito::Mock {
+        server
+            .mock("GET", format!("{}/{}", GET_DEVICES_AUTH_API, id).as_str())
+            .with_body(identity(id, mac, created_ts).to_string())
+            .create()
+    }
+
+    /// Devices of the inventory sorted by decreasing creation time, for the refresh.
+    fn mock_created_devices(server: &mut MockMenderServer, devices: &[(&str, &str)]) {
+        server.empty_page(GET_DEVICES_INVENTORY_API, 2);
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("sort".into(), "created_ts:desc".into()),
+                Matcher::UrlEncoded("page".into(), "1".into()),
+            ]))
+            .with_body(
+                serde_json::json!(devices
+                    .iter()
+                    .map(|(id, created_ts)| serde_json::json!({
+                        "id": id,
+                        "attributes": [{"name": "created_ts", "value": created_ts}],
+                    }))
+                    .collect::<Vec<_>>())
+                .to_string(),
+            )
+            .create();
+    }
+
+    fn search_index(
+        conf: &Config,
+        mac: &str,
+        rebuild: bool,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let client = blocking_client(conf)?;
+        search_identity_index(conf, &client, mock_server::TOKEN, "mac", mac, rebuild)
+    }
+
+    fn index_config(server: &MockMenderServer, cache_dir: &tempfile::TempDir) -> Config {
+        let mut conf = server.config(&["showconfig"]);
+        conf.cache_dir = Some(cache_dir.path().to_path_buf());
+        conf
+    }
+
+    const T1: &str = "2023-05-02T08:00:00Z";
+    const T2: &str = "2023-05-02T09:00:00Z";
+
+    #[test]
+    fn identity_index_is_built_then_refreshed() {
+        let mut server = MockMenderServer::new();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let conf = index_config(&server, &cache_dir);
+        let build = mock_accepted_identities(&mut server, &[("d1", "m1", T1)]).expect(1);
+        mock_identity(&mut server, "d1", "m1", T1);
+        mock_created_devices(&mut server, &[("d2", T2), ("d1", T1)]);
+        mock_identity(&mut server, "d2", "m2", T2);
+
+        assert_eq!(search_index(&conf, "m1", false).unwrap(), ["d1"]);
+        // d2 is found by the refresh, without a rebuild
+        assert_eq!(search_index(&conf, "m2", false).unwrap(), ["d2"]);
+        assert_eq!(search_index(&conf, "m1", false).unwrap(), ["d1"]);
+        build.assert();
+    }
+
+    #[test]
+    fn unknown_value_does_not_rebuild_recent_index() {
+        let mut server = MockMenderServer::new();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let conf = index_config(&server, &cache_dir);
+        let build = mock_accepted_identities(&mut server, &[("d1", "m1", T1)]).expect(1);
+        mock_created_devices(&mut server, &[("d1", T1)]);
+
+        for _ in 0..3 {
+            assert!(search_index(&conf, "unknown", false).unwrap().is_empty());
+        }
+        build.assert();
+    }
+
+    #[test]
+    fn stale_index_or_explicit_rebuild() {
+        let mut server = MockMenderServer::new();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let conf = index_config(&server, &cache_dir);
+        let build = mock_accepted_identities(&mut server, &[("d1", "m1", T1)]).expect(3);
+        mock_created_devices(&mut server, &[("d1", T1)]);
+
+        assert!(search_index(&conf, "unknown", false).unwrap().is_empty());
+        let mut index: IdentityIndex =
+            cache::load(conf.cache_dir.as_deref(), IDENTITY_INDEX, &conf.server_url).unwrap();
+        assert!(!index.is_stale());
+        let built_ts = chrono::Utc::now() - chrono::Duration::hours(25);
+        index.built_ts = Some(built_ts.to_rfc3339());
+        assert!(index.is_stale());
+        cache::save(
+            conf.cache_dir.as_deref(),
+            IDENTITY_INDEX,
+            &conf.server_url,
+            &index,
+        )
+        .unwrap();
+        // Rebuilt as it is stale, then only refreshed
+        assert!(search_index(&conf, "unknown", false).unwrap().is_empty());
+        assert!(search_index(&conf, "unknown", false).unwrap().is_empty());
+        assert!(search_index(&conf, "unknown", true).unwrap().is_empty());
+        build.assert();
+
+        // Indexes saved by older versions have no build time
+        index.built_ts = None;
+        assert!(index.is_stale());
+    }
+
+    #[test]
+    fn devices_created_in_the_same_second_are_indexed_once() {
+        let mut server = MockMenderServer::new();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let conf = index_config(&server, &cache_dir);
+        mock_accepted_identities(&mut server, &[("d1", "m1", T2), ("d0", "m0", T1)]);
+        let d1 = mock_identity(&mut server, "d1", "m1", T2).expect(0);
+        mock_created_devices(&mut server, &[("d2", T2), ("d1", T2), ("d0", T1)]);
+        let d2 = mock_identity(&mut server, "d2", "m2", T2).expect(3);
+
+        assert_eq!(search_index(&conf, "m1", false).unwrap(), ["d1"]);
+        // d2 is added by the refresh, d1 created in the same second is already indexed
+        assert_eq!(search_index(&conf, "m2", false).unwrap(), ["d2"]);
+        assert_eq!(search_index(&conf, "m2", false).unwrap(), ["d2"]);
+        d1.assert();
+        d2.assert();
+    }
+
+    #[test]
+    fn at_or_after() {
+        assert!(is_at_or_after(T2, Some(T1)));
+        assert!(is_at_or_after(T1, Some(T1)));
+        assert!(is_at_or_after("2023-05-02T10:00:00+02:00", Some(T1)));
+        assert!(!is_at_or_after(T1, Some(T2)));
+        assert!(is_at_or_after(T1, None));
+        assert!(is_at_or_after(T1, Some("unknown")));
+        assert!(!is_at_or_after("unknown", None));
+    }
+
+    fn mock_device_deployment(
+        server: &mut MockMenderServer,
+        devices: &[&str],
+        name: &str,
+    ) -> mockito::Mock {
+        server
+            .mock("GET", DEPLOYMENT_LIMITS_API)
+            .with_status(404)
+            .create();
+        server
+            .mock("POST", DEPLOY_API)
+            .match_body(Matcher::Json(serde_json::json!({
+                "artifact_name": "release-2",
+                "name": name,
+                "devices": devices,
+            })))
+            .with_status(201)
+            .create()
+    }
+
+    #[test]
+    fn deploy_to_devices_without_group() {
+        for (device_args, devices, name) in &[
+            (&["--device", "d1"][..], &["d1"][..], "d1"),
+            (
+                &["--device", "d1,d2", "--device", "d3"][..],
+                &["d1", "d2", "d3"][..],
+                "d1,d2,d3",
+            ),
+        ] {
+            let mut server = MockMenderServer::new();
+            let posted = mock_device_deployment(&mut server, devices, name);
+            let args = [
+                &["deploy", "release-2", "--allow-downgrade"][..],
+                device_args,
+            ]
+            .concat();
+            assert_eq!(deploy(&server.config(&args)).unwrap(), devices.len());
+            posted.assert();
+        }
+    }
+
+    #[test]
+    fn deploy_without_target_is_rejected() {
+        let mut server = MockMenderServer::new();
+        let posted = mock_device_deployment(&mut server, &[], "").expect(0);
+        let mut conf = server.config(&["deploy", "release-2", "--device", "d1"]);
+        if let Command::Deploy { device, .. } = &mut conf.command {
+            *device = Some(vec![]);
+        }
+        assert_eq!(
+            deploy(&conf).unwrap_err().to_string(),
+            "A group or a device id must be provided for deployment"
+        );
+        posted.assert();
+    }
+
+    /// Serve the devices of the inventory with SerialNumber SN-1 and the accepted devices
+    /// of the authentication service, each device having its identity.
+    fn mock_matches(
+        server: &mut MockMenderServer,
+        inventory: &[&str],
+        identity_data: &[(&str, &str)],
+    ) {
+        server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(Matcher::UrlEncoded("SerialNumber".into(), "SN-1".into()))
+            .with_body(
+                serde_json::json!(inventory
+                    .iter()
+                    .map(|id| device_with_serial_number(id, "SN-1"))
+                    .collect::<Vec<_>>())
+                .to_string(),
+            )
+            .create();
+        server.empty_page(GET_DEVICES_AUTH_API, 2);
+        server
+            .mock("GET", GET_DEVICES_AUTH_API)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("status".into(), "accepted".into()),
+                Matcher::UrlEncoded("page".into(), "1".into()),
+            ]))
+            .with_body(
+                serde_json::json!(identity_data
+                    .iter()
+                    .map(|(id, serial_number)| serde_json::json!({
+                        "id": id,
+                        "status": "accepted",
+                        "identity_data": {"SerialNumber": serial_number},
+                    }))
+                    .collect::<Vec<_>>())
+                .to_string(),
+            )
+            .create();
+        for id in inventory
+            .iter()
+            .chain(identity_data.iter().map(|(id, _)| id))
+        {
+            server
+                .mock("GET", format!("{}/{}", GET_DEVICES_AUTH_API, id).as_str())
+                .with_body(serde_json::json!({"id": id, "status": "accepted"}).to_string())
+                .create();
+        }
+    }
+
+    fn get_id_of_sn_1(server: &MockMenderServer, args: &[&str]) -> Result<String, String> {
+        let conf = server.config(&[&["--no-interactive", "getid", "SN-1"], args].concat());
+        get_id(&conf).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn get_id_without_match() {
+        let mut server = MockMenderServer::new();
+        mock_matches(&mut server, &[], &[("b", "SN-2")]);
+        assert_eq!(
+            get_id_of_sn_1(&server, &[]).unwrap_err(),
+            "SerialNumber not found"
+        );
+    }
+
+    #[test]
+    fn get_id_of_single_match() {
+        let mut server = MockMenderServer::new();
+        mock_matches(&mut server, &["a"], &[]);
+        assert_eq!(get_id_of_sn_1(&server, &[]).unwrap(), "a");
+
+        // Found in the identity data when the inventory doesn't have it
+        let mut server = MockMenderServer::new();
+        mock_matches(&mut server, &[], &[("a", "SN-2"), ("b", "SN-1")]);
+        assert_eq!(get_id_of_sn_1(&server, &[]).unwrap(), "b");
+    }
+
+    #[test]
+    fn get_id_reports_all_matches() {
+        let mut server = MockMenderServer::new();
+        mock_matches(&mut server, &["a", "b"], &[]);
+        assert_eq!(
+            get_id_of_sn_1(&server, &[]).unwrap_err(),
+            "2 devices with SerialNumber SN-1, use --first to take the first one:\n\
+             a SerialNumber SN-1 group <none> last update <unknown> auth status accepted\n\
+             b SerialNumber SN-1 group <none> last update <unknown> auth status accepted"
+        );
+        assert_eq!(get_id_of_sn_1(&server, &["--first"]).unwrap(), "a");
+
+        // The identity data scan doesn't stop at the first match
+        let mut server = MockMenderServer::new();
+        mock_matches(
+            &mut server,
+            &[],
+            &[("a", "SN-1"), ("b", "SN-2"), ("c", "SN-1")],
+        );
+        assert_eq!(
+            get_id_of_sn_1(&server, &[]).unwrap_err(),
+            "2 devices with SerialNumber SN-1, use --first to take the first one:\n\
+             a SerialNumber SN-1 auth status accepted\n\
+             c SerialNumber SN-1 auth status accepted"
+        );
+        assert_eq!(get_id_of_sn_1(&server, &["--first"]).unwrap(), "a");
+    }
+
+    #[test]
+    fn serial_number_reference_with_several_matches() {
+        let mut server = MockMenderServer::new();
+        mock_matches(&mut server, &["a", "b"], &[]);
+        let conf = server.config(&["--no-interactive", "getinfo", "sn:SN-1"]);
+        let err = get_info(&conf).unwrap_err().to_string();
+        assert!(
+            err.ends_with(
+                ": 2 devices with SerialNumber SN-1:\n\
+                 a SerialNumber SN-1 group <none> last update <unknown> auth status accepted\n\
+                 b SerialNumber SN-1 group <none> last update <unknown> auth status accepted"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    /// Mock the requests of the selftest steps not changing the server, with artifact
+    /// release-1 stored and device d1 in inventory. The mock of the deployment limits,
+    /// requested by the last step, is returned.
+    fn mock_selftest_server(server: &mut MockMenderServer) -> mockito::Mock {
+        server
+            .mock("GET", CURRENT_USER_API)
+            .with_body(r#"{"email": "admin@example.com"}"#)
+            .create();
+        server
+            .mock("GET", format!("{}/d1", GET_DEVICES_INVENTORY_API).as_str())
+            .with_body(
+                r#"{"id": "d1", "attributes": [
+                    {"name": "SerialNumber", "value": "SN1"},
+                    {"name": "artifact_name", "value": "release-1"}
+                ]}"#,
+            )
+            .create();
+        server
+            .mock("GET", GROUPS_API)
+            .with_body(r#"["production", "lab"]"#)
+            .create();
+        server
+            .mock("GET", ARTIFACTS_LIST_API)
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(
+                r#"[{"id": "a1", "name": "release-1", "device_types_compatible": ["rpi4"],
+                     "modified": "2023-05-02T08:00:00Z"}]"#,
+            )
+            .create();
+        server.empty_page(ARTIFACTS_LIST_API, 2);
+        mock_deployments(server, &["dep1", "dep2"]);
+        server
+            .mock("GET", DEPLOYMENT_LIMITS_API)
+            .with_status(404)
+            .create()
+    }
+
+    /// Status and details of each step of a selftest report.
+    fn selftest_steps(report: &str) -> Vec<(String, String)> {
+        report
+            .lines()
+            .map(|line| {
+                let (name, result) = line.split_at(20);
+                let mut words = result.split_whitespace();
+                let status = words.next().unwrap().to_string();
+                let details: Vec<&str> = match status.as_str() {
+                    "SKIPPED" => words.collect(),
+                    _ => words.skip(2).collect(),
+                };
+                (format!("{} {}", name.trim(), status), details.join(" "))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn selftest_passes() {
+        let mut server = MockMenderServer::new();
+        mock_selftest_server(&mut server);
+        let conf = server.config(&["selftest", "--device", "d1", "--artifact", "release-1"]);
+        assert_eq!(
+            selftest_steps(&selftest(&conf).unwrap()),
+            [
+                ("whoami PASS", "admin@example.com"),
+                ("getinfo PASS", "2 attributes"),
+                ("listgroups PASS", "2 groups"),
+                ("listartifacts PASS", "1 artifacts"),
+                ("listdeployments PASS", "2 deployments"),
+                (
+                    "deploy --dry-run PASS",
+                    "deployment to 1 device within the limits"
+                ),
+                ("deploy and abort SKIPPED", "changes the server"),
+            ]
+            .iter()
+            .map(|(step, details)| (step.to_string(), details.to_string()))
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn selftest_fails_on_a_failed_step() {
+        let mut server = MockMenderServer::new();
+        let limits = mock_selftest_server(&mut server);
+        let conf = server.config(&["selftest", "--device", "d1", "--artifact", "release-9"]);
+        let err = selftest(&conf).unwrap_err();
+        assert_eq!(err.to_string(), "1 selftest steps failed");
+        assert_eq!(exit_code(err.as_ref()), 2);
+        // The steps after the failed one are still run
+        limits.assert();
+    }
+
+    #[test]
+    fn group_name_is_percent_encoded() {
+        let mut server = MockMenderServer::new();
+        let devices_api = format!("{}/a%2Fb%20c%3F/devices", GROUPS_API);
+        server
+            .mock("GET", &*devices_api)
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(r#"["d1", "d2"]"#)
+            .create();
+        server.empty_page(&devices_api, 2);
+        let conf = server.config(&["groupdevices", "a/b c?"]);
+        assert_eq!(group_devices(&conf).unwrap(), vec!["d1", "d2"]);
+
+        server
+            .mock("GET", GROUPS_API)
+            .with_body(r#"["a/b c?"]"#)
+            .create();
+        server
+            .mock("GET", &*devices_api)
+            .match_query(Matcher::UrlEncoded("per_page".into(), "1".into()))
+            .with_header("X-Total-Count", "2")
+            .with_body(r#"["d1"]"#)
+            .create();
+        let deleted = server
+            .mock("DELETE", &*format!("{}/a%2Fb%20c%3F", GROUPS_API))
+            .with_status(204)
+            .create();
+        let conf = server.config(&["--yes", "deletegroup", "--name", "a/b c?"]);
+        assert_eq!(
+            delete_group(&conf).unwrap(),
+            "Group a/b c? deleted, 2 devices removed from it"
+        );
+        deleted.assert();
     }
-    disp
 }
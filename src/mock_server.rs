@@ -0,0 +1,65 @@
+//! Mender server simulated with mockito, to run the commands in the tests.
+
+use super::parse::{build_cli, Command, Config};
+
+pub const TOKEN: &str = "mock-token";
+
+pub struct MockMenderServer {
+    pub server: mockito::ServerGuard,
+}
+
+impl MockMenderServer {
+    pub fn new() -> MockMenderServer {
+        MockMenderServer {
+            server: mockito::Server::new(),
+        }
+    }
+
+    /// Expect a request with the token of the configurations returned by config.
+    pub fn mock(&mut self, method: &str, path: impl Into<mockito::Matcher>) -> mockito::Mock {
+        self.server
+            .mock(method, path)
+            .match_header("authorization", format!("Bearer {}", TOKEN).as_str())
+    }
+
+    /// Answer an empty list to the given page of a listing, which ends the pagination.
+    pub fn empty_page(&mut self, path: &str, page: usize) -> mockito::Mock {
+        self.mock("GET", path)
+            .match_query(mockito::Matcher::UrlEncoded(
+                "page".into(),
+                page.to_string(),
+            ))
+            .with_body("[]")
+            .create()
+    }
+
+    /// Configuration of the command line args run against the server.
+    pub fn config(&self, args: &[&str]) -> Config {
+        config(&self.server.url(), args)
+    }
+}
+
+/// Configuration of the command line args with the given server, with a token. The
+/// configuration file, the profiles and the cache of the user are ignored so that only
+/// args apply.
+pub fn config(server_url: &str, args: &[&str]) -> Config {
+    let matches = build_cli()
+        .get_matches_from_safe(
+            [
+                "mender-rust",
+                "--server-url",
+                server_url,
+                "--config",
+                "/dev/null",
+            ]
+            .iter()
+            .chain(args.iter())
+            .copied(),
+        )
+        .unwrap();
+    let mut conf = Config::with_config_dir(Command::ShowConfig, &matches, None).unwrap();
+    conf.command = Command::new(&matches).unwrap();
+    conf.token = Some(TOKEN.to_string());
+    conf.cache_dir = None;
+    conf
+}
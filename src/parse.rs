@@ -1,4 +1,51 @@
+use super::cache;
+use super::config_file;
+use super::env_file::Environment;
+use super::profile;
+use super::time::{parse_duration, parse_start_time};
+use super::timings::Timings;
 use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const DEVICE_HELP: &str =
+    "Mender id or SerialNumber of the device, prefix with id: or sn: to force the interpretation";
+
+/// Exit statuses of the tool, documented in the manual page.
+pub const EXIT_CODES: [(i32, &str); 5] = [
+    (0, "Success"),
+    (1, "Parse or configuration error, or no group found by listgroupsbyartifact"),
+    (2, "Error while running the command"),
+    (
+        3,
+        "Unexpected number of devices with deploy --expect-count, or failed devices reported by deploymentstatus",
+    ),
+    (
+        4,
+        "Deadline of --deadline reached, what completed before it is reported",
+    ),
+];
+
+/// Usage examples of the manual page, as a command line and its description.
+pub const EXAMPLES: [(&str, &str); 4] = [
+    (
+        "mender-rust login admin@example.com --save",
+        "Ask for the password and save the token of the default profile",
+    ),
+    (
+        "mender-rust deploy --group production release-1.2 --wait",
+        "Deploy artifact release-1.2 to the devices of group production and wait for the end of the deployment",
+    ),
+    (
+        "mender-rust getinfo sn:A1234",
+        "Show the inventory of the device whose SerialNumber is A1234",
+    ),
+    (
+        "mender-rust --output json listgroups",
+        "List the groups with their number of devices as JSON",
+    ),
+];
 
 pub fn build_cli() -> App<'static, 'static> {
     App::new("mender-rust")
@@ -7,9 +54,165 @@ pub fn build_cli() -> App<'static, 'static> {
         .about("A small command line tool to perform tasks on a Mender server using its APIs.")
         .after_help(
             "ENVIRONMENT VARIABLES:
-    SERVER_URL  Url of the mender server, must be provided
-    TOKEN       Authentication token, must be provided for all subcommands except login and help
-    CERT_FILE   Optional certificate for the SSL connection to the server",
+    SERVER_URL        Url of the mender server, must be provided
+    TOKEN             Authentication token, must be provided for all subcommands except login,
+                      logout, checkhealth, getpaginationdefaults, showconfig and help, unless
+                      a token has been saved for the profile with login --save
+    CERT_FILE         Optional certificate, PEM or DER, for the SSL connection to the server
+    HTTPS_PROXY, HTTP_PROXY
+                      Url of a proxy through which all the requests are sent, HTTPS_PROXY
+                      takes precedence, --proxy takes precedence over both
+    MENDER_PAGE_SIZE  Number of items requested per page in listings, default is 500
+    MENDER_CONCURRENT_REQUESTS, MENDER_REQUEST_TIMEOUT, MENDER_DEADLINE
+                      Defaults of --concurrent-requests, --request-timeout and --deadline
+    MENDER_RETRIES, MENDER_RETRY_DELAY_MS
+                      Defaults of --retries and --retry-delay-ms
+    MENDER_PROFILE    Profile used when --profile is not given
+    MENDER_RECORD_GROUP_HISTORY
+                      Set to 1 to record group changes as with --record-history
+    These variables can also be set in the file given with --env-file, the real environment
+    takes precedence over it.
+
+CONFIGURATION FILE:
+    server_url, cert_file and token can also be set in ~/.mender-rust/config.toml, or in the
+    file given with --config. The environment variables take precedence over the file and
+    --server-url over both. max_failure_rate and max_failures set the defaults of the
+    options of the same name, and per_page in a [pagination] table the default of
+    MENDER_PAGE_SIZE.
+
+PROFILES:
+    login --save saves the token of the profile under $XDG_CONFIG_HOME/mender-rust/tokens,
+    or ~/.config/mender-rust/tokens. The default profile falls back to the token saved in
+    ~/.mender-rust/token by older versions, logout removes both. Its settings are read from profiles/<profile>.toml in
+    the same directory: server_url, output (text or json), assume_yes (as --yes),
+    confirm_server, a name to type to confirm destructive actions instead of y, and
+    concurrent_requests, request_timeout and deadline, the defaults of the options of the
+    same name. They take precedence over the configuration file, the command line and the
+    environment over them.",
+        )
+        .arg(
+            Arg::with_name("config")
+                .help("Configuration file, default is ~/.mender-rust/config.toml")
+                .long("config")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("env-file")
+                .help("File of KEY=VALUE lines setting the environment variables for this invocation")
+                .long("env-file")
+                .alias("from-env-file")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("server-url")
+                .help("Url of the mender server, takes precedence over SERVER_URL")
+                .long("server-url")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .help("Url of a proxy through which all the requests are sent, takes precedence over HTTPS_PROXY and HTTP_PROXY")
+                .long("proxy")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("concurrent-requests")
+                .help("Maximum number of pages requested at once in listings, default is 1")
+                .long("concurrent-requests")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("request-timeout")
+                .help("Maximum duration of each HTTP request, e.g. 30s or 2m")
+                .long("request-timeout")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("deadline")
+                .help("Maximum duration of the whole command, remaining requests fail once it is reached")
+                .long("deadline")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .help("Number of retries of the requests failing on a network error, 429 or 5xx, default is 3")
+                .long("retries")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("retry-delay-ms")
+                .help("Delay before the first retry in milliseconds, doubled on each retry, default is 500")
+                .long("retry-delay-ms")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("ignore-case")
+                .help("Ignore the case when looking up devices by SerialNumber")
+                .long("ignore-case")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("trim")
+                .help("Ignore leading and trailing spaces when looking up devices by SerialNumber")
+                .long("trim")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .help("Do not ask for confirmation before destructive actions")
+                .long("yes")
+                .short("y")
+                .alias("force")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .help("Only print what destructive actions would do, takes precedence over --yes")
+                .long("dry-run")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("no-interactive")
+                .help("Fail instead of asking which one to use when several devices or auth sets match")
+                .long("no-interactive")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("strict-schema")
+                .help("Check the listings returned by the server against the expected fields and types, to debug incompatible servers")
+                .long("strict-schema")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .help("Profile whose settings and saved token are used, default is 'default'")
+                .long("profile")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .help("Format of the results, default is text, with json progress information is printed on stderr")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("timings")
+                .help("Print a summary of the time spent in HTTP requests")
+                .long("timings")
+                .global(true),
         )
         .subcommand(
             SubCommand::with_name("login")
@@ -18,14 +221,62 @@ pub fn build_cli() -> App<'static, 'static> {
                     Arg::with_name("email")
                         .help("User email used to login to Mender server")
                         .required(true),
+                )
+                .arg(
+                    Arg::with_name("save")
+                        .help("Save the token for the profile instead of printing it")
+                        .long("save"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("logout").about("Remove the token saved for the profile"),
+        )
         .subcommand(
             SubCommand::with_name("getid")
-                .about("Get the mender id of a device from its SerialNumber attribute")
+                .about("Get the mender id of a device from its SerialNumber or another attribute")
+                .arg(
+                    Arg::with_name("value")
+                        .help("Value of the attribute of the device")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("attribute")
+                        .help("Inventory or identity attribute to search, like mac or hostname")
+                        .long("attribute")
+                        .visible_alias("key")
+                        .default_value("SerialNumber"),
+                )
                 .arg(
-                    Arg::with_name("serial number")
-                        .help("SerialNumber attribute of the device")
+                    Arg::with_name("first")
+                        .help("Take the first device when several match instead of failing")
+                        .long("first"),
+                )
+                .arg(
+                    Arg::with_name("index")
+                        .help(
+                            "Search the identity data in an index kept in the cache directory, \
+                             refreshed with the devices created since the last run and rebuilt \
+                             when a value is not found in an index older than a day",
+                        )
+                        .long("index"),
+                )
+                .arg(
+                    Arg::with_name("rebuild-index")
+                        .help("Rebuild the identity index from all accepted devices, implies --index")
+                        .long("rebuild-index"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("findid")
+                .about("Get the mender ids of all the devices having a value of an inventory attribute")
+                .arg(
+                    Arg::with_name("attribute")
+                        .help("Name of the inventory attribute, like hostname or ip_address")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("value")
+                        .help("Value of the attribute")
                         .required(true),
                 ),
         )
@@ -33,14 +284,33 @@ pub fn build_cli() -> App<'static, 'static> {
             SubCommand::with_name("getinfo")
                 .about("Get info of a device")
                 .arg(
-                    Arg::with_name("id")
-                        .help("Mender id of the device")
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
                         .required(true),
+                )
+                .arg(
+                    Arg::with_name("attr")
+                        .help("Only print this attribute as name=value, can be repeated")
+                        .long("attr")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
                 ),
         )
         .subcommand(
             SubCommand::with_name("countartifacts")
-                .about("List artifacts and count how much devices are using each"),
+                .about("List artifacts and count how much devices are using each")
+                .arg(
+                    Arg::with_name("group")
+                        .help("Only count devices of this group")
+                        .long("group")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("refresh")
+                        .help("Count the devices even if the cached result looks up to date")
+                        .long("refresh"),
+                ),
         )
         .subcommand(
             SubCommand::with_name("deploy")
@@ -49,16 +319,21 @@ pub fn build_cli() -> App<'static, 'static> {
                     Arg::with_name("group")
                         .help("Name of the group to which the update will be deployed")
                         .short("g")
+                        .long("group")
                         .required_unless("device")
                         .conflicts_with("device")
                         .takes_value(true),
                 )
                 .arg(
                     Arg::with_name("device")
-                        .help("Id of the device to which the update will be deployed")
+                        .help("Id of a device to which the update will be deployed, can be repeated or comma separated")
                         .short("d")
+                        .long("device")
                         .required_unless("group")
-                        .takes_value(true),
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .use_delimiter(true),
                 )
                 .arg(
                     Arg::with_name("artifact")
@@ -68,96 +343,2687 @@ pub fn build_cli() -> App<'static, 'static> {
                 .arg(
                     Arg::with_name("name")
                         .help("Name of the deployment, if not present device/group name is used"),
+                )
+                .arg(
+                    Arg::with_name("allow-downgrade")
+                        .help("Deploy even if most devices run a newer version of the artifact")
+                        .long("allow-downgrade"),
+                )
+                .arg(
+                    Arg::with_name("start-at")
+                        .help("Start time of the deployment, e.g. '02:00', 'tomorrow 02:00', '2021-06-01 02:00 +02:00', local timezone is used if none given")
+                        .long("start-at")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("wait")
+                        .help("Wait for the deployment to finish before returning")
+                        .long("wait"),
+                )
+                .arg(
+                    Arg::with_name("wait-timeout")
+                        .help("With --wait, maximum time to wait, e.g. 90s, 30m or 2h")
+                        .long("wait-timeout")
+                        .default_value("2h"),
+                )
+                .arg(
+                    Arg::with_name("wait-interval")
+                        .help("With --wait, time between two checks of the deployment status")
+                        .long("wait-interval")
+                        .default_value("10s"),
+                )
+                .arg(
+                    Arg::with_name("max-failure-rate")
+                        .help("With --wait, fail if the percentage of failed devices is above this value")
+                        .long("max-failure-rate")
+                        .takes_value(true)
+                        .requires("wait"),
+                )
+                .arg(
+                    Arg::with_name("max-failures")
+                        .help("With --wait, fail if the number of failed devices is above this value")
+                        .long("max-failures")
+                        .takes_value(true)
+                        .requires("wait"),
+                )
+                .arg(
+                    Arg::with_name("artifact-checksum")
+                        .help("Refuse to deploy if the SHA-256 of the artifact on the server is not this one")
+                        .long("artifact-checksum")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("summary-file")
+                        .help("Write a JSON summary of the deployment to this file")
+                        .long("summary-file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("expect-count")
+                        .help("Abort with exit code 3 unless the number of devices is N or within MIN..MAX")
+                        .long("expect-count")
+                        .takes_value(true),
                 ),
         )
-}
-
-pub struct Config {
-    pub command: Command,
-    pub token: Option<String>,
-    pub server_url: String,
-    pub cert_file: Option<String>,
-}
-
-impl Config {
-    pub fn new(command: Command) -> Result<Config, &'static str> {
-        let server_url = if let Ok(url) = std::env::var("SERVER_URL") {
-            url
-        } else {
-            return Err("SERVER_URL env variable must be defined");
-        };
-        let token = if let Ok(token) = std::env::var("TOKEN") {
-            Some(token)
-        } else {
-            None
-        };
-        let cert_file = if let Ok(cert) = std::env::var("CERT_FILE") {
-            Some(cert)
-        } else {
-            None
-        };
-        match &command {
-            Command::Deploy { .. }
-            | Command::GetId { .. }
-            | Command::GetInfo { .. }
-            | Command::CountArtifacts
-                if token == None =>
-            {
-                return Err(
-                    "TOKEN must be provided for deploy, getid, getinfo and countartifacts commands",
+        .subcommand(
+            SubCommand::with_name("deploymentstatus")
+                .about("Follow a deployment until no device is pending or updating, exit code is 3 if a device failed")
+                .arg(
+                    Arg::with_name("deployment-id")
+                        .help("Id of the deployment")
+                        .long("deployment-id")
+                        .takes_value(true)
+                        .required(true),
                 )
-            }
-            _ => (),
-        }
-        Ok(Config {
-            command,
-            token,
-            server_url,
-            cert_file,
-        })
-    }
-}
-
-#[derive(PartialEq, Debug)]
-pub enum Command {
-    Login {
-        email: String,
-    },
-    Deploy {
-        group: Option<String>,
-        device: Option<String>,
-        artifact: String,
-        name: Option<String>,
-    },
-    GetId {
-        serial_number: String,
-    },
-    GetInfo {
-        id: String,
-    },
-    CountArtifacts,
-}
-
-impl Command {
-    pub fn new(args: ArgMatches) -> Result<Command, &'static str> {
-        match args.subcommand() {
-            ("countartifacts", _) => Ok(Command::CountArtifacts),
-            ("login", Some(sub_args)) => Ok(Command::Login {
-                email: sub_args.value_of("email").unwrap().to_string(),
-            }),
-            ("deploy", Some(sub_args)) => Ok(Command::Deploy {
-                group: sub_args.value_of("group").map(|s| s.to_string()),
-                device: sub_args.value_of("device").map(|s| s.to_string()),
-                artifact: sub_args.value_of("artifact").unwrap().to_string(),
-                name: sub_args.value_of("name").map(|s| s.to_string()),
-            }),
-            ("getid", Some(sub_args)) => Ok(Command::GetId {
-                serial_number: sub_args.value_of("serial number").unwrap().to_string(),
-            }),
-            ("getinfo", Some(sub_args)) => Ok(Command::GetInfo {
-                id: sub_args.value_of("id").unwrap().to_string(),
-            }),
-            _ => return Err("unrecognized or no subcommand, see help for available subcommands"),
-        }
+                .arg(
+                    Arg::with_name("poll-interval-secs")
+                        .help("Seconds between two polls of the deployment statistics")
+                        .long("poll-interval-secs")
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .help("Maximum time to wait, e.g. 90s, 30m or 2h")
+                        .long("timeout")
+                        .default_value("2h"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Get statistics of a deployment")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Id of the deployment")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("max-failure-rate")
+                        .help("Fail if the percentage of failed devices is above this value")
+                        .long("max-failure-rate")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-failures")
+                        .help("Fail if the number of failed devices is above this value")
+                        .long("max-failures")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Search devices by inventory attributes")
+                .arg(
+                    Arg::with_name("attribute")
+                        .help("Attribute filter as name=value, can be repeated")
+                        .long("attribute")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("missing-attribute")
+                        .help("Only keep devices not reporting this attribute at all, can be repeated")
+                        .long("missing-attribute")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .help("Only search devices of this group")
+                        .long("group")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .help("Preset of columns: narrow for id, serial and artifact, wide adds group, device_type, status and last_seen")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["narrow", "wide"]),
+                )
+                .arg(
+                    Arg::with_name("columns")
+                        .help("Comma separated columns to print, instead of a preset")
+                        .long("columns")
+                        .takes_value(true)
+                        .use_delimiter(true)
+                        .possible_values(&DeviceColumn::NAMES)
+                        .conflicts_with_all(&["format"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("setdeviceattributesfromfile")
+                .about("Set attributes of a device from a JSON file")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .help("JSON file with a list of {\"name\", \"value\", \"scope\"} objects")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("listdevices")
+                .about("List devices with their SerialNumber and group")
+                .arg(
+                    Arg::with_name("group")
+                        .help("Only list devices of this group")
+                        .long("group")
+                        .takes_value(true)
+                        .conflicts_with_all(&["group-regex", "ungrouped"]),
+                )
+                .arg(
+                    Arg::with_name("group-regex")
+                        .help("Only list devices of the groups matching this regex")
+                        .long("group-regex")
+                        .takes_value(true)
+                        .conflicts_with("ungrouped"),
+                )
+                .arg(
+                    Arg::with_name("ungrouped")
+                        .help("Only list devices which are not in any group")
+                        .long("ungrouped"),
+                )
+                .arg(
+                    Arg::with_name("status")
+                        .help("Only list devices with this authentication status, as one JSON object per line")
+                        .long("status")
+                        .takes_value(true)
+                        .possible_values(&["accepted", "pending", "rejected", "preauthorized"])
+                        .conflicts_with_all(&["group", "group-regex", "ungrouped"]),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .help("Preset of columns: narrow for id, serial and artifact, wide adds group, device_type, status and last_seen")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["narrow", "wide"])
+                        .conflicts_with("status"),
+                )
+                .arg(
+                    Arg::with_name("columns")
+                        .help("Comma separated columns to print, instead of a preset")
+                        .long("columns")
+                        .takes_value(true)
+                        .use_delimiter(true)
+                        .possible_values(&DeviceColumn::NAMES)
+                        .conflicts_with_all(&["format", "status"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("devicecount")
+                .about("Count the devices of each authentication status")
+                .visible_alias("countdevices"),
+        )
+        .subcommand(
+            SubCommand::with_name("man")
+                .about("Print the manual page in roff format, or write it to a directory")
+                .arg(
+                    Arg::with_name("out-dir")
+                        .help("Directory where mender-rust.1 is written")
+                        .long("out-dir")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("groupdevices")
+                .about("List the ids of the devices of a group")
+                .arg(
+                    Arg::with_name("group")
+                        .help("Name of the group")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("countgroups")
+                .about("Count the devices of each group, and the accepted devices in no group"),
+        )
+        .subcommand(SubCommand::with_name("listgroups").about("List all the device groups with their number of devices"))
+        .subcommand(
+            SubCommand::with_name("stale")
+                .about("List the devices whose inventory hasn't been updated for a number of days")
+                .arg(
+                    Arg::with_name("days")
+                        .help("Minimum number of days since the last inventory update")
+                        .long("days")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("getdevicesnotinanygroup")
+                .visible_aliases(&["getdeviceswithoutagroup", "ungrouped"])
+                .about("List devices which are not in any group"),
+        )
+        .subcommand(
+            SubCommand::with_name("getartifactprovides")
+                .about("Get the provides fields of an artifact")
+                .arg(
+                    Arg::with_name("artifact")
+                        .help("Name of the artifact")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("listartifacts")
+                .about("List the artifacts stored on the server"),
+        )
+        .subcommand(
+            SubCommand::with_name("uploadartifact")
+                .about("Upload a .mender artifact file")
+                .arg(
+                    Arg::with_name("file")
+                        .help("Path of the artifact file")
+                        .long("file")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("description")
+                        .help("Description of the artifact")
+                        .long("description")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("listgroupsfordevice")
+                .visible_alias("listdevicegroups")
+                .about("List the groups of a device")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("getgroup")
+                .about("Show the static group of a device, ungrouped if it is in none")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("waitattr")
+                .about("Wait until an inventory attribute of a device reaches a value")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("condition")
+                        .help("Expected attribute value as name=value, name!=value or name~regex")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .help("Maximum time to wait, e.g. 90s, 30m or 2h")
+                        .long("timeout")
+                        .default_value("30m"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .help("Time between two checks of the device inventory")
+                        .long("interval")
+                        .default_value("30s"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("getdeploymenterrors")
+                .about("Summarize the errors of the failed devices of a deployment")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Id of the deployment")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("abortdeployment")
+                .about("Abort a deployment which is not finished")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Id of the deployment")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("deploymentdevices")
+                .about("List the devices of a deployment with their status, substate and attempts")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Id of the deployment")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("stuck")
+                        .help("Only list the unfinished devices started longer ago than this duration, e.g. 2h")
+                        .long("stuck")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("checkhealth")
+                .about("Check the health of Mender services")
+                .arg(
+                    Arg::with_name("service")
+                        .help("Service to check, all services are checked if not present")
+                        .possible_values(&[
+                            "useradm",
+                            "inventory",
+                            "deployments",
+                            "devauth",
+                            "deviceconnect",
+                            "devicemonitor",
+                            "deviceconfig",
+                        ]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("selftest")
+                .about(
+                    "Check the main commands against the server with a known device and artifact, \
+                     without changing anything unless --allow-mutations is given",
+                )
+                .arg(
+                    Arg::with_name("device")
+                        .help("Mender id of a device used by the checks")
+                        .long("device")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("artifact")
+                        .help("Name of an artifact stored on the server used by the checks")
+                        .long("artifact")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("allow-mutations")
+                        .help("Also deploy the artifact to the device and abort the deployment right away")
+                        .long("allow-mutations"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("getdeploymentyaml")
+                .about("Export a deployment as a YAML file")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Id of the deployment")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .help("File to write, YAML is printed if not present")
+                        .long("out")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("exportfilters")
+                .about("Export the definitions of all saved inventory filters as JSON")
+                .arg(
+                    Arg::with_name("out")
+                        .help("File to write, JSON is printed if not present")
+                        .long("out")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("importfilters")
+                .about("Create saved inventory filters from a file written by exportfilters")
+                .arg(
+                    Arg::with_name("file")
+                        .help("JSON file with the filter definitions")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("overwrite")
+                        .help("Replace the filters which already exist instead of skipping them")
+                        .long("overwrite"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("getpaginationdefaults")
+                .about("Print the settings used for listings done page by page"),
+        )
+        .subcommand(
+            SubCommand::with_name("statsattr")
+                .about("Statistics and histogram of a numeric inventory attribute")
+                .arg(
+                    Arg::with_name("attribute")
+                        .help("Name of the attribute")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .help("Only use devices of this group")
+                        .long("group")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("buckets")
+                        .help("Number of buckets of the histogram")
+                        .long("buckets")
+                        .default_value("10"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("getlatestartifactperdevicetype")
+                .about("Get the most recent artifact of each device type"),
+        )
+        .subcommand(
+            SubCommand::with_name("showconfig")
+                .about("Print the configuration in use as JSON, with the token masked"),
+        )
+        .subcommand(
+            SubCommand::with_name("getpendingdeploymentstatsall")
+                .about("Sum up the statistics of all the deployments in progress or pending"),
+        )
+        .subcommand(
+            SubCommand::with_name("setgroup")
+                .about("Move a device to a group")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .help("Name of the group")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("record-history")
+                        .help("Append the change to the group_history tag of the device")
+                        .long("record-history"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("addtogroup")
+                .about("Add a device to a static group, printing its previous group")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .help("Name of the group, made of letters, digits, '-' and '_'")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("assigngroup")
+                .about("Move the devices listed in a file, one id or SerialNumber per line, to a group")
+                .arg(
+                    Arg::with_name("file")
+                        .help("File of the devices")
+                        .long("file")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .help("Name of the group, made of letters, digits, '-' and '_'")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("creategroup")
+                .about("Create a group with devices, a group exists only while it has devices")
+                .arg(
+                    Arg::with_name("name")
+                        .help("Name of the group, made of letters, digits, '-' and '_'")
+                        .long("name")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("device-ids")
+                        .help("Ids of the devices moved to the group, can be repeated or comma separated")
+                        .long("device-ids")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .use_delimiter(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("deletegroup")
+                .about("Delete a group, its devices are left without a group")
+                .arg(
+                    Arg::with_name("name")
+                        .help("Name of the group")
+                        .long("name")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("renamegroup")
+                .about("Move all the devices of a group to another group")
+                .arg(
+                    Arg::with_name("old")
+                        .help("Name of the group to rename")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .help("New name of the group, made of letters, digits, '-' and '_'")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("progress-every")
+                        .help("Number of devices between two progress lines")
+                        .long("progress-every")
+                        .default_value("100"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("removefromgroup")
+                .about("Remove a device from its static group")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                )
+                .arg(Arg::with_name("group").help("Name of the group, default is the group of the device")),
+        )
+        .subcommand(
+            SubCommand::with_name("setgroupfromdeployment")
+                .about("Move the devices of a deployment to a group")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Id of the deployment")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .help("Name of the group")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("status")
+                        .help("Only move the devices whose deployment ended with this status")
+                        .long("status")
+                        .takes_value(true)
+                        .possible_values(&[
+                            "success",
+                            "failure",
+                            "aborted",
+                            "noartifact",
+                            "already-installed",
+                            "decommissioned",
+                        ]),
+                )
+                .arg(
+                    Arg::with_name("record-history")
+                        .help("Append the change to the group_history tag of the devices")
+                        .long("record-history"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Generate a self-contained HTML report")
+                .arg(
+                    Arg::with_name("kind")
+                        .help("Kind of report")
+                        .possible_values(&["fleet"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .help("HTML file to write")
+                        .long("out")
+                        .default_value("report.html"),
+                )
+                .arg(
+                    Arg::with_name("stale-days")
+                        .help("Number of days without inventory update after which a device is stale")
+                        .long("stale-days")
+                        .default_value("7"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("listpending")
+                .about("List the devices waiting for their authentication to be accepted"),
+        )
+        .subcommand(
+            SubCommand::with_name("getauth")
+                .about("Show the auth sets of a device")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("compact")
+                        .help("Print one line per auth set instead of JSON")
+                        .long("compact"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("preauth")
+                .about("Preauthorize a device so that it's accepted when it first connects")
+                .arg(
+                    Arg::with_name("identity")
+                        .help("JSON file with the identity data of the device")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("pubkey")
+                        .help("PEM file with the public key of the device")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("accept")
+                .visible_alias("acceptdevice")
+                .about("Accept the pending authentication of a device")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required_unless("device-id"),
+                )
+                .arg(
+                    Arg::with_name("device-id")
+                        .help("Mender id of the device, instead of the device argument")
+                        .long("device-id")
+                        .takes_value(true)
+                        .conflicts_with("device"),
+                )
+                .arg(
+                    Arg::with_name("authset")
+                        .help("Id of the auth set to accept, needed when the device has several pending ones")
+                        .long("authset")
+                        .visible_alias("auth-set-id")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reject")
+                .visible_alias("rejectdevice")
+                .about("Reject the accepted or pending authentication of a device")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required_unless("device-id"),
+                )
+                .arg(
+                    Arg::with_name("device-id")
+                        .help("Mender id of the device, instead of the device argument")
+                        .long("device-id")
+                        .takes_value(true)
+                        .conflicts_with("device"),
+                )
+                .arg(
+                    Arg::with_name("authset")
+                        .help("Id of the auth set to reject, needed when the device has several accepted or pending ones")
+                        .long("authset")
+                        .visible_alias("auth-set-id")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("decommission")
+                .visible_alias("decommissiondevice")
+                .about("Remove a device from the server, its authentication and its inventory")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("deleteinventory")
+                .about("Remove the inventory of a device, keeping its authentication")
+                .arg(
+                    Arg::with_name("device")
+                        .help(DEVICE_HELP)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("listgroupsbyartifact")
+                .about("List the groups where most devices have the given artifact installed")
+                .arg(
+                    Arg::with_name("artifact_name")
+                        .help("Name of the artifact")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("threshold")
+                        .help("Minimum percentage of devices of the group having the artifact")
+                        .long("threshold")
+                        .default_value("90"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("listdeployments")
+                .visible_alias("getdeployments")
+                .about("List the deployments, optionally filtered")
+                .arg(
+                    Arg::with_name("older-than")
+                        .help("Minimum age of the deployments, from their end or creation, e.g. 90d")
+                        .long("older-than")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("status")
+                        .help("Comma separated statuses of the deployments")
+                        .long("status")
+                        .takes_value(true)
+                        .use_delimiter(true),
+                )
+                .arg(
+                    Arg::with_name("max-devices")
+                        .help("Maximum number of devices of the deployments")
+                        .long("max-devices")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("device")
+                        .help("Only list the deployments of this device, given as for the device arguments")
+                        .long("device")
+                        .alias("device-id")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cleanupdeployments")
+                .about("Delete the deployments matching a filter, or list them if the server can't delete")
+                .arg(
+                    Arg::with_name("older-than")
+                        .help("Minimum age of the deployments, from their end or creation, e.g. 90d")
+                        .long("older-than")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("status")
+                        .help("Comma separated statuses of the deployments")
+                        .long("status")
+                        .takes_value(true)
+                        .use_delimiter(true)
+                        .default_value("finished"),
+                )
+                .arg(
+                    Arg::with_name("max-devices")
+                        .help("Maximum number of devices of the deployments")
+                        .long("max-devices")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cleanupfinisheddeployments")
+                .about("Clean up finished deployments older than a number of days")
+                .arg(
+                    Arg::with_name("days")
+                        .help("Minimum age in days of the finished deployments to clean up")
+                        .required(true),
+                ),
+        )
+}
+
+#[derive(Serialize)]
+pub struct Config {
+    pub command: Command,
+    pub profile: String,
+    /// Directory of the profile settings and saved tokens, None if there is none.
+    #[serde(skip)]
+    pub config_dir: Option<PathBuf>,
+    /// Directory of the cached results, None if there is none.
+    #[serde(skip)]
+    pub cache_dir: Option<PathBuf>,
+    #[serde(skip)]
+    pub token: Option<String>,
+    pub server_url: String,
+    pub cert_file: Option<String>,
+    /// Skipped as it may hold credentials.
+    #[serde(skip)]
+    pub proxy_url: Option<String>,
+    #[serde(skip)]
+    pub timings: Option<Timings>,
+    pub execution: ExecutionPolicy,
+    pub retry: RetryPolicy,
+    pub serial_number_matching: SerialNumberMatching,
+    pub output_format: OutputFormat,
+    pub assume_yes: bool,
+    /// Name to type to confirm destructive actions, from the profile.
+    pub confirm_server: Option<String>,
+    pub no_interactive: bool,
+    pub dry_run: bool,
+    pub pagination: PaginationConfig,
+    pub record_group_history: bool,
+    pub strict_schema: bool,
+}
+
+/// Format of the results printed by commands.
+#[derive(PartialEq, Debug, Serialize)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Limits on the HTTP requests made by a command, from the global flags, else the
+/// environment, else the profile settings.
+#[derive(Debug, Serialize)]
+pub struct ExecutionPolicy {
+    pub max_concurrent_requests: usize,
+    pub request_timeout: Option<Duration>,
+    pub deadline: Option<Duration>,
+    #[serde(skip)]
+    start: Instant,
+}
+
+impl ExecutionPolicy {
+    fn new(
+        args: &ArgMatches,
+        env: &Environment,
+        settings: &profile::ProfileSettings,
+    ) -> Result<ExecutionPolicy, &'static str> {
+        let setting = |name: &str, variable: &str, profile_value: Option<String>| {
+            global_value(args, name)
+                .map(|value| value.to_string())
+                .or_else(|| env.var(variable))
+                .or(profile_value)
+        };
+        let max_concurrent_requests = match setting(
+            "concurrent-requests",
+            "MENDER_CONCURRENT_REQUESTS",
+            settings.concurrent_requests.map(|nb| nb.to_string()),
+        ) {
+            Some(nb) => match nb.parse() {
+                Ok(nb) if nb > 0 => nb,
+                _ => return Err("concurrent requests must be a strictly positive integer"),
+            },
+            None => 1,
+        };
+        Ok(ExecutionPolicy {
+            max_concurrent_requests,
+            request_timeout: setting(
+                "request-timeout",
+                "MENDER_REQUEST_TIMEOUT",
+                settings.request_timeout.clone(),
+            )
+            .map(|timeout| parse_duration(&timeout))
+            .transpose()?,
+            deadline: setting("deadline", "MENDER_DEADLINE", settings.deadline.clone())
+                .map(|deadline| parse_duration(&deadline))
+                .transpose()?,
+            start: Instant::now(),
+        })
+    }
+
+    /// Time left before the deadline, None if there is no deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_sub(self.start.elapsed()))
+    }
+
+    /// Timeout of the next request, the request timeout capped by the time left
+    /// before the deadline.
+    pub fn timeout(&self) -> Option<Duration> {
+        match (self.request_timeout, self.remaining()) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        }
+    }
+}
+
+/// Retries of the HTTP requests failing on a transient error, from the global flags or
+/// the environment.
+#[derive(Debug, Serialize)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub retry_delay: Duration,
+}
+
+impl RetryPolicy {
+    fn new(args: &ArgMatches, env: &Environment) -> Result<RetryPolicy, &'static str> {
+        let setting = |name: &str, variable: &str| {
+            global_value(args, name)
+                .map(|value| value.to_string())
+                .or_else(|| env.var(variable))
+        };
+        Ok(RetryPolicy {
+            retries: match setting("retries", "MENDER_RETRIES") {
+                Some(nb) => nb
+                    .parse()
+                    .map_err(|_| "retries must be a positive integer")?,
+                None => 3,
+            },
+            retry_delay: match setting("retry-delay-ms", "MENDER_RETRY_DELAY_MS") {
+                Some(delay) => Duration::from_millis(
+                    delay
+                        .parse()
+                        .map_err(|_| "retry delay must be a number of milliseconds")?,
+                ),
+                None => Duration::from_millis(500),
+            },
+        })
+    }
+
+    /// Delay before the given retry, starting at 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.retry_delay.saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+/// Normalization of the SerialNumbers compared when looking up a device.
+#[derive(Debug, Default, Serialize)]
+pub struct SerialNumberMatching {
+    pub ignore_case: bool,
+    pub trim: bool,
+}
+
+impl SerialNumberMatching {
+    /// True when SerialNumbers are not compared as is.
+    pub fn normalizes(&self) -> bool {
+        self.ignore_case || self.trim
+    }
+
+    pub fn normalize(&self, serial_number: &str) -> String {
+        let serial_number = if self.trim {
+            serial_number.trim()
+        } else {
+            serial_number
+        };
+        if self.ignore_case {
+            serial_number.to_lowercase()
+        } else {
+            serial_number.to_string()
+        }
+    }
+
+    pub fn matches(&self, expected: &str, serial_number: &str) -> bool {
+        self.normalize(expected) == self.normalize(serial_number)
+    }
+}
+
+/// Settings of the listings done page by page.
+#[derive(Debug, Serialize)]
+pub struct PaginationConfig {
+    pub per_page: usize,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> PaginationConfig {
+        PaginationConfig { per_page: 500 }
+    }
+}
+
+impl PaginationConfig {
+    /// Page size of MENDER_PAGE_SIZE, else of the pagination table of the configuration
+    /// file.
+    fn new(
+        env: &Environment,
+        config_file: &config_file::ConfigFile,
+    ) -> Result<PaginationConfig, &'static str> {
+        match env.var("MENDER_PAGE_SIZE") {
+            Some(per_page) => match per_page.parse() {
+                Ok(per_page) if per_page > 0 => Ok(PaginationConfig { per_page }),
+                _ => Err("MENDER_PAGE_SIZE must be a strictly positive integer"),
+            },
+            None => match config_file.pagination.per_page {
+                Some(0) => Err("per_page of the configuration file must be strictly positive"),
+                Some(per_page) => Ok(PaginationConfig { per_page }),
+                None => Ok(PaginationConfig::default()),
+            },
+        }
+    }
+}
+
+/// Token of TOKEN, else of the configuration file, else the one saved for the profile.
+/// An empty or blank token is considered as not provided and the next one is used.
+fn select_token(
+    env_token: Option<String>,
+    file_token: Option<String>,
+    saved_token: impl FnOnce() -> Result<Option<String>, &'static str>,
+) -> Result<Option<String>, &'static str> {
+    let token = match env_token
+        .filter(|token| !token.trim().is_empty())
+        .or(file_token.filter(|token| !token.trim().is_empty()))
+    {
+        Some(token) => Some(token),
+        None => saved_token()?,
+    };
+    if matches!(&token, Some(token) if token.contains(&['\n', '\r'][..])) {
+        return Err("TOKEN must not contain a newline");
+    }
+    Ok(token)
+}
+
+impl Config {
+    pub fn new(command: Command, args: &ArgMatches) -> Result<Config, &'static str> {
+        Config::with_config_dir(command, args, profile::config_dir())
+    }
+
+    /// Same as new with the profiles of the given configuration directory.
+    pub fn with_config_dir(
+        mut command: Command,
+        args: &ArgMatches,
+        config_dir: Option<PathBuf>,
+    ) -> Result<Config, &'static str> {
+        let env = Environment::new(global_value(args, "env-file"))?;
+        let config_file = config_file::load(global_value(args, "config"))?;
+        let pagination = PaginationConfig::new(&env, &config_file)?;
+        if let Command::Deploy { thresholds, .. } | Command::Stats { thresholds, .. } = &mut command
+        {
+            thresholds.or_defaults(&config_file);
+        }
+        let profile = global_value(args, "profile")
+            .map(|profile| profile.to_string())
+            .or_else(|| env.var("MENDER_PROFILE"))
+            .unwrap_or_else(|| profile::DEFAULT_PROFILE.to_string());
+        profile::check_name(&profile)?;
+        let settings = profile::load_settings(config_dir.as_deref(), &profile)?;
+        let execution = ExecutionPolicy::new(args, &env, &settings)?;
+        let server_url = if let Some(url) = global_value(args, "server-url")
+            .map(|url| url.to_string())
+            .or_else(|| env.var("SERVER_URL"))
+            .or(settings.server_url)
+            .or(config_file.server_url)
+        {
+            url
+        } else {
+            return Err("SERVER_URL env variable, --server-url or server_url in the profile or configuration file must be defined");
+        };
+        let token = select_token(env.var("TOKEN"), config_file.token, || {
+            profile::load_token(config_dir.as_deref(), &profile)
+                .map_err(|_| "token saved for the profile can't be read")
+        })?;
+        let output_format = match global_value(args, "output").or(settings.output.as_deref()) {
+            Some("json") => OutputFormat::Json,
+            Some("text") | None => OutputFormat::Text,
+            Some(_) => return Err("output of the profile must be text or json"),
+        };
+        let cert_file = env.var("CERT_FILE").or(config_file.cert_file);
+        let proxy_url = global_value(args, "proxy")
+            .map(|url| url.to_string())
+            .or_else(|| env.var("HTTPS_PROXY"))
+            .or_else(|| env.var("HTTP_PROXY"))
+            .filter(|url| !url.trim().is_empty());
+        match &command {
+            Command::Login { .. }
+            | Command::Logout
+            | Command::CheckHealth { .. }
+            | Command::GetPaginationDefaults
+            | Command::ShowConfig => (),
+            _ if token.is_none() => {
+                return Err("TOKEN must be provided for this subcommand, see help")
+            }
+            _ => (),
+        }
+        Ok(Config {
+            command,
+            profile,
+            config_dir,
+            cache_dir: cache::cache_dir(),
+            token,
+            server_url,
+            cert_file,
+            proxy_url,
+            timings: if is_present(args, "timings") {
+                Some(Timings::new())
+            } else {
+                None
+            },
+            execution,
+            retry: RetryPolicy::new(args, &env)?,
+            serial_number_matching: SerialNumberMatching {
+                ignore_case: is_present(args, "ignore-case"),
+                trim: is_present(args, "trim"),
+            },
+            output_format,
+            assume_yes: is_present(args, "yes") || settings.assume_yes == Some(true),
+            confirm_server: settings.confirm_server,
+            no_interactive: is_present(args, "no-interactive"),
+            dry_run: is_present(args, "dry-run"),
+            strict_schema: is_present(args, "strict-schema"),
+            pagination,
+            record_group_history: is_present(args, "record-history")
+                || env
+                    .var("MENDER_RECORD_GROUP_HISTORY")
+                    .is_some_and(|record| record == "1"),
+        })
+    }
+
+    /// Configuration as JSON, only the first 8 characters of the token are kept.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        let mut json = serde_json::to_value(self)?;
+        json["token"] = serde_json::json!(self
+            .token
+            .as_ref()
+            .map(|token| token.chars().take(8).collect::<String>()));
+        json["masked"] = serde_json::json!(true);
+        serde_json::to_string_pretty(&json)
+    }
+}
+
+/// Limits on the failed devices of a finished deployment, checked by stats and deploy --wait.
+#[derive(PartialEq, Debug, Default, Serialize)]
+pub struct FailureThresholds {
+    pub max_failure_rate: Option<f64>,
+    pub max_failures: Option<u64>,
+}
+
+impl FailureThresholds {
+    fn new(args: &ArgMatches) -> Result<FailureThresholds, &'static str> {
+        Ok(FailureThresholds {
+            max_failure_rate: args
+                .value_of("max-failure-rate")
+                .map(|rate| rate.parse())
+                .transpose()
+                .map_err(|_| "max failure rate must be a number")?,
+            max_failures: args
+                .value_of("max-failures")
+                .map(|nb| nb.parse())
+                .transpose()
+                .map_err(|_| "max failures must be a positive integer")?,
+        })
+    }
+
+    /// Use the thresholds of the configuration file for the ones not given on the
+    /// command line.
+    fn or_defaults(&mut self, config_file: &config_file::ConfigFile) {
+        self.max_failure_rate = self.max_failure_rate.or(config_file.max_failure_rate);
+        self.max_failures = self.max_failures.or(config_file.max_failures);
+    }
+}
+
+/// Selection of deployments shared by listdeployments and cleanupdeployments.
+#[derive(PartialEq, Debug, Default, Serialize)]
+pub struct DeploymentFilter {
+    /// Minimum time since the deployment finished, or was created if it didn't.
+    pub older_than: Option<Duration>,
+    /// Accepted statuses, any status if empty.
+    pub statuses: Vec<String>,
+    pub max_devices: Option<usize>,
+    /// Device the deployments must target, any device if None.
+    pub device: Option<DeviceRef>,
+}
+
+impl DeploymentFilter {
+    fn new(args: &ArgMatches) -> Result<DeploymentFilter, &'static str> {
+        Ok(DeploymentFilter {
+            older_than: args
+                .value_of("older-than")
+                .map(parse_duration)
+                .transpose()?,
+            statuses: args
+                .values_of("status")
+                .map(|statuses| statuses.map(|status| status.to_string()).collect())
+                .unwrap_or_default(),
+            max_devices: args
+                .value_of("max-devices")
+                .map(|nb| nb.parse())
+                .transpose()
+                .map_err(|_| "max devices must be a positive integer")?,
+            device: args.value_of("device").map(DeviceRef::new).transpose()?,
+        })
+    }
+
+    /// Filter of cleanupfinisheddeployments: finished deployments older than a number of
+    /// days.
+    pub fn finished_older_than_days(days: u64) -> DeploymentFilter {
+        DeploymentFilter {
+            older_than: Some(Duration::from_secs(days * 86400)),
+            statuses: vec![String::from("finished")],
+            ..DeploymentFilter::default()
+        }
+    }
+}
+
+/// Device given as the device argument, or as a Mender id with --device-id.
+fn device_or_device_id(args: &ArgMatches) -> Result<DeviceRef, &'static str> {
+    match args.value_of("device-id") {
+        Some(id) => DeviceRef::new(&format!("id:{}", id)),
+        None => DeviceRef::new(args.value_of("device").unwrap()),
+    }
+}
+
+/// Check a global flag, which may have been given before or after the subcommand.
+fn is_present(args: &ArgMatches, name: &str) -> bool {
+    args.is_present(name)
+        || args
+            .subcommand()
+            .1
+            .is_some_and(|sub_args| sub_args.is_present(name))
+}
+
+/// Get the value of a global argument, which may have been given before or after the subcommand.
+fn global_value<'a>(args: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    args.subcommand()
+        .1
+        .and_then(|sub_args| sub_args.value_of(name))
+        .or_else(|| args.value_of(name))
+}
+
+/// Check that a group name only has the characters allowed by the Mender inventory.
+fn check_group_name(group: &str) -> Result<String, &'static str> {
+    if !group.is_empty()
+        && group
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(group.to_string())
+    } else {
+        Err("group name must only contain letters, digits, '-' and '_'")
+    }
+}
+
+/// Columns of the device listings, their values are extracted from the inventory in
+/// mender::device_column.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize)]
+pub enum DeviceColumn {
+    Id,
+    SerialNumber,
+    Artifact,
+    Group,
+    DeviceType,
+    Status,
+    LastSeen,
+    ClientVersion,
+}
+
+impl DeviceColumn {
+    /// Names of the columns for --columns, in the order of the enum.
+    pub const NAMES: [&'static str; 8] = [
+        "id",
+        "serial",
+        "artifact",
+        "group",
+        "device_type",
+        "status",
+        "last_seen",
+        "client_version",
+    ];
+    const ALL: [DeviceColumn; 8] = [
+        DeviceColumn::Id,
+        DeviceColumn::SerialNumber,
+        DeviceColumn::Artifact,
+        DeviceColumn::Group,
+        DeviceColumn::DeviceType,
+        DeviceColumn::Status,
+        DeviceColumn::LastSeen,
+        DeviceColumn::ClientVersion,
+    ];
+    const NARROW: [DeviceColumn; 3] = [
+        DeviceColumn::Id,
+        DeviceColumn::SerialNumber,
+        DeviceColumn::Artifact,
+    ];
+    const WIDE: [DeviceColumn; 7] = [
+        DeviceColumn::Id,
+        DeviceColumn::SerialNumber,
+        DeviceColumn::Artifact,
+        DeviceColumn::Group,
+        DeviceColumn::DeviceType,
+        DeviceColumn::Status,
+        DeviceColumn::LastSeen,
+    ];
+
+    /// Columns selected by --columns or --format, the default ones otherwise. Column
+    /// names have already been validated by clap.
+    fn selected(args: &ArgMatches, default: &[DeviceColumn]) -> Vec<DeviceColumn> {
+        if let Some(names) = args.values_of("columns") {
+            names
+                .filter_map(|name| {
+                    DeviceColumn::NAMES
+                        .iter()
+                        .position(|known| *known == name)
+                        .map(|idx| DeviceColumn::ALL[idx])
+                })
+                .collect()
+        } else {
+            match args.value_of("format") {
+                Some("narrow") => DeviceColumn::NARROW.to_vec(),
+                Some("wide") => DeviceColumn::WIDE.to_vec(),
+                _ => default.to_vec(),
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Serialize)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Match,
+}
+
+/// Condition on an attribute value, regex validity is checked when parsing.
+#[derive(PartialEq, Debug, Serialize)]
+pub struct AttributeCondition {
+    pub name: String,
+    pub operator: Operator,
+    pub value: String,
+}
+
+impl AttributeCondition {
+    fn new(condition: &str) -> Result<AttributeCondition, &'static str> {
+        let (name, operator, value) = if let Some((name, value)) = condition.split_once("!=") {
+            (name, Operator::NotEqual, value)
+        } else if let Some((name, value)) = condition.split_once('~') {
+            regex::Regex::new(value).map_err(|_| "invalid regex in attribute condition")?;
+            (name, Operator::Match, value)
+        } else if let Some((name, value)) = condition.split_once('=') {
+            (name, Operator::Equal, value)
+        } else {
+            return Err("attribute condition must be name=value, name!=value or name~regex");
+        };
+        Ok(AttributeCondition {
+            name: name.to_string(),
+            operator,
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Device given either by its Mender id or by its SerialNumber attribute.
+#[derive(PartialEq, Debug, Serialize)]
+pub enum DeviceRef {
+    Id(String),
+    SerialNumber(String),
+}
+
+impl DeviceRef {
+    /// Values formatted like a Mender id, 24 hexadecimal digits or a UUID depending on the
+    /// server version, are ids, anything else is a SerialNumber. The `id:` and `sn:`
+    /// prefixes force the interpretation.
+    pub fn new(device: &str) -> Result<DeviceRef, &'static str> {
+        let device_ref = if let Some(id) = device.strip_prefix("id:") {
+            DeviceRef::Id(id.to_string())
+        } else if let Some(serial_number) = device.strip_prefix("sn:") {
+            DeviceRef::SerialNumber(serial_number.to_string())
+        } else if is_mender_id(device) {
+            DeviceRef::Id(device.to_string())
+        } else {
+            DeviceRef::SerialNumber(device.to_string())
+        };
+        match &device_ref {
+            DeviceRef::Id(value) | DeviceRef::SerialNumber(value) if value.is_empty() => {
+                Err("device must not be empty")
+            }
+            _ => Ok(device_ref),
+        }
+    }
+}
+
+/// Number of devices expected in a deployment, a single value or an inclusive range.
+#[derive(PartialEq, Debug, Serialize)]
+pub struct ExpectedCount {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl ExpectedCount {
+    /// Parse `N` or `MIN..MAX`.
+    pub fn new(expected: &str) -> Result<ExpectedCount, &'static str> {
+        const FORMAT_ERROR: &str = "expected count must be a number or a range MIN..MAX";
+        let parse = |nb: &str| nb.trim().parse::<usize>().map_err(|_| FORMAT_ERROR);
+        let (min, max) = match expected.split_once("..") {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(expected)?, parse(expected)?),
+        };
+        if min > max {
+            return Err("expected count range must be MIN..MAX with MIN not above MAX");
+        }
+        Ok(ExpectedCount { min, max })
+    }
+
+    pub fn contains(&self, count: usize) -> bool {
+        (self.min..=self.max).contains(&count)
+    }
+}
+
+impl std::fmt::Display for ExpectedCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}..{}", self.min, self.max)
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceRef::Id(id) => write!(f, "{}", id),
+            DeviceRef::SerialNumber(serial_number) => write!(f, "SerialNumber {}", serial_number),
+        }
+    }
+}
+
+fn is_mender_id(value: &str) -> bool {
+    let is_hex = |part: &str| part.chars().all(|c| c.is_ascii_hexdigit());
+    let parts: Vec<&str> = value.split('-').collect();
+    match parts.as_slice() {
+        [object_id] => object_id.len() == 24 && is_hex(object_id),
+        [_, _, _, _, _] => parts
+            .iter()
+            .zip(&[8, 4, 4, 4, 12])
+            .all(|(part, len)| part.len() == *len && is_hex(part)),
+        _ => false,
+    }
+}
+
+#[derive(PartialEq, Debug, Serialize)]
+pub enum Command {
+    Login {
+        email: String,
+        save: bool,
+    },
+    Logout,
+    /// Exactly one of group and device is set, clap requires one of --group and --device
+    /// and rejects both.
+    Deploy {
+        group: Option<String>,
+        device: Option<Vec<String>>,
+        artifact: String,
+        name: Option<String>,
+        wait: bool,
+        wait_timeout: Duration,
+        wait_interval: Duration,
+        thresholds: FailureThresholds,
+        allow_downgrade: bool,
+        start_at: Option<chrono::DateTime<chrono::Utc>>,
+        summary_file: Option<String>,
+        artifact_checksum: Option<String>,
+        expected_count: Option<ExpectedCount>,
+    },
+    GetId {
+        value: String,
+        attribute: String,
+        first: bool,
+        index: bool,
+        rebuild_index: bool,
+    },
+    FindId {
+        attribute: String,
+        value: String,
+    },
+    GetInfo {
+        device: DeviceRef,
+        attributes: Vec<String>,
+    },
+    CountArtifacts {
+        group: Option<String>,
+        refresh: bool,
+    },
+    DeploymentStatus {
+        deployment_id: String,
+        poll_interval: Duration,
+        timeout: Duration,
+    },
+    Stats {
+        id: String,
+        thresholds: FailureThresholds,
+    },
+    Search {
+        filters: Vec<(String, String)>,
+        missing_attributes: Vec<String>,
+        group: Option<String>,
+        columns: Vec<DeviceColumn>,
+    },
+    SetAttributesFromFile {
+        device: DeviceRef,
+        file: String,
+    },
+    ListDevices {
+        group: Option<String>,
+        group_regex: Option<String>,
+        ungrouped: bool,
+        status: Option<String>,
+        columns: Vec<DeviceColumn>,
+    },
+    DeviceCount,
+    ListGroups,
+    CountGroups,
+    GroupDevices {
+        group: String,
+    },
+    Man {
+        out_dir: Option<PathBuf>,
+    },
+    Stale {
+        days: u64,
+    },
+    GetDevicesNotInAnyGroup,
+    GetArtifactProvides {
+        artifact_name: String,
+    },
+    ListArtifacts,
+    UploadArtifact {
+        path: PathBuf,
+        description: Option<String>,
+    },
+    ListGroupsForDevice {
+        device: DeviceRef,
+    },
+    GetGroup {
+        device: DeviceRef,
+    },
+    WaitAttr {
+        device: DeviceRef,
+        condition: AttributeCondition,
+        timeout: Duration,
+        interval: Duration,
+    },
+    GetDeploymentErrors {
+        deployment_id: String,
+    },
+    AbortDeployment {
+        deployment_id: String,
+    },
+    SelfTest {
+        device: String,
+        artifact: String,
+        allow_mutations: bool,
+    },
+    DeploymentDevices {
+        deployment_id: String,
+        stuck: Option<Duration>,
+    },
+    CheckHealth {
+        service: Option<String>,
+    },
+    GetDeploymentYaml {
+        deployment_id: String,
+        output: Option<String>,
+    },
+    ExportFilters {
+        out: Option<String>,
+    },
+    ImportFilters {
+        file: String,
+        overwrite: bool,
+    },
+    GetPaginationDefaults,
+    StatsAttr {
+        attribute: String,
+        group: Option<String>,
+        buckets: usize,
+    },
+    GetLatestArtifactPerDeviceType,
+    ShowConfig,
+    GetPendingDeploymentStatsAll,
+    SetGroup {
+        device: DeviceRef,
+        group: String,
+    },
+    AddToGroup {
+        device: DeviceRef,
+        group: String,
+    },
+    RemoveFromGroup {
+        device: DeviceRef,
+        group: Option<String>,
+    },
+    CreateGroup {
+        name: String,
+        devices: Vec<String>,
+    },
+    DeleteGroup {
+        name: String,
+    },
+    RenameGroup {
+        old: String,
+        new: String,
+        progress_every: usize,
+    },
+    AssignGroup {
+        file: PathBuf,
+        group: String,
+    },
+    SetGroupFromDeployment {
+        deployment_id: String,
+        group: String,
+        status: Option<String>,
+    },
+    FleetReport {
+        out: String,
+        stale_days: u64,
+    },
+    ListPending,
+    GetAuth {
+        device: DeviceRef,
+        compact: bool,
+    },
+    Preauth {
+        identity_file: String,
+        pubkey_file: String,
+    },
+    Accept {
+        device: DeviceRef,
+        authset: Option<String>,
+    },
+    Reject {
+        device: DeviceRef,
+        authset: Option<String>,
+    },
+    Decommission {
+        device: DeviceRef,
+    },
+    DeleteInventory {
+        device: DeviceRef,
+    },
+    ListGroupsByArtifact {
+        artifact_name: String,
+        threshold_percent: f64,
+    },
+    ListDeployments {
+        filter: DeploymentFilter,
+    },
+    CleanupDeployments {
+        filter: DeploymentFilter,
+    },
+    /// Shortcut of cleanupdeployments for the finished deployments older than a number
+    /// of days, kept for compatibility.
+    CleanupFinishedDeployments {
+        older_than_days: u64,
+    },
+}
+
+impl Command {
+    pub fn new(args: &ArgMatches) -> Result<Command, &'static str> {
+        match args.subcommand() {
+            ("countartifacts", Some(sub_args)) => Ok(Command::CountArtifacts {
+                group: sub_args.value_of("group").map(|s| s.to_string()),
+                refresh: sub_args.is_present("refresh"),
+            }),
+            ("logout", _) => Ok(Command::Logout),
+            ("login", Some(sub_args)) => Ok(Command::Login {
+                email: sub_args.value_of("email").unwrap().to_string(),
+                save: sub_args.is_present("save"),
+            }),
+            ("deploy", Some(sub_args)) => Ok(Command::Deploy {
+                group: sub_args.value_of("group").map(|s| s.to_string()),
+                device: sub_args
+                    .values_of("device")
+                    .map(|devices| devices.map(|s| s.to_string()).collect()),
+                artifact: sub_args.value_of("artifact").unwrap().to_string(),
+                name: sub_args.value_of("name").map(|s| s.to_string()),
+                wait: sub_args.is_present("wait"),
+                wait_timeout: parse_duration(sub_args.value_of("wait-timeout").unwrap())?,
+                wait_interval: parse_duration(sub_args.value_of("wait-interval").unwrap())?,
+                thresholds: FailureThresholds::new(sub_args)?,
+                allow_downgrade: sub_args.is_present("allow-downgrade"),
+                start_at: sub_args
+                    .value_of("start-at")
+                    .map(parse_start_time)
+                    .transpose()?,
+                summary_file: sub_args.value_of("summary-file").map(|s| s.to_string()),
+                artifact_checksum: sub_args
+                    .value_of("artifact-checksum")
+                    .map(|checksum| {
+                        if checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                            Ok(checksum.to_ascii_lowercase())
+                        } else {
+                            Err("artifact checksum must be a SHA-256 of 64 hexadecimal digits")
+                        }
+                    })
+                    .transpose()?,
+                expected_count: sub_args
+                    .value_of("expect-count")
+                    .map(ExpectedCount::new)
+                    .transpose()?,
+            }),
+            ("deploymentstatus", Some(sub_args)) => Ok(Command::DeploymentStatus {
+                deployment_id: sub_args.value_of("deployment-id").unwrap().to_string(),
+                poll_interval: Duration::from_secs(
+                    sub_args
+                        .value_of("poll-interval-secs")
+                        .unwrap()
+                        .parse()
+                        .map_err(|_| "poll interval must be a positive number of seconds")?,
+                ),
+                timeout: parse_duration(sub_args.value_of("timeout").unwrap())?,
+            }),
+            ("stats", Some(sub_args)) => Ok(Command::Stats {
+                id: sub_args.value_of("id").unwrap().to_string(),
+                thresholds: FailureThresholds::new(sub_args)?,
+            }),
+            ("getid", Some(sub_args)) => Ok(Command::GetId {
+                value: sub_args.value_of("value").unwrap().to_string(),
+                attribute: sub_args.value_of("attribute").unwrap().to_string(),
+                first: sub_args.is_present("first"),
+                index: sub_args.is_present("index") || sub_args.is_present("rebuild-index"),
+                rebuild_index: sub_args.is_present("rebuild-index"),
+            }),
+            ("findid", Some(sub_args)) => Ok(Command::FindId {
+                attribute: sub_args.value_of("attribute").unwrap().to_string(),
+                value: sub_args.value_of("value").unwrap().to_string(),
+            }),
+            ("getinfo", Some(sub_args)) => Ok(Command::GetInfo {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+                attributes: sub_args
+                    .values_of("attr")
+                    .map(|names| names.map(|name| name.to_string()).collect())
+                    .unwrap_or_default(),
+            }),
+            ("search", Some(sub_args)) => Ok(Command::Search {
+                filters: sub_args
+                    .values_of("attribute")
+                    .into_iter()
+                    .flatten()
+                    .map(|filter| {
+                        filter
+                            .split_once('=')
+                            .map(|(name, value)| (name.to_string(), value.to_string()))
+                            .ok_or("attribute filters must be formatted as name=value")
+                    })
+                    .collect::<Result<_, _>>()?,
+                missing_attributes: sub_args
+                    .values_of("missing-attribute")
+                    .into_iter()
+                    .flatten()
+                    .map(|s| s.to_string())
+                    .collect(),
+                group: sub_args.value_of("group").map(|s| s.to_string()),
+                columns: DeviceColumn::selected(
+                    sub_args,
+                    &[
+                        DeviceColumn::Id,
+                        DeviceColumn::SerialNumber,
+                        DeviceColumn::ClientVersion,
+                    ],
+                ),
+            }),
+            ("setdeviceattributesfromfile", Some(sub_args)) => Ok(Command::SetAttributesFromFile {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+                file: sub_args.value_of("file").unwrap().to_string(),
+            }),
+            ("listdevices", Some(sub_args)) => Ok(Command::ListDevices {
+                group: sub_args.value_of("group").map(|s| s.to_string()),
+                group_regex: sub_args
+                    .value_of("group-regex")
+                    .map(|group_regex| {
+                        regex::Regex::new(group_regex)
+                            .map(|_| group_regex.to_string())
+                            .map_err(|_| "invalid group regex")
+                    })
+                    .transpose()?,
+                ungrouped: sub_args.is_present("ungrouped"),
+                status: sub_args.value_of("status").map(|s| s.to_string()),
+                columns: DeviceColumn::selected(
+                    sub_args,
+                    &[
+                        DeviceColumn::Id,
+                        DeviceColumn::SerialNumber,
+                        DeviceColumn::Group,
+                    ],
+                ),
+            }),
+            ("devicecount", _) => Ok(Command::DeviceCount),
+            ("listgroups", _) => Ok(Command::ListGroups),
+            ("countgroups", _) => Ok(Command::CountGroups),
+            ("groupdevices", Some(sub_args)) => Ok(Command::GroupDevices {
+                group: sub_args.value_of("group").unwrap().to_string(),
+            }),
+            ("man", Some(sub_args)) => Ok(Command::Man {
+                out_dir: sub_args.value_of("out-dir").map(PathBuf::from),
+            }),
+            ("stale", Some(sub_args)) => Ok(Command::Stale {
+                days: sub_args
+                    .value_of("days")
+                    .unwrap()
+                    .parse()
+                    .map_err(|_| "days must be a positive integer")?,
+            }),
+            ("getdevicesnotinanygroup", _) => Ok(Command::GetDevicesNotInAnyGroup),
+            ("getartifactprovides", Some(sub_args)) => Ok(Command::GetArtifactProvides {
+                artifact_name: sub_args.value_of("artifact").unwrap().to_string(),
+            }),
+            ("listartifacts", _) => Ok(Command::ListArtifacts),
+            ("uploadartifact", Some(sub_args)) => Ok(Command::UploadArtifact {
+                path: PathBuf::from(sub_args.value_of("file").unwrap()),
+                description: sub_args.value_of("description").map(|s| s.to_string()),
+            }),
+            ("getgroup", Some(sub_args)) => Ok(Command::GetGroup {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+            }),
+            ("listgroupsfordevice", Some(sub_args)) => Ok(Command::ListGroupsForDevice {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+            }),
+            ("waitattr", Some(sub_args)) => Ok(Command::WaitAttr {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+                condition: AttributeCondition::new(sub_args.value_of("condition").unwrap())?,
+                timeout: parse_duration(sub_args.value_of("timeout").unwrap())?,
+                interval: parse_duration(sub_args.value_of("interval").unwrap())?,
+            }),
+            ("getdeploymenterrors", Some(sub_args)) => Ok(Command::GetDeploymentErrors {
+                deployment_id: sub_args.value_of("id").unwrap().to_string(),
+            }),
+            ("abortdeployment", Some(sub_args)) => Ok(Command::AbortDeployment {
+                deployment_id: sub_args.value_of("id").unwrap().to_string(),
+            }),
+            ("selftest", Some(sub_args)) => Ok(Command::SelfTest {
+                device: sub_args.value_of("device").unwrap().to_string(),
+                artifact: sub_args.value_of("artifact").unwrap().to_string(),
+                allow_mutations: sub_args.is_present("allow-mutations"),
+            }),
+            ("deploymentdevices", Some(sub_args)) => Ok(Command::DeploymentDevices {
+                deployment_id: sub_args.value_of("id").unwrap().to_string(),
+                stuck: sub_args.value_of("stuck").map(parse_duration).transpose()?,
+            }),
+            ("checkhealth", Some(sub_args)) => Ok(Command::CheckHealth {
+                service: sub_args.value_of("service").map(|s| s.to_string()),
+            }),
+            ("getdeploymentyaml", Some(sub_args)) => Ok(Command::GetDeploymentYaml {
+                deployment_id: sub_args.value_of("id").unwrap().to_string(),
+                output: sub_args.value_of("out").map(|s| s.to_string()),
+            }),
+            ("exportfilters", Some(sub_args)) => Ok(Command::ExportFilters {
+                out: sub_args.value_of("out").map(|s| s.to_string()),
+            }),
+            ("importfilters", Some(sub_args)) => Ok(Command::ImportFilters {
+                file: sub_args.value_of("file").unwrap().to_string(),
+                overwrite: sub_args.is_present("overwrite"),
+            }),
+            ("getpaginationdefaults", _) => Ok(Command::GetPaginationDefaults),
+            ("statsattr", Some(sub_args)) => Ok(Command::StatsAttr {
+                attribute: sub_args.value_of("attribute").unwrap().to_string(),
+                group: sub_args.value_of("group").map(|s| s.to_string()),
+                buckets: match sub_args.value_of("buckets").unwrap().parse() {
+                    Ok(buckets) if buckets > 0 => buckets,
+                    _ => return Err("buckets must be a strictly positive integer"),
+                },
+            }),
+            ("getlatestartifactperdevicetype", _) => Ok(Command::GetLatestArtifactPerDeviceType),
+            ("showconfig", _) => Ok(Command::ShowConfig),
+            ("getpendingdeploymentstatsall", _) => Ok(Command::GetPendingDeploymentStatsAll),
+            ("setgroup", Some(sub_args)) => Ok(Command::SetGroup {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+                group: sub_args.value_of("group").unwrap().to_string(),
+            }),
+            ("addtogroup", Some(sub_args)) => Ok(Command::AddToGroup {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+                group: check_group_name(sub_args.value_of("group").unwrap())?,
+            }),
+            ("assigngroup", Some(sub_args)) => Ok(Command::AssignGroup {
+                file: PathBuf::from(sub_args.value_of("file").unwrap()),
+                group: check_group_name(sub_args.value_of("group").unwrap())?,
+            }),
+            ("creategroup", Some(sub_args)) => Ok(Command::CreateGroup {
+                name: check_group_name(sub_args.value_of("name").unwrap())?,
+                devices: sub_args
+                    .values_of("device-ids")
+                    .unwrap()
+                    .map(|s| s.to_string())
+                    .collect(),
+            }),
+            ("deletegroup", Some(sub_args)) => Ok(Command::DeleteGroup {
+                name: sub_args.value_of("name").unwrap().to_string(),
+            }),
+            ("renamegroup", Some(sub_args)) => Ok(Command::RenameGroup {
+                old: sub_args.value_of("old").unwrap().to_string(),
+                new: check_group_name(sub_args.value_of("new").unwrap())?,
+                progress_every: sub_args
+                    .value_of("progress-every")
+                    .unwrap()
+                    .parse()
+                    .ok()
+                    .filter(|&progress_every| progress_every > 0)
+                    .ok_or("progress-every must be a positive integer")?,
+            }),
+            ("removefromgroup", Some(sub_args)) => Ok(Command::RemoveFromGroup {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+                group: sub_args
+                    .value_of("group")
+                    .map(check_group_name)
+                    .transpose()?,
+            }),
+            ("setgroupfromdeployment", Some(sub_args)) => Ok(Command::SetGroupFromDeployment {
+                deployment_id: sub_args.value_of("id").unwrap().to_string(),
+                group: sub_args.value_of("group").unwrap().to_string(),
+                status: sub_args.value_of("status").map(|s| s.to_string()),
+            }),
+            ("report", Some(sub_args)) => Ok(Command::FleetReport {
+                out: sub_args.value_of("out").unwrap().to_string(),
+                stale_days: sub_args
+                    .value_of("stale-days")
+                    .unwrap()
+                    .parse()
+                    .map_err(|_| "stale days must be a positive integer")?,
+            }),
+            ("listpending", _) => Ok(Command::ListPending),
+            ("getauth", Some(sub_args)) => Ok(Command::GetAuth {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+                compact: sub_args.is_present("compact"),
+            }),
+            ("preauth", Some(sub_args)) => Ok(Command::Preauth {
+                identity_file: sub_args.value_of("identity").unwrap().to_string(),
+                pubkey_file: sub_args.value_of("pubkey").unwrap().to_string(),
+            }),
+            ("accept", Some(sub_args)) => Ok(Command::Accept {
+                device: device_or_device_id(sub_args)?,
+                authset: sub_args.value_of("authset").map(|s| s.to_string()),
+            }),
+            ("reject", Some(sub_args)) => Ok(Command::Reject {
+                device: device_or_device_id(sub_args)?,
+                authset: sub_args.value_of("authset").map(|s| s.to_string()),
+            }),
+            ("decommission", Some(sub_args)) => Ok(Command::Decommission {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+            }),
+            ("deleteinventory", Some(sub_args)) => Ok(Command::DeleteInventory {
+                device: DeviceRef::new(sub_args.value_of("device").unwrap())?,
+            }),
+            ("listgroupsbyartifact", Some(sub_args)) => Ok(Command::ListGroupsByArtifact {
+                artifact_name: sub_args.value_of("artifact_name").unwrap().to_string(),
+                threshold_percent: match sub_args.value_of("threshold").unwrap().parse() {
+                    Ok(threshold) if (0.0..=100.0).contains(&threshold) => threshold,
+                    _ => return Err("threshold must be a percentage between 0 and 100"),
+                },
+            }),
+            ("listdeployments", Some(sub_args)) => Ok(Command::ListDeployments {
+                filter: DeploymentFilter::new(sub_args)?,
+            }),
+            ("cleanupdeployments", Some(sub_args)) => Ok(Command::CleanupDeployments {
+                filter: DeploymentFilter::new(sub_args)?,
+            }),
+            ("cleanupfinisheddeployments", Some(sub_args)) => {
+                let days: u64 = sub_args
+                    .value_of("days")
+                    .unwrap()
+                    .parse()
+                    .map_err(|_| "days must be a positive integer")?;
+                Ok(Command::CleanupFinishedDeployments {
+                    older_than_days: days,
+                })
+            }
+            _ => Err("unrecognized or no subcommand, see help for available subcommands"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server;
+
+    fn command(args: &[&str]) -> Result<Command, &'static str> {
+        let matches = build_cli()
+            .get_matches_from_safe(std::iter::once("mender-rust").chain(args.iter().copied()))
+            .unwrap();
+        Command::new(&matches)
+    }
+
+    fn config(args: &[&str]) -> Config {
+        mock_server::config("https://mender.test", args)
+    }
+
+    #[test]
+    fn concurrent_requests_before_or_after_subcommand() {
+        for args in &[
+            ["--concurrent-requests", "4", "showconfig"],
+            ["showconfig", "--concurrent-requests", "4"],
+        ] {
+            assert_eq!(config(args).execution.max_concurrent_requests, 4);
+        }
+    }
+
+    /// Error of the configuration of the command line args.
+    fn config_error(args: &[&str]) -> &'static str {
+        let matches = build_cli()
+            .get_matches_from_safe(
+                [
+                    "mender-rust",
+                    "--server-url",
+                    "https://mender.test",
+                    "--config",
+                    "/dev/null",
+                ]
+                .iter()
+                .chain(args.iter())
+                .copied(),
+            )
+            .unwrap();
+        match Config::with_config_dir(Command::ShowConfig, &matches, None) {
+            Ok(_) => panic!("{:?} is a valid configuration", args),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn execution_policy_from_flags_or_env_file() {
+        let execution =
+            config(&["--request-timeout", "5s", "showconfig", "--deadline", "1m"]).execution;
+        assert_eq!(execution.request_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(execution.deadline, Some(Duration::from_secs(60)));
+
+        let env_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            env_file.path(),
+            "MENDER_CONCURRENT_REQUESTS=3\nMENDER_REQUEST_TIMEOUT=10s\nMENDER_DEADLINE=2m\n",
+        )
+        .unwrap();
+        let env_file = env_file.path().to_str().unwrap();
+        let execution = config(&["--env-file", env_file, "showconfig"]).execution;
+        assert_eq!(execution.max_concurrent_requests, 3);
+        assert_eq!(execution.request_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(execution.deadline, Some(Duration::from_secs(120)));
+        let execution =
+            config(&["--env-file", env_file, "showconfig", "--deadline", "30s"]).execution;
+        assert_eq!(execution.deadline, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn request_timeout_is_capped_by_deadline() {
+        let execution = config(&["showconfig"]).execution;
+        assert_eq!(execution.timeout(), None);
+        assert_eq!(execution.remaining(), None);
+        let execution = config(&["showconfig", "--request-timeout", "10s"]).execution;
+        assert_eq!(execution.timeout(), Some(Duration::from_secs(10)));
+        let execution =
+            config(&["showconfig", "--request-timeout", "10m", "--deadline", "1m"]).execution;
+        let timeout = execution.timeout().unwrap();
+        assert!(timeout <= Duration::from_secs(60) && timeout > Duration::from_secs(50));
+    }
+
+    #[test]
+    fn invalid_execution_policy() {
+        assert_eq!(
+            config_error(&["showconfig", "--concurrent-requests", "0"]),
+            "concurrent requests must be a strictly positive integer"
+        );
+        assert!(!config_error(&["showconfig", "--deadline", "soon"]).is_empty());
+    }
+
+    #[test]
+    fn output_format_before_or_after_subcommand() {
+        assert_eq!(config(&["showconfig"]).output_format, OutputFormat::Text);
+        for args in &[
+            ["--output", "json", "showconfig"],
+            ["showconfig", "-o", "json"],
+        ] {
+            assert_eq!(config(args).output_format, OutputFormat::Json);
+        }
+    }
+
+    #[test]
+    fn timings_before_or_after_subcommand() {
+        assert!(config(&["--timings", "showconfig"]).timings.is_some());
+        assert!(config(&["showconfig", "--timings"]).timings.is_some());
+        assert!(config(&["showconfig"]).timings.is_none());
+    }
+
+    #[test]
+    fn thresholds_default_to_configuration_file() {
+        let config_file = config_file::ConfigFile {
+            max_failure_rate: Some(5.0),
+            max_failures: Some(10),
+            ..Default::default()
+        };
+        let mut thresholds = match command(&["stats", "id", "--max-failures", "2"]).unwrap() {
+            Command::Stats { thresholds, .. } => thresholds,
+            command => panic!("unexpected command {:?}", command),
+        };
+        thresholds.or_defaults(&config_file);
+        assert_eq!(
+            thresholds,
+            FailureThresholds {
+                max_failure_rate: Some(5.0),
+                max_failures: Some(2),
+            }
+        );
+    }
+
+    #[test]
+    fn cleanup_finished_deployments_shortcut() {
+        match command(&["cleanupfinisheddeployments", "30"]) {
+            Ok(Command::CleanupFinishedDeployments { older_than_days }) => {
+                assert_eq!(older_than_days, 30)
+            }
+            _ => panic!("cleanupfinisheddeployments not parsed"),
+        }
+        assert_eq!(
+            DeploymentFilter::finished_older_than_days(30),
+            DeploymentFilter {
+                older_than: Some(Duration::from_secs(30 * 86400)),
+                statuses: vec![String::from("finished")],
+                max_devices: None,
+                device: None,
+            }
+        );
+        assert!(command(&["cleanupfinisheddeployments", "soon"]).is_err());
+    }
+
+    #[test]
+    fn deploy_thresholds_require_wait() {
+        for threshold in &["--max-failure-rate", "--max-failures"] {
+            let err = build_cli()
+                .get_matches_from_safe([
+                    "mender-rust",
+                    "deploy",
+                    "--group",
+                    "g1",
+                    "release-2",
+                    threshold,
+                    "1",
+                ])
+                .unwrap_err();
+            assert_eq!(
+                err.kind,
+                clap::ErrorKind::MissingRequiredArgument,
+                "{}",
+                threshold
+            );
+        }
+        assert!(command(&[
+            "deploy",
+            "--group",
+            "g1",
+            "release-2",
+            "--wait",
+            "--max-failures",
+            "1"
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn deploy_to_group_or_devices() {
+        let targets = |args: &[&str]| match command(args).unwrap() {
+            Command::Deploy { group, device, .. } => (group, device),
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(
+            targets(&["deploy", "--group", "g1", "release-2"]),
+            (Some("g1".to_string()), None)
+        );
+        assert_eq!(
+            targets(&["deploy", "-d", "d1", "release-2"]),
+            (None, Some(vec!["d1".to_string()]))
+        );
+        assert_eq!(
+            targets(&["deploy", "--device", "d1,d2", "--device", "d3", "release-2"]),
+            (
+                None,
+                Some(vec!["d1".to_string(), "d2".to_string(), "d3".to_string()])
+            )
+        );
+        for args in &[
+            &["deploy", "release-2"][..],
+            &["deploy", "--group", "g1", "--device", "d1", "release-2"][..],
+        ] {
+            assert!(build_cli()
+                .get_matches_from_safe(std::iter::once("mender-rust").chain(args.iter().copied()))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn deploy_wait_timeout_and_interval() {
+        match command(&["deploy", "--group", "g", "a", "--wait-timeout", "5m"]).unwrap() {
+            Command::Deploy {
+                wait_timeout,
+                wait_interval,
+                ..
+            } => {
+                assert_eq!(wait_timeout, Duration::from_secs(300));
+                assert_eq!(wait_interval, Duration::from_secs(10));
+            }
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn blank_tokens_are_not_provided() {
+        for blank in &["", " ", "\t \n"] {
+            assert_eq!(
+                select_token(Some(blank.to_string()), None, || Ok(None)),
+                Ok(None)
+            );
+            assert_eq!(
+                select_token(Some(blank.to_string()), Some(blank.to_string()), || Ok(
+                    Some("saved".to_string())
+                )),
+                Ok(Some("saved".to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn tokens_with_newline_are_rejected() {
+        for token in &["abc\ndef", "abc\r\n", "\nabc"] {
+            assert_eq!(
+                select_token(Some(token.to_string()), None, || Ok(None)),
+                Err("TOKEN must not contain a newline")
+            );
+            assert_eq!(
+                select_token(None, Some(token.to_string()), || Ok(None)),
+                Err("TOKEN must not contain a newline")
+            );
+        }
+    }
+
+    #[test]
+    fn valid_token_precedence() {
+        let saved = || -> Result<Option<String>, &'static str> { panic!("saved token read") };
+        assert_eq!(
+            select_token(Some("env".to_string()), Some("file".to_string()), saved),
+            Ok(Some("env".to_string()))
+        );
+        assert_eq!(
+            select_token(None, Some("file".to_string()), saved),
+            Ok(Some("file".to_string()))
+        );
+        assert_eq!(
+            select_token(None, None, || Ok(Some("saved".to_string()))),
+            Ok(Some("saved".to_string()))
+        );
+    }
+
+    #[test]
+    fn device_ref_ids_and_serial_numbers() {
+        let id = |value: &str| DeviceRef::Id(value.to_string());
+        let sn = |value: &str| DeviceRef::SerialNumber(value.to_string());
+        for (device, expected) in &[
+            ("5f0c6a5e8f8c1d0001a2b3c4", id("5f0c6a5e8f8c1d0001a2b3c4")),
+            ("5F0C6A5E8F8C1D0001A2B3C4", id("5F0C6A5E8F8C1D0001A2B3C4")),
+            (
+                "0f7fbf3e-4fb2-4b1e-a7a2-7b1e0a1d9c3e",
+                id("0f7fbf3e-4fb2-4b1e-a7a2-7b1e0a1d9c3e"),
+            ),
+            // One digit short or long, or not hexadecimal: serial numbers
+            ("5f0c6a5e8f8c1d0001a2b3c", sn("5f0c6a5e8f8c1d0001a2b3c")),
+            ("5f0c6a5e8f8c1d0001a2b3c4d", sn("5f0c6a5e8f8c1d0001a2b3c4d")),
+            ("5f0c6a5e8f8c1d0001a2b3cg", sn("5f0c6a5e8f8c1d0001a2b3cg")),
+            (
+                "0f7fbf3e-4fb2-4b1e-a7a2-7b1e0a1d9c3",
+                sn("0f7fbf3e-4fb2-4b1e-a7a2-7b1e0a1d9c3"),
+            ),
+            (
+                "0f7fbf3e4fb2-4b1e-a7a2-7b1e-0a1d9c3e",
+                sn("0f7fbf3e4fb2-4b1e-a7a2-7b1e-0a1d9c3e"),
+            ),
+            ("123456", sn("123456")),
+            ("SN-0042", sn("SN-0042")),
+            // Prefixes force the interpretation
+            ("id:123456", id("123456")),
+            (
+                "sn:5f0c6a5e8f8c1d0001a2b3c4",
+                sn("5f0c6a5e8f8c1d0001a2b3c4"),
+            ),
+            ("sn:id:1", sn("id:1")),
+        ] {
+            assert_eq!(DeviceRef::new(device).as_ref(), Ok(expected), "{}", device);
+        }
+    }
+
+    #[test]
+    fn accept_device_id_and_auth_set_id() {
+        let accept = |args: &[&str]| match command(args) {
+            Ok(Command::Accept { device, authset }) => (device, authset),
+            command => panic!("accept not parsed: {:?}", command),
+        };
+        assert_eq!(
+            accept(&["acceptdevice", "--device-id", "d1", "--auth-set-id", "a1"]),
+            (DeviceRef::Id(String::from("d1")), Some(String::from("a1")))
+        );
+        assert_eq!(
+            accept(&["accept", "SN1", "--authset", "a1"]),
+            (
+                DeviceRef::SerialNumber(String::from("SN1")),
+                Some(String::from("a1"))
+            )
+        );
+        for args in &[
+            &["accept"][..],
+            &["accept", "SN1", "--device-id", "d1"],
+            &["accept", "--device-id", ""],
+        ] {
+            assert!(
+                build_cli()
+                    .get_matches_from_safe(
+                        std::iter::once("mender-rust").chain(args.iter().copied())
+                    )
+                    .map_err(|_| "")
+                    .and_then(|matches| Command::new(&matches))
+                    .is_err(),
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn empty_device_ref() {
+        for device in &["", "id:", "sn:"] {
+            assert_eq!(DeviceRef::new(device), Err("device must not be empty"));
+        }
+    }
+
+    #[test]
+    fn deployment_filter_of_args() {
+        match command(&[
+            "cleanupdeployments",
+            "--older-than",
+            "90d",
+            "--status",
+            "aborted,finished",
+            "--max-devices",
+            "0",
+        ]) {
+            Ok(Command::CleanupDeployments { filter }) => assert_eq!(
+                filter,
+                DeploymentFilter {
+                    older_than: Some(Duration::from_secs(90 * 86400)),
+                    statuses: vec![String::from("aborted"), String::from("finished")],
+                    max_devices: Some(0),
+                    device: None,
+                }
+            ),
+            _ => panic!("cleanupdeployments not parsed"),
+        }
+        // listdeployments lists any status by default, cleanupdeployments only finished
+        match command(&["listdeployments"]) {
+            Ok(Command::ListDeployments { filter }) => {
+                assert_eq!(filter, DeploymentFilter::default())
+            }
+            _ => panic!("listdeployments not parsed"),
+        }
+        match command(&["cleanupdeployments"]) {
+            Ok(Command::CleanupDeployments { filter }) => {
+                assert_eq!(filter.statuses, [String::from("finished")])
+            }
+            _ => panic!("cleanupdeployments not parsed"),
+        }
+        for args in &[
+            ["listdeployments", "--max-devices", "many"],
+            ["listdeployments", "--older-than", "soon"],
+        ] {
+            assert!(command(args).is_err(), "{:?}", args);
+        }
+    }
+
+    fn listdevices_columns(args: &[&str]) -> Vec<DeviceColumn> {
+        match command(&[&["listdevices"], args].concat()) {
+            Ok(Command::ListDevices { columns, .. }) => columns,
+            _ => panic!("listdevices not parsed"),
+        }
+    }
+
+    #[test]
+    fn column_presets() {
+        use DeviceColumn::*;
+        assert_eq!(listdevices_columns(&[]), [Id, SerialNumber, Group]);
+        assert_eq!(
+            listdevices_columns(&["--format", "narrow"]),
+            [Id, SerialNumber, Artifact]
+        );
+        assert_eq!(
+            listdevices_columns(&["--format", "wide"]),
+            [
+                Id,
+                SerialNumber,
+                Artifact,
+                Group,
+                DeviceType,
+                Status,
+                LastSeen
+            ]
+        );
+        assert_eq!(
+            listdevices_columns(&["--columns", "client_version,id"]),
+            [ClientVersion, Id]
+        );
+        match command(&["search", "--format", "narrow"]) {
+            Ok(Command::Search { columns, .. }) => {
+                assert_eq!(columns, [Id, SerialNumber, Artifact])
+            }
+            _ => panic!("search not parsed"),
+        }
+        match command(&["search"]) {
+            Ok(Command::Search { columns, .. }) => {
+                assert_eq!(columns, [Id, SerialNumber, ClientVersion])
+            }
+            _ => panic!("search not parsed"),
+        }
+    }
+
+    #[test]
+    fn unknown_column_lists_available_ones() {
+        let err = build_cli()
+            .get_matches_from_safe(["mender-rust", "listdevices", "--columns", "id,uptime"])
+            .unwrap_err();
+        assert_eq!(err.kind, clap::ErrorKind::InvalidValue);
+        for name in &DeviceColumn::NAMES {
+            assert!(
+                err.message.contains(name),
+                "{} not in {}",
+                name,
+                err.message
+            );
+        }
+        assert!(build_cli()
+            .get_matches_from_safe(["mender-rust", "listdevices", "--format", "huge"])
+            .is_err());
+        assert!(build_cli()
+            .get_matches_from_safe([
+                "mender-rust",
+                "listdevices",
+                "--format",
+                "wide",
+                "--columns",
+                "id"
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn serial_number_normalization() {
+        let matching = |ignore_case, trim| SerialNumberMatching { ignore_case, trim };
+        for (ignore_case, trim, expected, found, matches) in &[
+            (false, false, "SN-ABC", "SN-ABC", true),
+            (false, false, "SN-ABC", "sn-abc", false),
+            (false, false, "SN-ABC", " SN-ABC ", false),
+            (true, false, "SN-ABC", "sn-Abc", true),
+            (true, false, "SN-ABC", "sn-abc ", false),
+            (false, true, "SN-ABC", "\tSN-ABC  ", true),
+            (false, true, "SN-ABC", " sn-abc", false),
+            (true, true, "SN-ABC", " sn-abc\n", true),
+            (true, true, " sn-abc", "SN-ABC ", true),
+            (true, true, "SN-ABC", "SN-AB C", false),
+            (true, true, "SN-ABC", "SN-ABCD", false),
+        ] {
+            assert_eq!(
+                matching(*ignore_case, *trim).matches(expected, found),
+                *matches,
+                "{:?} {:?} ignore case {} trim {}",
+                expected,
+                found,
+                ignore_case,
+                trim
+            );
+        }
+        assert_eq!(matching(true, true).normalize("  Sn-ABC "), "sn-abc");
+        assert!(!matching(false, false).normalizes());
+        assert!(matching(false, true).normalizes());
+    }
+
+    #[test]
+    fn serial_number_matching_flags() {
+        let matching = config(&["getid", "SN"]).serial_number_matching;
+        assert!(!matching.ignore_case && !matching.trim);
+        for args in &[
+            ["--ignore-case", "--trim", "getid", "SN"],
+            ["getid", "SN", "--trim", "--ignore-case"],
+        ] {
+            let matching = config(args).serial_number_matching;
+            assert!(matching.ignore_case && matching.trim);
+        }
+    }
+
+    #[test]
+    fn expected_count_values_and_ranges() {
+        for (expected, min, max) in &[
+            ("120", 120, 120),
+            ("0", 0, 0),
+            ("100..150", 100, 150),
+            (" 100 .. 150 ", 100, 150),
+            ("7..7", 7, 7),
+        ] {
+            assert_eq!(
+                ExpectedCount::new(expected),
+                Ok(ExpectedCount {
+                    min: *min,
+                    max: *max
+                }),
+                "{}",
+                expected
+            );
+        }
+        for expected in &[
+            "", "abc", "-1", "1.5", "..150", "100..", "1..2..3", "100-150",
+        ] {
+            assert_eq!(
+                ExpectedCount::new(expected),
+                Err("expected count must be a number or a range MIN..MAX"),
+                "{}",
+                expected
+            );
+        }
+        assert_eq!(
+            ExpectedCount::new("150..100"),
+            Err("expected count range must be MIN..MAX with MIN not above MAX")
+        );
+    }
+
+    #[test]
+    fn expected_count_comparison() {
+        let range = ExpectedCount::new("100..150").unwrap();
+        assert!(!range.contains(99));
+        assert!(range.contains(100));
+        assert!(range.contains(150));
+        assert!(!range.contains(151));
+        let single = ExpectedCount::new("120").unwrap();
+        assert!(single.contains(120));
+        assert!(!single.contains(119) && !single.contains(121));
+        assert_eq!(range.to_string(), "100..150");
+        assert_eq!(single.to_string(), "120");
+    }
+
+    #[test]
+    fn env_file_precedence() {
+        let config_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(config_dir.path().join("profiles")).unwrap();
+        std::fs::write(
+            config_dir.path().join("profiles").join("default.toml"),
+            "server_url = \"https://profile.test\"\n",
+        )
+        .unwrap();
+        let env_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(env_file.path(), "SERVER_URL=https://env-file.test\n").unwrap();
+        let env_file = env_file.path().to_str().unwrap();
+        let server_url = |args: &[&str]| {
+            let matches = build_cli()
+                .get_matches_from_safe(
+                    ["mender-rust", "--config", "/dev/null"]
+                        .iter()
+                        .chain(args.iter())
+                        .copied(),
+                )
+                .unwrap();
+            Config::with_config_dir(
+                Command::ShowConfig,
+                &matches,
+                Some(config_dir.path().to_path_buf()),
+            )
+            .unwrap()
+            .server_url
+        };
+
+        assert_eq!(server_url(&["showconfig"]), "https://profile.test");
+        assert_eq!(
+            server_url(&["--env-file", env_file, "showconfig"]),
+            "https://env-file.test"
+        );
+        assert_eq!(
+            server_url(&[
+                "showconfig",
+                "--env-file",
+                env_file,
+                "--server-url",
+                "https://cli.test"
+            ]),
+            "https://cli.test"
+        );
+    }
+
+    #[test]
+    fn page_size_of_env_or_configuration_file() {
+        let config_file = tempfile::NamedTempFile::new().unwrap();
+        let env_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(env_file.path(), "MENDER_PAGE_SIZE=50\n").unwrap();
+        let pagination = |file_content: &str, args: &[&str]| {
+            std::fs::write(config_file.path(), file_content).unwrap();
+            let matches = build_cli()
+                .get_matches_from_safe(
+                    [
+                        "mender-rust",
+                        "--server-url",
+                        "https://mender.test",
+                        "--config",
+                        config_file.path().to_str().unwrap(),
+                    ]
+                    .iter()
+                    .chain(args.iter())
+                    .copied(),
+                )
+                .unwrap();
+            Config::with_config_dir(Command::ShowConfig, &matches, None)
+                .map(|conf| conf.pagination.per_page)
+        };
+
+        assert_eq!(pagination("", &["showconfig"]), Ok(500));
+        assert_eq!(
+            pagination("[pagination]\nper_page = 100\n", &["showconfig"]),
+            Ok(100)
+        );
+        let env_file = env_file.path().to_str().unwrap();
+        assert_eq!(
+            pagination(
+                "[pagination]\nper_page = 100\n",
+                &["--env-file", env_file, "showconfig"]
+            ),
+            Ok(50)
+        );
+        for invalid in &["[pagination]\nper_page = 0\n", "[pagination]\npage = 1\n"] {
+            assert!(pagination(invalid, &["showconfig"]).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn token_of_env_file_is_masked() {
+        let env_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(env_file.path(), "TOKEN=0123456789secret\n").unwrap();
+        let matches = build_cli()
+            .get_matches_from_safe(vec![
+                "mender-rust",
+                "--server-url",
+                "https://mender.test",
+                "--config",
+                "/dev/null",
+                "--env-file",
+                env_file.path().to_str().unwrap(),
+                "showconfig",
+            ])
+            .unwrap();
+        let conf = Config::with_config_dir(Command::ShowConfig, &matches, None).unwrap();
+        assert_eq!(conf.token.as_deref(), Some("0123456789secret"));
+        let json = conf.to_json().unwrap();
+        assert!(!json.contains("secret"), "{}", json);
+        assert!(json.contains("\"01234567\""), "{}", json);
     }
 }
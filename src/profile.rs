@@ -0,0 +1,363 @@
+use super::token_cache;
+use serde::Deserialize;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Profile used when none is given.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Check that a profile name can safely be used as a file name.
+pub fn check_name(profile: &str) -> Result<(), &'static str> {
+    if !profile.is_empty()
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err("profile name must only contain letters, digits, '-' and '_'")
+    }
+}
+
+/// Configuration directory of mender-rust, under XDG_CONFIG_HOME or else ~/.config.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("mender-rust"))
+}
+
+/// File of the token saved by login --save, one file per profile.
+fn token_path(config_dir: Option<&Path>, profile: &str) -> Result<PathBuf, Box<dyn Error>> {
+    config_dir
+        .map(|dir| dir.join("tokens").join(profile))
+        .ok_or_else(|| "neither XDG_CONFIG_HOME nor HOME is defined".into())
+}
+
+/// Defaults of a profile, read from profiles/<profile>.toml in the configuration
+/// directory. The command line and the environment take precedence over them.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileSettings {
+    pub server_url: Option<String>,
+    /// Default of --output, text or json.
+    pub output: Option<String>,
+    /// Proceed without confirmation as with --yes.
+    pub assume_yes: Option<bool>,
+    /// Name to type to confirm destructive actions instead of y.
+    pub confirm_server: Option<String>,
+    /// Defaults of --concurrent-requests, --request-timeout and --deadline.
+    pub concurrent_requests: Option<usize>,
+    pub request_timeout: Option<String>,
+    pub deadline: Option<String>,
+}
+
+/// Read the settings of a profile, a profile without settings file has none.
+pub fn load_settings(
+    config_dir: Option<&Path>,
+    profile: &str,
+) -> Result<ProfileSettings, &'static str> {
+    let path = match config_dir {
+        Some(dir) => dir.join("profiles").join(format!("{}.toml", profile)),
+        None => return Ok(ProfileSettings::default()),
+    };
+    match std::fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content).map_err(|_| {
+            "profile settings must be TOML with only server_url, output, assume_yes, \
+             confirm_server, concurrent_requests, request_timeout and deadline keys"
+        }),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(ProfileSettings::default()),
+        Err(_) => Err("profile settings can't be read"),
+    }
+}
+
+/// Read the token saved for a profile, None if there is none. The default profile falls
+/// back to the token saved in ~/.mender-rust/token.
+pub fn load_token(
+    config_dir: Option<&Path>,
+    profile: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    load_token_or(config_dir, profile, token_cache::load_token)
+}
+
+/// Read the token saved for a profile, or else the default token for the default profile.
+fn load_token_or(
+    config_dir: Option<&Path>,
+    profile: &str,
+    default_token: impl FnOnce() -> Result<Option<String>, Box<dyn Error>>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if config_dir.is_none() {
+        return Ok(None);
+    }
+    match token_cache::read_token(&token_path(config_dir, profile)?)? {
+        None if profile == DEFAULT_PROFILE => default_token(),
+        token => Ok(token),
+    }
+}
+
+/// Save the token of a profile, the file is only readable by the current user.
+pub fn save_token(
+    config_dir: Option<&Path>,
+    profile: &str,
+    token: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let path = token_path(config_dir, profile)?;
+    token_cache::write_token(&path, token)?;
+    Ok(path)
+}
+
+/// Remove the token saved for a profile, return false if there was none. The token saved
+/// in ~/.mender-rust/token is removed too for the default profile, as it would be used
+/// otherwise.
+pub fn remove_token(config_dir: Option<&Path>, profile: &str) -> Result<bool, Box<dyn Error>> {
+    remove_token_or(config_dir, profile, token_cache::remove_token)
+}
+
+/// Remove the token saved for a profile, and the default token for the default profile.
+fn remove_token_or(
+    config_dir: Option<&Path>,
+    profile: &str,
+    remove_default_token: impl FnOnce() -> Result<bool, Box<dyn Error>>,
+) -> Result<bool, Box<dyn Error>> {
+    let removed = token_cache::remove_token_file(&token_path(config_dir, profile)?)?;
+    if profile == DEFAULT_PROFILE {
+        Ok(remove_default_token()? || removed)
+    } else {
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mender::{get_devices_not_in_any_group, GET_DEVICES_INVENTORY_API};
+    use crate::parse::{build_cli, Command, Config, OutputFormat};
+    use std::time::Duration;
+
+    fn config(config_dir: &Path, profile: &str) -> Config {
+        config_with_args(config_dir, profile, &[])
+    }
+
+    fn config_with_args(config_dir: &Path, profile: &str, args: &[&str]) -> Config {
+        let matches = build_cli()
+            .get_matches_from_safe(
+                [
+                    "mender-rust",
+                    "--config",
+                    "/dev/null",
+                    "--profile",
+                    profile,
+                    "getdevicesnotinanygroup",
+                ]
+                .iter()
+                .chain(args.iter())
+                .copied(),
+            )
+            .unwrap();
+        Config::with_config_dir(
+            Command::new(&matches).unwrap(),
+            &matches,
+            Some(config_dir.to_path_buf()),
+        )
+        .unwrap()
+    }
+
+    /// Server expecting a listing of the devices with the given token.
+    fn server_for(token: &str) -> (mockito::ServerGuard, mockito::Mock) {
+        let mut server = mockito::Server::new();
+        let devices = server
+            .mock("GET", GET_DEVICES_INVENTORY_API)
+            .match_query(mockito::Matcher::Any)
+            .match_header("authorization", format!("Bearer {}", token).as_str())
+            .with_body("[]")
+            .create();
+        (server, devices)
+    }
+
+    #[test]
+    fn tokens_and_settings_are_isolated_between_profiles() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let dir = Some(config_dir.path());
+        let (production_server, production_devices) = server_for("production-token");
+        let (lab_server, lab_devices) = server_for("lab-token");
+        std::fs::create_dir(config_dir.path().join("profiles")).unwrap();
+        std::fs::write(
+            config_dir.path().join("profiles").join("production.toml"),
+            format!(
+                "server_url = \"{}\"\noutput = \"json\"\nassume_yes = false\nconfirm_server = \"production\"\n",
+                production_server.url()
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            config_dir.path().join("profiles").join("lab.toml"),
+            format!("server_url = \"{}\"\nassume_yes = true\n", lab_server.url()),
+        )
+        .unwrap();
+        save_token(dir, "production", "production-token").unwrap();
+        save_token(dir, "lab", "lab-token").unwrap();
+
+        let production = config(config_dir.path(), "production");
+        assert_eq!(production.server_url, production_server.url());
+        assert_eq!(production.output_format, OutputFormat::Json);
+        assert!(!production.assume_yes);
+        assert_eq!(production.confirm_server.as_deref(), Some("production"));
+        get_devices_not_in_any_group(&production).unwrap();
+        production_devices.assert();
+
+        let lab = config(config_dir.path(), "lab");
+        assert_eq!(lab.server_url, lab_server.url());
+        assert_eq!(lab.output_format, OutputFormat::Text);
+        assert!(lab.assume_yes);
+        assert_eq!(lab.confirm_server, None);
+        get_devices_not_in_any_group(&lab).unwrap();
+        lab_devices.assert();
+
+        // Logging out of a profile keeps the token of the other one
+        assert!(remove_token(dir, "lab").unwrap());
+        assert_eq!(load_token(dir, "lab").unwrap(), None);
+        assert_eq!(
+            load_token(dir, "production").unwrap().as_deref(),
+            Some("production-token")
+        );
+    }
+
+    #[test]
+    fn execution_policy_of_profile() {
+        let config_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(config_dir.path().join("profiles")).unwrap();
+        std::fs::write(
+            config_dir.path().join("profiles").join("lab.toml"),
+            "server_url = \"https://lab.test\"\nconcurrent_requests = 4\nrequest_timeout = \"20s\"\ndeadline = \"5m\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            config_dir.path().join("profiles").join("production.toml"),
+            "server_url = \"https://production.test\"\n",
+        )
+        .unwrap();
+        for profile in &["lab", "production"] {
+            save_token(Some(config_dir.path()), profile, "token").unwrap();
+        }
+
+        let execution = config(config_dir.path(), "lab").execution;
+        assert_eq!(execution.max_concurrent_requests, 4);
+        assert_eq!(execution.request_timeout, Some(Duration::from_secs(20)));
+        assert_eq!(execution.deadline, Some(Duration::from_secs(300)));
+        // The command line takes precedence over the profile
+        let execution = config_with_args(
+            config_dir.path(),
+            "lab",
+            &["--deadline", "30s", "--concurrent-requests", "2"],
+        )
+        .execution;
+        assert_eq!(execution.max_concurrent_requests, 2);
+        assert_eq!(execution.request_timeout, Some(Duration::from_secs(20)));
+        assert_eq!(execution.deadline, Some(Duration::from_secs(30)));
+        // Other profiles keep the defaults
+        let execution = config(config_dir.path(), "production").execution;
+        assert_eq!(execution.max_concurrent_requests, 1);
+        assert_eq!(execution.deadline, None);
+    }
+
+    #[test]
+    fn saved_token_is_private() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let path = save_token(Some(config_dir.path()), "default", " token\n").unwrap();
+        assert_eq!(
+            load_token(Some(config_dir.path()), "default")
+                .unwrap()
+                .as_deref(),
+            Some("token")
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(
+                &std::fs::metadata(path).unwrap().permissions()
+            ) & 0o777,
+            0o600
+        );
+    }
+
+    #[test]
+    fn invalid_profile_settings() {
+        let config_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(config_dir.path().join("profiles")).unwrap();
+        std::fs::write(
+            config_dir.path().join("profiles").join("default.toml"),
+            "token = \"abc\"\n",
+        )
+        .unwrap();
+        assert!(load_settings(Some(config_dir.path()), "default").is_err());
+        assert!(load_settings(Some(config_dir.path()), "other")
+            .unwrap()
+            .server_url
+            .is_none());
+    }
+
+    #[test]
+    fn logout_of_default_profile_removes_older_token() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let dir = Some(config_dir.path());
+        let home = tempfile::tempdir().unwrap();
+        let cached = home.path().join(".mender-rust").join("token");
+        let remove_cached = || token_cache::remove_token_file(&cached);
+        let cached_token = || token_cache::read_token(&cached);
+
+        // A token saved by an older version only
+        token_cache::write_token(&cached, "cached-token").unwrap();
+        assert!(!remove_token_or(dir, "lab", remove_cached).unwrap());
+        assert!(remove_token_or(dir, DEFAULT_PROFILE, remove_cached).unwrap());
+        assert_eq!(
+            load_token_or(dir, DEFAULT_PROFILE, cached_token).unwrap(),
+            None
+        );
+
+        // Both tokens
+        token_cache::write_token(&cached, "cached-token").unwrap();
+        save_token(dir, DEFAULT_PROFILE, "profile-token").unwrap();
+        assert!(remove_token_or(dir, DEFAULT_PROFILE, remove_cached).unwrap());
+        assert_eq!(
+            load_token_or(dir, DEFAULT_PROFILE, cached_token).unwrap(),
+            None
+        );
+        assert!(!remove_token_or(dir, DEFAULT_PROFILE, remove_cached).unwrap());
+    }
+
+    #[test]
+    fn default_profile_falls_back_to_saved_token() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let dir = Some(config_dir.path());
+        let home = tempfile::tempdir().unwrap();
+        let cached = home.path().join(".mender-rust").join("token");
+        let cached_token = || token_cache::read_token(&cached);
+        assert_eq!(
+            load_token_or(dir, DEFAULT_PROFILE, cached_token).unwrap(),
+            None
+        );
+
+        token_cache::write_token(&cached, "cached-token").unwrap();
+        assert_eq!(
+            load_token_or(dir, DEFAULT_PROFILE, cached_token)
+                .unwrap()
+                .as_deref(),
+            Some("cached-token")
+        );
+        // Other profiles only use their own token
+        assert_eq!(load_token_or(dir, "lab", cached_token).unwrap(), None);
+        // The token of the profile takes precedence
+        save_token(dir, DEFAULT_PROFILE, "profile-token").unwrap();
+        assert_eq!(
+            load_token_or(dir, DEFAULT_PROFILE, cached_token)
+                .unwrap()
+                .as_deref(),
+            Some("profile-token")
+        );
+        // No configuration directory means no saved token at all
+        assert_eq!(
+            load_token_or(None, DEFAULT_PROFILE, cached_token).unwrap(),
+            None
+        );
+    }
+}
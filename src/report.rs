@@ -0,0 +1,154 @@
+/// Page of the fleet report, `{{name}}` placeholders are replaced when rendering.
+const FLEET_REPORT_TEMPLATE: &str = include_str!("fleet_report.html");
+
+/// Width in pixels of the longest bar of the charts.
+const BAR_MAX_WIDTH: usize = 400;
+/// Height in pixels of a bar, including the space below it.
+const BAR_HEIGHT: usize = 22;
+/// Width in pixels reserved for the labels on the left of the bars.
+const LABEL_WIDTH: usize = 250;
+
+/// Data shown in the fleet report.
+pub struct FleetReport {
+    pub server_url: String,
+    pub generated: String,
+    pub device_statuses: Vec<(String, usize)>,
+    pub artifacts_per_group: Vec<(String, Vec<(String, usize)>)>,
+    pub stale_days: u64,
+    pub stale_devices: Vec<[String; 3]>,
+    pub deployments: Vec<[String; 3]>,
+}
+
+impl FleetReport {
+    /// Render the report as a self-contained HTML page.
+    pub fn render(&self) -> String {
+        let mut artifact_adoption = String::new();
+        for (group, artifacts) in &self.artifacts_per_group {
+            artifact_adoption.push_str(&format!("<h3>{}</h3>\n", escape(group)));
+            artifact_adoption.push_str(&bar_chart(artifacts));
+            artifact_adoption.push_str(&table(&["Artifact", "Devices"], &counts_rows(artifacts)));
+        }
+        if self.artifacts_per_group.is_empty() {
+            artifact_adoption.push_str("<p>No group</p>\n");
+        }
+
+        render_template(
+            FLEET_REPORT_TEMPLATE,
+            &[
+                ("server_url", escape(&self.server_url)),
+                ("generated", escape(&self.generated)),
+                (
+                    "device_status",
+                    bar_chart(&self.device_statuses)
+                        + &table(&["Status", "Devices"], &counts_rows(&self.device_statuses)),
+                ),
+                ("artifact_adoption", artifact_adoption),
+                ("stale_days", self.stale_days.to_string()),
+                (
+                    "stale_devices",
+                    table(
+                        &["Device", "SerialNumber", "Last update"],
+                        &self.stale_devices,
+                    ),
+                ),
+                (
+                    "deployments",
+                    table(&["Deployment", "Name", "Artifact"], &self.deployments),
+                ),
+            ],
+        )
+    }
+}
+
+/// Replace the `{{name}}` placeholders of a template in a single pass, so that values
+/// are inserted as is. Unknown placeholders are kept.
+fn render_template(template: &str, values: &[(&str, String)]) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find("}}") {
+            Some(end) => end + 2,
+            None => break,
+        };
+        match values.iter().find(|(name, _)| *name == &rest[2..end - 2]) {
+            Some((_, value)) => rendered.push_str(value),
+            None => rendered.push_str(&rest[..end]),
+        }
+        rest = &rest[end..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn counts_rows(counts: &[(String, usize)]) -> Vec<[String; 2]> {
+    counts
+        .iter()
+        .map(|(name, count)| [name.clone(), count.to_string()])
+        .collect()
+}
+
+fn table<const N: usize>(headers: &[&str; N], rows: &[[String; N]]) -> String {
+    if rows.is_empty() {
+        return String::from("<p>None</p>\n");
+    }
+    let mut html = String::from("<table>\n<tr>");
+    for header in headers {
+        html.push_str(&format!("<th>{}</th>", escape(header)));
+    }
+    html.push_str("</tr>\n");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            if cell.parse::<usize>().is_ok() {
+                html.push_str(&format!("<td class=\"count\">{}</td>", cell));
+            } else {
+                html.push_str(&format!("<td>{}</td>", escape(cell)));
+            }
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// Horizontal bar chart as inline SVG, bars are scaled on the largest count.
+fn bar_chart(counts: &[(String, usize)]) -> String {
+    let max = match counts.iter().map(|(_, count)| *count).max() {
+        Some(max) if max > 0 => max,
+        _ => return String::new(),
+    };
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+        LABEL_WIDTH + BAR_MAX_WIDTH + 60,
+        counts.len() * BAR_HEIGHT
+    );
+    for (idx, (name, count)) in counts.iter().enumerate() {
+        let y = idx * BAR_HEIGHT;
+        let width = count * BAR_MAX_WIDTH / max;
+        svg.push_str(&format!(
+            "<text x=\"0\" y=\"{}\">{}</text>\
+             <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#4a90d9\"/>\
+             <text x=\"{}\" y=\"{}\">{}</text>\n",
+            y + 14,
+            escape(name),
+            LABEL_WIDTH,
+            y + 2,
+            width,
+            BAR_HEIGHT - 6,
+            LABEL_WIDTH + width + 5,
+            y + 14,
+            count
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Status of a deployment. The vocabulary changed across server versions, so statuses
+/// are normalized by `parse` and the ones we don't know are kept as `Other` instead of
+/// being rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeploymentStatus {
+    /// Created or scheduled, no device started the update yet.
+    Pending,
+    InProgress,
+    Finished,
+    /// Status unknown to this version, never considered finished.
+    Other(String),
+}
+
+impl DeploymentStatus {
+    /// Map a status returned by the server, whatever its spelling, onto its variant.
+    pub fn parse(status: &str) -> DeploymentStatus {
+        match compact(status).as_str() {
+            "pending" | "scheduled" => DeploymentStatus::Pending,
+            "inprogress" => DeploymentStatus::InProgress,
+            "finished" => DeploymentStatus::Finished,
+            _ => DeploymentStatus::Other(status.to_string()),
+        }
+    }
+
+    /// Name of the status as used in the requests to the server.
+    pub fn as_str(&self) -> &str {
+        match self {
+            DeploymentStatus::Pending => "pending",
+            DeploymentStatus::InProgress => "inprogress",
+            DeploymentStatus::Finished => "finished",
+            DeploymentStatus::Other(status) => status,
+        }
+    }
+
+    /// Whether the deployment is over, a note is printed the first time an unknown
+    /// status is seen.
+    pub fn is_finished(&self) -> bool {
+        match self {
+            DeploymentStatus::Finished => true,
+            DeploymentStatus::Other(status) => {
+                note_unknown("deployment", status);
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for DeploymentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Status of a device in a deployment, as counted in the deployment statistics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceStatus {
+    Pending,
+    Downloading,
+    Installing,
+    Rebooting,
+    PauseBeforeInstalling,
+    PauseBeforeCommitting,
+    PauseBeforeRebooting,
+    Success,
+    Failure,
+    AlreadyInstalled,
+    NoArtifact,
+    Aborted,
+    Decommissioned,
+    /// Status unknown to this version, considered as still updating.
+    Other(String),
+}
+
+impl DeviceStatus {
+    /// Map a status returned by the server, whatever its spelling, onto its variant.
+    pub fn parse(status: &str) -> DeviceStatus {
+        match compact(status).as_str() {
+            "pending" => DeviceStatus::Pending,
+            "downloading" => DeviceStatus::Downloading,
+            "installing" => DeviceStatus::Installing,
+            "rebooting" => DeviceStatus::Rebooting,
+            "pausebeforeinstalling" => DeviceStatus::PauseBeforeInstalling,
+            "pausebeforecommitting" => DeviceStatus::PauseBeforeCommitting,
+            "pausebeforerebooting" => DeviceStatus::PauseBeforeRebooting,
+            "success" => DeviceStatus::Success,
+            "failure" | "failed" => DeviceStatus::Failure,
+            "alreadyinstalled" => DeviceStatus::AlreadyInstalled,
+            "noartifact" => DeviceStatus::NoArtifact,
+            "aborted" => DeviceStatus::Aborted,
+            "decommissioned" => DeviceStatus::Decommissioned,
+            _ => DeviceStatus::Other(status.to_string()),
+        }
+    }
+
+    /// Name of the status as returned in the statistics by the current servers.
+    pub fn as_str(&self) -> &str {
+        match self {
+            DeviceStatus::Pending => "pending",
+            DeviceStatus::Downloading => "downloading",
+            DeviceStatus::Installing => "installing",
+            DeviceStatus::Rebooting => "rebooting",
+            DeviceStatus::PauseBeforeInstalling => "pause_before_installing",
+            DeviceStatus::PauseBeforeCommitting => "pause_before_committing",
+            DeviceStatus::PauseBeforeRebooting => "pause_before_rebooting",
+            DeviceStatus::Success => "success",
+            DeviceStatus::Failure => "failure",
+            DeviceStatus::AlreadyInstalled => "already-installed",
+            DeviceStatus::NoArtifact => "noartifact",
+            DeviceStatus::Aborted => "aborted",
+            DeviceStatus::Decommissioned => "decommissioned",
+            DeviceStatus::Other(status) => status,
+        }
+    }
+
+    /// Whether the device is done with the deployment. Unknown statuses are not, a
+    /// note is printed the first time one is seen.
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            DeviceStatus::Success
+            | DeviceStatus::Failure
+            | DeviceStatus::AlreadyInstalled
+            | DeviceStatus::NoArtifact
+            | DeviceStatus::Aborted
+            | DeviceStatus::Decommissioned => true,
+            DeviceStatus::Other(status) => {
+                note_unknown("device", status);
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Rename the states of deployment statistics to their canonical name, the counts of
+/// synonyms are added up.
+pub fn normalize_statistics(statistics: HashMap<String, i32>) -> HashMap<String, i32> {
+    let mut normalized = HashMap::new();
+    for (state, count) in statistics {
+        *normalized
+            .entry(DeviceStatus::parse(&state).as_str().to_string())
+            .or_insert(0) += count;
+    }
+    normalized
+}
+
+/// Lowercase a status and drop its separators, so that `in_progress`, `in-progress` and
+/// `inprogress` are the same.
+fn compact(status: &str) -> String {
+    status
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Unknown statuses already reported.
+static NOTED_UNKNOWN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Print a note on stderr the first time an unknown status is seen.
+fn note_unknown(kind: &str, status: &str) {
+    let mut noted = NOTED_UNKNOWN.lock().unwrap_or_else(|err| err.into_inner());
+    let key = format!("{} {}", kind, status);
+    if !noted.contains(&key) {
+        eprintln!(
+            "Note: unknown {} status '{}', considered as not finished",
+            kind, status
+        );
+        noted.push(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deployment_statuses_of_all_servers() {
+        for (observed, status) in &[
+            ("pending", DeploymentStatus::Pending),
+            ("scheduled", DeploymentStatus::Pending),
+            ("inprogress", DeploymentStatus::InProgress),
+            ("in_progress", DeploymentStatus::InProgress),
+            ("in-progress", DeploymentStatus::InProgress),
+            ("In Progress", DeploymentStatus::InProgress),
+            ("finished", DeploymentStatus::Finished),
+            (" Finished\n", DeploymentStatus::Finished),
+        ] {
+            assert_eq!(DeploymentStatus::parse(observed), *status, "{}", observed);
+        }
+        assert_eq!(
+            DeploymentStatus::parse("paused"),
+            DeploymentStatus::Other(String::from("paused"))
+        );
+    }
+
+    #[test]
+    fn device_statuses_of_all_servers() {
+        use DeviceStatus::*;
+        for (observed, status) in &[
+            ("pending", Pending),
+            ("downloading", Downloading),
+            ("installing", Installing),
+            ("rebooting", Rebooting),
+            ("pause_before_installing", PauseBeforeInstalling),
+            ("pause-before-installing", PauseBeforeInstalling),
+            ("pause_before_committing", PauseBeforeCommitting),
+            ("PauseBeforeCommitting", PauseBeforeCommitting),
+            ("pause_before_rebooting", PauseBeforeRebooting),
+            ("success", Success),
+            ("failure", Failure),
+            ("failed", Failure),
+            ("already-installed", AlreadyInstalled),
+            ("already_installed", AlreadyInstalled),
+            ("alreadyinstalled", AlreadyInstalled),
+            ("noartifact", NoArtifact),
+            ("no_artifact", NoArtifact),
+            ("aborted", Aborted),
+            ("decommissioned", Decommissioned),
+        ] {
+            assert_eq!(DeviceStatus::parse(observed), *status, "{}", observed);
+        }
+        assert_eq!(
+            DeviceStatus::parse("postponed"),
+            Other(String::from("postponed"))
+        );
+    }
+
+    #[test]
+    fn canonical_names_parse_back() {
+        for status in &[
+            DeploymentStatus::Pending,
+            DeploymentStatus::InProgress,
+            DeploymentStatus::Finished,
+        ] {
+            assert_eq!(DeploymentStatus::parse(status.as_str()), *status);
+        }
+        use DeviceStatus::*;
+        for status in &[
+            Pending,
+            Downloading,
+            Installing,
+            Rebooting,
+            PauseBeforeInstalling,
+            PauseBeforeCommitting,
+            PauseBeforeRebooting,
+            Success,
+            Failure,
+            AlreadyInstalled,
+            NoArtifact,
+            Aborted,
+            Decommissioned,
+        ] {
+            assert_eq!(DeviceStatus::parse(status.as_str()), *status);
+        }
+    }
+
+    #[test]
+    fn unknown_statuses_are_not_finished() {
+        assert!(DeploymentStatus::Finished.is_finished());
+        assert!(!DeploymentStatus::InProgress.is_finished());
+        assert!(!DeploymentStatus::parse("paused").is_finished());
+        assert!(DeviceStatus::parse("already_installed").is_terminal());
+        assert!(!DeviceStatus::parse("pause_before_committing").is_terminal());
+        assert!(!DeviceStatus::parse("postponed").is_terminal());
+    }
+
+    #[test]
+    fn statistics_of_synonyms_are_added_up() {
+        let statistics: HashMap<String, i32> = [
+            ("failure", 1),
+            ("failed", 2),
+            ("already-installed", 3),
+            ("already_installed", 4),
+            ("pause_before_committing", 5),
+            ("postponed", 6),
+        ]
+        .iter()
+        .map(|(state, count)| (state.to_string(), *count))
+        .collect();
+        let mut normalized: Vec<(String, i32)> =
+            normalize_statistics(statistics).into_iter().collect();
+        normalized.sort();
+        assert_eq!(
+            normalized,
+            [
+                (String::from("already-installed"), 7),
+                (String::from("failure"), 3),
+                (String::from("pause_before_committing"), 5),
+                (String::from("postponed"), 6),
+            ]
+        );
+    }
+}
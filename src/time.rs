@@ -0,0 +1,280 @@
+use chrono::{
+    DateTime, Duration as ChronoDuration, FixedOffset, Local, LocalResult, NaiveDate, NaiveTime,
+    TimeZone, Utc,
+};
+use std::time::Duration;
+
+/// Parse a duration made of a number and an optional unit: s (default), m, h or d.
+pub fn parse_duration(duration: &str) -> Result<Duration, &'static str> {
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    const FORMAT_ERROR: &str = "durations must be a number followed by s, m, h or d";
+    let value: u64 = value.parse().map_err(|_| FORMAT_ERROR)?;
+    let unit_seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(FORMAT_ERROR),
+    };
+    value
+        .checked_mul(unit_seconds)
+        .map(Duration::from_secs)
+        .ok_or(FORMAT_ERROR)
+}
+
+enum Day {
+    Next,
+    Today,
+    Tomorrow,
+    Date(NaiveDate),
+}
+
+/// Parse a start time given as RFC3339 or as '[today|tomorrow|YYYY-MM-DD] HH:MM[:SS] [zone]'.
+/// The zone is UTC, Z or an offset such as +02:00, the local timezone is used if absent.
+/// Without a day the next occurrence of the time is used.
+pub fn parse_start_time(start: &str) -> Result<DateTime<Utc>, &'static str> {
+    if let Ok(start) = DateTime::parse_from_rfc3339(start) {
+        return Ok(start.with_timezone(&Utc));
+    }
+    const FORMAT_ERROR: &str =
+        "start time must be RFC3339 or [today|tomorrow|YYYY-MM-DD] HH:MM[:SS] [UTC|Z|+HH:MM]";
+
+    let mut tokens: Vec<&str> = start.split_whitespace().collect();
+    let zone = match tokens.last() {
+        Some(&"UTC") | Some(&"Z") => {
+            tokens.pop();
+            Some(FixedOffset::east_opt(0).unwrap())
+        }
+        Some(zone) if zone.starts_with(&['+', '-'][..]) => {
+            let offset = DateTime::parse_from_str(
+                &format!("2000-01-01T00:00:00{}", zone),
+                "%Y-%m-%dT%H:%M:%S%:z",
+            )
+            .map_err(|_| FORMAT_ERROR)?
+            .offset()
+            .to_owned();
+            tokens.pop();
+            Some(offset)
+        }
+        _ => None,
+    };
+    let (day, time) = match tokens[..] {
+        [time] => (Day::Next, time),
+        ["today", time] => (Day::Today, time),
+        ["tomorrow", time] => (Day::Tomorrow, time),
+        [date, time] => (
+            Day::Date(NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| FORMAT_ERROR)?),
+            time,
+        ),
+        _ => return Err(FORMAT_ERROR),
+    };
+    let time = NaiveTime::parse_from_str(time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+        .map_err(|_| FORMAT_ERROR)?;
+
+    match zone {
+        Some(zone) => resolve(&zone, day, time),
+        None => resolve(&Local, day, time),
+    }
+}
+
+/// Get the UTC time of a day and time in a timezone, times which are skipped or
+/// repeated by a DST transition are rejected.
+fn resolve<Tz: TimeZone>(
+    zone: &Tz,
+    day: Day,
+    time: NaiveTime,
+) -> Result<DateTime<Utc>, &'static str> {
+    let today = Utc::now().with_timezone(zone).date_naive();
+    let date = match day {
+        Day::Date(date) => date,
+        Day::Today | Day::Next => today,
+        Day::Tomorrow => today + ChronoDuration::days(1),
+    };
+    let start = match zone.from_local_datetime(&date.and_time(time)) {
+        LocalResult::Single(start) => start,
+        LocalResult::Ambiguous(_, _) => {
+            return Err(
+                "start time happens twice because of a DST transition, add a zone such as +01:00",
+            )
+        }
+        LocalResult::None => {
+            return Err("start time doesn't exist because of a DST transition, use another time")
+        }
+    };
+    match day {
+        Day::Next if start <= Utc::now() => resolve(zone, Day::Tomorrow, time),
+        _ => Ok(start.with_timezone(&Utc)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        for invalid in &["", "m", "1w", "-1s", "1.5h", "1 h"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn duration_overflow() {
+        let max = u64::MAX.to_string();
+        assert_eq!(parse_duration(&max), Ok(Duration::from_secs(u64::MAX)));
+        for unit in &["m", "h", "d"] {
+            assert_eq!(
+                parse_duration(&format!("{}{}", max, unit)),
+                Err("durations must be a number followed by s, m, h or d")
+            );
+        }
+        assert_eq!(
+            parse_duration(&format!("{}0", max)),
+            Err("durations must be a number followed by s, m, h or d")
+        );
+    }
+
+    /// Timezone at +01:00 in winter and +02:00 in summer, as Europe/Paris in 2030.
+    #[derive(Clone)]
+    struct DstZone;
+
+    impl DstZone {
+        const WINTER: i32 = 3600;
+        const SUMMER: i32 = 7200;
+
+        fn is_summer(utc: &NaiveDateTime) -> bool {
+            let transition = |month, day| {
+                NaiveDate::from_ymd_opt(2030, month, day)
+                    .unwrap()
+                    .and_hms_opt(1, 0, 0)
+                    .unwrap()
+            };
+            *utc >= transition(3, 31) && *utc < transition(10, 27)
+        }
+    }
+
+    impl TimeZone for DstZone {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> DstZone {
+            DstZone
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let offsets: Vec<FixedOffset> = [DstZone::WINTER, DstZone::SUMMER]
+                .iter()
+                .map(|secs| FixedOffset::east_opt(*secs).unwrap())
+                .filter(|offset| {
+                    self.offset_from_utc_datetime(
+                        &(*local - ChronoDuration::seconds(offset.local_minus_utc().into())),
+                    ) == *offset
+                })
+                .collect();
+            match offsets[..] {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(offset),
+                [winter, summer] => LocalResult::Ambiguous(summer, winter),
+                _ => unreachable!(),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            FixedOffset::east_opt(if DstZone::is_summer(utc) {
+                DstZone::SUMMER
+            } else {
+                DstZone::WINTER
+            })
+            .unwrap()
+        }
+    }
+
+    fn resolve_in_dst_zone(date: &str, time: &str) -> Result<DateTime<Utc>, &'static str> {
+        resolve(
+            &DstZone,
+            Day::Date(NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()),
+            NaiveTime::parse_from_str(time, "%H:%M").unwrap(),
+        )
+    }
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn spring_forward_gap_is_rejected() {
+        assert_eq!(
+            resolve_in_dst_zone("2030-03-31", "02:30"),
+            Err("start time doesn't exist because of a DST transition, use another time")
+        );
+        assert_eq!(
+            resolve_in_dst_zone("2030-03-31", "01:59"),
+            Ok(utc("2030-03-31T00:59:00Z"))
+        );
+        assert_eq!(
+            resolve_in_dst_zone("2030-03-31", "03:00"),
+            Ok(utc("2030-03-31T01:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn fall_back_repeated_time_is_rejected() {
+        assert_eq!(
+            resolve_in_dst_zone("2030-10-27", "02:30"),
+            Err("start time happens twice because of a DST transition, add a zone such as +01:00")
+        );
+        assert_eq!(
+            resolve_in_dst_zone("2030-10-27", "01:59"),
+            Ok(utc("2030-10-26T23:59:00Z"))
+        );
+        assert_eq!(
+            resolve_in_dst_zone("2030-10-27", "03:00"),
+            Ok(utc("2030-10-27T02:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn start_time_with_zone() {
+        assert_eq!(
+            parse_start_time("2030-10-27 02:30 +01:00"),
+            Ok(utc("2030-10-27T01:30:00Z"))
+        );
+        assert_eq!(
+            parse_start_time("2030-03-31 02:30 UTC"),
+            Ok(utc("2030-03-31T02:30:00Z"))
+        );
+        assert_eq!(
+            parse_start_time("2030-03-31T02:30:00+02:00"),
+            Ok(utc("2030-03-31T00:30:00Z"))
+        );
+        for invalid in &["02:30 CET", "next week", "2030-13-01 02:30 Z", "25:00 Z"] {
+            assert!(parse_start_time(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn next_occurrence_is_in_the_future() {
+        let start = parse_start_time("00:00 Z").unwrap();
+        let now = Utc::now();
+        assert!(start > now && start <= now + ChronoDuration::days(1));
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Wall time of the HTTP requests made during a command.
+pub struct Timings {
+    start: Instant,
+    requests: Mutex<Vec<(String, Duration)>>,
+}
+
+impl Timings {
+    pub fn new() -> Timings {
+        Timings {
+            start: Instant::now(),
+            requests: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn record(&self, url: &str, duration: Duration) {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((url.to_string(), duration));
+    }
+
+    /// Summary of the recorded requests with the slowest ones, the time not spent
+    /// waiting for the server is accounted as client-side processing.
+    pub fn summary(&self) -> String {
+        let total = self.start.elapsed();
+        let mut requests = self.requests.lock().unwrap().clone();
+        let network: Duration = requests.iter().map(|(_, duration)| *duration).sum();
+        requests.sort_by_key(|request| std::cmp::Reverse(request.1));
+
+        let mut disp = format!(
+            "Timings: {} requests in {:.2?}, network {:.2?}, client-side processing {:.2?}\n",
+            requests.len(),
+            total,
+            network,
+            total.checked_sub(network).unwrap_or_default()
+        );
+        for (url, duration) in requests.iter().take(5) {
+            disp.push_str(&format!("  {:>10.2?} {}\n", duration, url));
+        }
+        disp
+    }
+}
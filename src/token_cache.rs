@@ -0,0 +1,108 @@
+//! Token of the default profile saved by login --save in ~/.mender-rust/token by older
+//! versions, only read as a fallback of the token of the default profile, see profile.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Directory of the saved token, ~/.mender-rust.
+fn token_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(|home| PathBuf::from(home).join(".mender-rust"))
+}
+
+/// File of the saved token, None if HOME is not defined.
+pub fn token_path() -> Option<PathBuf> {
+    token_dir().map(|dir| dir.join("token"))
+}
+
+/// Read the saved token, None if there is none.
+pub fn load_token() -> Result<Option<String>, Box<dyn Error>> {
+    match token_path() {
+        Some(path) => read_token(&path),
+        None => Ok(None),
+    }
+}
+
+/// Remove the saved token, return false if there was none.
+pub fn remove_token() -> Result<bool, Box<dyn Error>> {
+    match token_path() {
+        Some(path) => remove_token_file(&path),
+        None => Ok(false),
+    }
+}
+
+/// Write a token to a file created only readable by the current user, along with its
+/// directory.
+pub fn write_token(path: &Path, token: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, a token saved by an older version is restricted
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    std::io::Write::write_all(&mut file, token.as_bytes())?;
+    Ok(())
+}
+
+/// Read the token of a file without the surrounding whitespace, None if there is no file
+/// or it is blank.
+pub fn read_token(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    match std::fs::read_to_string(path) {
+        Ok(token) => Ok(Some(token.trim().to_string()).filter(|token| !token.is_empty())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Remove a token file, return false if there was none.
+pub fn remove_token_file(path: &Path) -> Result<bool, Box<dyn Error>> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_round_trip() {
+        let home = tempfile::tempdir().unwrap();
+        let path = home.path().join(".mender-rust").join("token");
+        assert_eq!(read_token(&path).unwrap(), None);
+        write_token(&path, "first\n").unwrap();
+        assert_eq!(read_token(&path).unwrap().as_deref(), Some("first"));
+        write_token(&path, "second").unwrap();
+        assert_eq!(read_token(&path).unwrap().as_deref(), Some("second"));
+        write_token(&path, " \n").unwrap();
+        assert_eq!(read_token(&path).unwrap(), None);
+        assert!(remove_token_file(&path).unwrap());
+        assert!(!remove_token_file(&path).unwrap());
+        assert_eq!(read_token(&path).unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn token_is_only_readable_by_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let home = tempfile::tempdir().unwrap();
+        let path = home.path().join("token");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        write_token(&path, "token").unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        // An existing readable file is restricted when the token is saved again
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_token(&path, "token").unwrap();
+        assert_eq!(mode(&path), 0o600);
+    }
+}
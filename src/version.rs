@@ -0,0 +1,185 @@
+use std::cmp::Ordering;
+
+/// Version found in an artifact name, e.g. release-2.1.0-rc1 is 2.1.0 with pre-release rc1.
+#[derive(Debug)]
+pub struct Version {
+    numbers: Vec<u64>,
+    pre_release: Option<String>,
+}
+
+impl Version {
+    /// Parse the version of a name, None is returned if there is none. The version is
+    /// the first dotted number starting a word, e.g. 2.0.0 in imx8mm-release-2.0.0, or
+    /// else the first number starting a word. The text following a '-' or '~' right
+    /// after it is the pre-release, up to a '+' starting the build metadata.
+    pub fn parse(name: &str) -> Option<Version> {
+        let candidates: Vec<&str> = name
+            .char_indices()
+            .filter(|(idx, c)| c.is_ascii_digit() && starts_word(&name[..*idx]))
+            .map(|(idx, _)| &name[idx..])
+            .collect();
+        let rest = candidates
+            .iter()
+            .find(|rest| {
+                let end = number_end(rest);
+                rest[..end].contains('.')
+            })
+            .or_else(|| candidates.first())?;
+        let end = number_end(rest);
+        let numbers = rest[..end]
+            .split('.')
+            .filter(|number| !number.is_empty())
+            .map(|number| number.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        let pre_release = rest[end..]
+            .strip_prefix(&['-', '~'][..])
+            .map(|pre_release| pre_release.split('+').next().unwrap_or_default())
+            .filter(|pre_release| !pre_release.is_empty())
+            .map(|pre_release| pre_release.to_string());
+        Some(Version {
+            numbers,
+            pre_release,
+        })
+    }
+}
+
+/// Whether a number following this text starts a word, a 'v' prefix is allowed.
+fn starts_word(before: &str) -> bool {
+    let before = before.strip_suffix(&['v', 'V'][..]).unwrap_or(before);
+    !before.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '.')
+}
+
+/// End of the dotted number at the start of text.
+fn number_end(text: &str) -> usize {
+    text.find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len())
+}
+
+/// Part of a pre-release identifier, digits are compared as numbers and are older than
+/// text so that rc2 is older than rc10 and 1 older than rc1.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum PreReleasePart<'a> {
+    Number(u64),
+    Text(&'a str),
+}
+
+/// Parts of each dot separated identifier of a pre-release.
+fn pre_release_parts(pre_release: &str) -> Vec<Vec<PreReleasePart<'_>>> {
+    pre_release
+        .split('.')
+        .map(|identifier| {
+            let mut parts = vec![];
+            let mut rest = identifier;
+            while let Some(first) = rest.chars().next() {
+                let is_digit = first.is_ascii_digit();
+                let end = rest
+                    .find(|c: char| c.is_ascii_digit() != is_digit)
+                    .unwrap_or(rest.len());
+                parts.push(if is_digit {
+                    PreReleasePart::Number(rest[..end].parse().unwrap_or(u64::MAX))
+                } else {
+                    PreReleasePart::Text(&rest[..end])
+                });
+                rest = &rest[end..];
+            }
+            parts
+        })
+        .collect()
+}
+
+impl Ord for Version {
+    /// Missing numbers count as 0 so 2.1 equals 2.1.0 and a pre-release is older than
+    /// its release. Pre-releases are compared identifier by identifier, a pre-release
+    /// with more identifiers is newer when the others are equal, as in semver.
+    fn cmp(&self, other: &Version) -> Ordering {
+        let len = self.numbers.len().max(other.numbers.len());
+        let number = |numbers: &[u64], idx| *numbers.get(idx).unwrap_or(&0);
+        (0..len)
+            .map(|idx| number(&self.numbers, idx).cmp(&number(&other.numbers, idx)))
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or_else(|| match (&self.pre_release, &other.pre_release) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => pre_release_parts(a).cmp(&pre_release_parts(b)),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(name: &str) -> Version {
+        Version::parse(name).unwrap_or_else(|| panic!("{} has no version", name))
+    }
+
+    #[test]
+    fn parse_version_of_names() {
+        for (name, numbers, pre_release) in &[
+            ("release-2.1.0", vec![2, 1, 0], None),
+            ("release-2.1.0-rc1", vec![2, 1, 0], Some("rc1")),
+            ("v3.4", vec![3, 4], None),
+            ("app_v3.4~beta.2", vec![3, 4], Some("beta.2")),
+            ("imx8mm-release-2.0.0", vec![2, 0, 0], None),
+            ("rpi4-fw-12", vec![12], None),
+            ("2.0.0-rc.1+build.5", vec![2, 0, 0], Some("rc.1")),
+            ("2.0.0+build.5", vec![2, 0, 0], None),
+            ("1.2.", vec![1, 2], None),
+        ] {
+            let version = version(name);
+            assert_eq!(&version.numbers, numbers, "{}", name);
+            assert_eq!(version.pre_release.as_deref(), *pre_release, "{}", name);
+        }
+    }
+
+    #[test]
+    fn names_without_version() {
+        for name in &["release", "", "imx8mm", "fw2023-latest"] {
+            assert!(Version::parse(name).is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn compare_numbers() {
+        assert!(version("release-2.1.0") > version("release-2.0.9"));
+        assert!(version("release-2.10.0") > version("release-2.9.0"));
+        assert!(version("release-3") > version("release-2.99"));
+        assert_eq!(version("release-2.1"), version("release-2.1.0"));
+        assert!(version("imx8mm-release-2.0.0") < version("imx8mm-release-10.0.0"));
+    }
+
+    #[test]
+    fn compare_pre_releases() {
+        assert!(version("2.0.0-rc1") < version("2.0.0"));
+        assert!(version("2.0.0-rc1") > version("1.9.9"));
+        assert!(version("2.0.0-rc2") < version("2.0.0-rc10"));
+        assert!(version("2.0.0-rc.2") < version("2.0.0-rc.10"));
+        assert!(version("2.0.0-alpha") < version("2.0.0-beta"));
+        assert!(version("2.0.0-alpha") < version("2.0.0-alpha.1"));
+        assert!(version("2.0.0-1") < version("2.0.0-alpha"));
+        assert!(version("2.0.0-beta.11") > version("2.0.0-beta.2"));
+        assert_eq!(version("2.0.0-rc1"), version("2.0.0~rc1"));
+    }
+
+    #[test]
+    fn build_metadata_is_ignored() {
+        assert_eq!(version("2.0.0+build.1"), version("2.0.0+build.2"));
+        assert_eq!(version("2.0.0+build.1"), version("2.0.0"));
+        assert_eq!(version("2.0.0-rc1+a"), version("2.0.0-rc1+b"));
+    }
+}